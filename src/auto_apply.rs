@@ -0,0 +1,321 @@
+use solana_sdk::{pubkey::Pubkey, signer::Signer, transaction::Transaction};
+use spl_token_2022::solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair};
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::AutoApplyConfig,
+    error::ApiError,
+    routes::deposit::{PendingApply, prepare_apply_pending, read_confidential_account, send_apply_pending},
+    state::AppState,
+};
+
+/// Accounts the auto-apply worker may apply pending balances for
+///
+/// Applying needs the owner's signature and both encryption keys, so only
+/// accounts owned by the server payer whose keys were handed over at
+/// registration can be registered. The registry itself is in memory; use
+/// [`register`] and [`unregister`] to keep the key vault's copy in step.
+#[derive(Default)]
+pub struct AutoApplyRegistry {
+    accounts: RwLock<HashMap<Pubkey, RegisteredAccount>>,
+}
+
+struct RegisteredAccount {
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+    /// Last successful apply, or registration
+    last_applied: Instant,
+}
+
+impl AutoApplyRegistry {
+    /// Register an account, returning whether it replaced an existing registration
+    pub fn register(&self, token_account: Pubkey, elgamal_keypair: ElGamalKeypair, aes_key: AeKey) -> bool {
+        self.accounts
+            .write()
+            .expect("auto-apply registry poisoned")
+            .insert(
+                token_account,
+                RegisteredAccount {
+                    elgamal_keypair,
+                    aes_key,
+                    last_applied: Instant::now(),
+                },
+            )
+            .is_some()
+    }
+
+    pub fn is_registered(&self, token_account: &Pubkey) -> bool {
+        self.accounts
+            .read()
+            .expect("auto-apply registry poisoned")
+            .contains_key(token_account)
+    }
+
+    /// Stop applying for an account; returns whether it was registered
+    pub fn unregister(&self, token_account: &Pubkey) -> bool {
+        self.accounts
+            .write()
+            .expect("auto-apply registry poisoned")
+            .remove(token_account)
+            .is_some()
+    }
+
+    fn token_accounts(&self) -> Vec<Pubkey> {
+        self.accounts
+            .read()
+            .expect("auto-apply registry poisoned")
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Keys and last apply time of a registered account
+    fn get(&self, token_account: &Pubkey) -> Option<(ElGamalKeypair, AeKey, Instant)> {
+        self.accounts
+            .read()
+            .expect("auto-apply registry poisoned")
+            .get(token_account)
+            .map(|account| {
+                (
+                    account.elgamal_keypair.clone(),
+                    account.aes_key.clone(),
+                    account.last_applied,
+                )
+            })
+    }
+
+    fn mark_applied(&self, token_account: &Pubkey) {
+        if let Some(account) = self
+            .accounts
+            .write()
+            .expect("auto-apply registry poisoned")
+            .get_mut(token_account)
+        {
+            account.last_applied = Instant::now();
+        }
+    }
+}
+
+/// Register an account for auto-apply, saving its keys to the key vault if
+/// one is configured so the registration survives a restart
+///
+/// Returns whether it replaced an existing registration, and whether it was saved.
+pub async fn register(
+    state: &AppState,
+    token_account: Pubkey,
+    elgamal_keypair: ElGamalKeypair,
+    aes_key: AeKey,
+) -> Result<(bool, bool), ApiError> {
+    let persisted = save_registration(state, &token_account, &elgamal_keypair, &aes_key).await?;
+    let replaced = state.auto_apply.register(token_account, elgamal_keypair, aes_key);
+    Ok((replaced, persisted))
+}
+
+/// Unregister an account, deleting its saved keys; returns whether it was registered
+pub async fn unregister(state: &AppState, token_account: &Pubkey) -> Result<bool, ApiError> {
+    let removed = remove_registration(state, token_account).await?;
+    Ok(state.auto_apply.unregister(token_account) || removed)
+}
+
+/// Reload the registrations saved in the key vault at startup
+pub async fn restore(state: &AppState) {
+    match load_registrations(state).await {
+        Ok(Some(accounts)) => {
+            let count = accounts.len();
+            for (token_account, elgamal_keypair, aes_key) in accounts {
+                state.auto_apply.register(token_account, elgamal_keypair, aes_key);
+            }
+            tracing::info!("Restored {} auto-apply registration(s) from the key vault", count);
+        }
+        Ok(None) => tracing::warn!(
+            "No key vault configured: auto-apply registrations are kept in memory only and \
+             must be made again after every restart"
+        ),
+        Err(e) => tracing::error!(
+            "Failed to restore auto-apply registrations, none are active until re-registered: {}",
+            e
+        ),
+    }
+}
+
+#[cfg(feature = "database")]
+async fn save_registration(
+    state: &AppState,
+    token_account: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<bool, ApiError> {
+    let Some(vault) = &state.vault else {
+        return Ok(false);
+    };
+    vault.save_auto_apply(token_account, elgamal_keypair, aes_key).await?;
+    Ok(true)
+}
+
+#[cfg(not(feature = "database"))]
+async fn save_registration(
+    _state: &AppState,
+    _token_account: &Pubkey,
+    _elgamal_keypair: &ElGamalKeypair,
+    _aes_key: &AeKey,
+) -> Result<bool, ApiError> {
+    Ok(false)
+}
+
+#[cfg(feature = "database")]
+async fn remove_registration(state: &AppState, token_account: &Pubkey) -> Result<bool, ApiError> {
+    match &state.vault {
+        Some(vault) => Ok(vault.remove_auto_apply(token_account).await?),
+        None => Ok(false),
+    }
+}
+
+#[cfg(not(feature = "database"))]
+async fn remove_registration(_state: &AppState, _token_account: &Pubkey) -> Result<bool, ApiError> {
+    Ok(false)
+}
+
+/// Saved registrations, or `None` without a key vault
+#[cfg(feature = "database")]
+async fn load_registrations(state: &AppState) -> Result<Option<Vec<(Pubkey, ElGamalKeypair, AeKey)>>, ApiError> {
+    match &state.vault {
+        Some(vault) => Ok(Some(vault.auto_apply_accounts().await?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(feature = "database"))]
+async fn load_registrations(_state: &AppState) -> Result<Option<Vec<(Pubkey, ElGamalKeypair, AeKey)>>, ApiError> {
+    Ok(None)
+}
+
+/// Whether pending credits are due to be applied
+///
+/// Due once the counter reaches `counter_threshold` of the maximum, or when
+/// any credits have been pending for longer than `max_interval`.
+fn is_due(
+    config: &AutoApplyConfig,
+    pending_balance_credit_counter: u64,
+    maximum_pending_balance_credit_counter: u64,
+    since_last_apply: Duration,
+) -> bool {
+    if pending_balance_credit_counter == 0 {
+        return false;
+    }
+
+    let threshold = (maximum_pending_balance_credit_counter as f64 * config.counter_threshold).ceil() as u64;
+    pending_balance_credit_counter >= threshold.max(1) || since_last_apply >= config.max_interval
+}
+
+/// Spawn the auto-apply worker, polling registered accounts until shutdown
+pub fn spawn(state: AppState, config: AutoApplyConfig) -> tokio::task::JoinHandle<()> {
+    tracing::info!(
+        "Auto-apply worker started (poll {:?}, threshold {}, interval {:?})",
+        config.poll_interval,
+        config.counter_threshold,
+        config.max_interval
+    );
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(config.poll_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            for token_account in state.auto_apply.token_accounts() {
+                if let Err(e) = apply_if_due(&state, &config, &token_account).await {
+                    tracing::warn!("Auto-apply for {} failed: {}", token_account, e);
+                }
+            }
+        }
+    })
+}
+
+/// Apply a registered account's pending balance if it is due
+async fn apply_if_due(state: &AppState, config: &AutoApplyConfig, token_account: &Pubkey) -> Result<(), ApiError> {
+    // Unregistered since the poll started
+    let Some((elgamal_keypair, aes_key, last_applied)) = state.auto_apply.get(token_account) else {
+        return Ok(());
+    };
+
+    let client = &state.rpc;
+    let payer = &state.payer;
+
+    let extension = read_confidential_account(client, token_account).await?;
+    if !is_due(
+        config,
+        extension.pending_balance_credit_counter.into(),
+        extension.maximum_pending_balance_credit_counter.into(),
+        last_applied.elapsed(),
+    ) {
+        return Ok(());
+    }
+
+    let PendingApply {
+        instruction,
+        pending_balance_credit_counter,
+        new_available_balance,
+    } = prepare_apply_pending(
+        token_account,
+        &payer.pubkey(),
+        &extension,
+        elgamal_keypair.secret(),
+        &aes_key,
+    )?;
+
+    let recent_blockhash = client.get_latest_blockhash().await.map_err(ApiError::rpc)?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[payer.as_ref()],
+        recent_blockhash,
+    );
+
+    let signature =
+        send_apply_pending(client, &transaction, token_account, pending_balance_credit_counter).await?;
+    state.auto_apply.mark_applied(token_account);
+
+    tracing::info!(
+        "Auto-applied {} pending credits on {} (available {}): {}",
+        pending_balance_credit_counter,
+        token_account,
+        new_available_balance,
+        signature
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AutoApplyConfig {
+        AutoApplyConfig {
+            poll_interval: Duration::from_secs(30),
+            counter_threshold: 0.5,
+            max_interval: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn test_is_due() {
+        let config = config();
+        let recent = Duration::from_secs(10);
+        let stale = Duration::from_secs(7200);
+
+        // Nothing pending is never due
+        assert!(!is_due(&config, 0, 65536, stale));
+
+        // Counter threshold
+        assert!(!is_due(&config, 32767, 65536, recent));
+        assert!(is_due(&config, 32768, 65536, recent));
+
+        // Time interval
+        assert!(is_due(&config, 1, 65536, stale));
+    }
+}
@@ -0,0 +1,209 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::crypto::MAX_ATTESTATION_TTL_SECS;
+
+/// Runtime configuration, read once from the environment at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Solana JSON-RPC endpoint
+    pub rpc_url: String,
+    /// Address the HTTP server binds to
+    pub bind_addr: SocketAddr,
+    /// Keypair file for the initial attestation signing key; a fresh key is
+    /// generated at startup when unset
+    pub attestation_keypair_path: Option<PathBuf>,
+    /// File the attestation keyring and revocations are saved to; they are
+    /// kept in memory only, and lost on restart, when unset
+    pub attestation_state_path: Option<PathBuf>,
+    /// How long issued attestations stay valid, in seconds
+    pub attestation_ttl_secs: u64,
+    /// Bearer token for admin endpoints; admin endpoints are disabled when
+    /// unset
+    pub admin_token: Option<String>,
+    /// Background apply of pending balances for registered accounts;
+    /// disabled when unset
+    pub auto_apply: Option<AutoApplyConfig>,
+    /// Encrypted key storage for custodial accounts; disabled when unset
+    #[cfg(feature = "database")]
+    pub vault: Option<VaultConfig>,
+    /// Background indexing of confidential transaction history; disabled when
+    /// unset, and requires the key vault's database
+    #[cfg(feature = "database")]
+    pub history: Option<HistoryConfig>,
+}
+
+/// Where custodial keys are stored and the key they are encrypted under
+#[cfg(feature = "database")]
+#[derive(Clone)]
+pub struct VaultConfig {
+    /// SQLite connection string, e.g. `sqlite://keys.db`
+    pub database_url: String,
+    /// AES-256-GCM key the stored keys are encrypted with
+    pub master_key: [u8; 32],
+}
+
+// Never print the master key
+#[cfg(feature = "database")]
+impl std::fmt::Debug for VaultConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultConfig")
+            .field("database_url", &self.database_url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How often the history indexer polls registered accounts
+#[cfg(feature = "database")]
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    /// How often registered accounts are checked for new transactions
+    pub poll_interval: Duration,
+}
+
+/// When the auto-apply worker applies a registered account's pending balance
+#[derive(Debug, Clone)]
+pub struct AutoApplyConfig {
+    /// How often registered accounts are checked
+    pub poll_interval: Duration,
+    /// Apply once the pending credit counter reaches this fraction of the
+    /// account's `maximum_pending_balance_credit_counter`
+    pub counter_threshold: f64,
+    /// Apply any pending credits at least this often, regardless of the counter
+    pub max_interval: Duration,
+}
+
+impl Config {
+    /// Build the configuration from environment variables
+    ///
+    /// - `SOLANA_RPC_URL` (default: devnet)
+    /// - `BIND_ADDR` (default: `0.0.0.0:3001`)
+    /// - `ATTESTATION_KEYPAIR_PATH` (optional)
+    /// - `ATTESTATION_STATE_PATH` (optional)
+    /// - `ATTESTATION_TTL_SECS` (default: 3600, at most 30 days)
+    /// - `ADMIN_TOKEN` (optional)
+    /// - `AUTO_APPLY_ENABLED` (default: false), with `AUTO_APPLY_POLL_SECS`
+    ///   (default: 30), `AUTO_APPLY_THRESHOLD` (default: 0.5) and
+    ///   `AUTO_APPLY_INTERVAL_SECS` (default: 3600)
+    /// - `DATABASE_URL` (optional, `database` feature), which requires
+    ///   `VAULT_MASTER_KEY` (base64, 32 bytes)
+    /// - `HISTORY_INDEXER_ENABLED` (default: false, `database` feature), which
+    ///   requires `DATABASE_URL`, with `HISTORY_INDEXER_POLL_SECS` (default: 60)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let rpc_url = std::env::var("SOLANA_RPC_URL")
+            .unwrap_or_else(|_| "https://api.devnet.solana.com".to_string());
+
+        let bind_addr = match std::env::var("BIND_ADDR") {
+            Ok(addr) => addr
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid BIND_ADDR {}: {}", addr, e))?,
+            Err(_) => SocketAddr::from(([0, 0, 0, 0], 3001)),
+        };
+
+        let attestation_keypair_path = std::env::var("ATTESTATION_KEYPAIR_PATH")
+            .ok()
+            .map(PathBuf::from);
+        let attestation_state_path = std::env::var("ATTESTATION_STATE_PATH")
+            .ok()
+            .map(PathBuf::from);
+
+        let attestation_ttl_secs = match std::env::var("ATTESTATION_TTL_SECS") {
+            Ok(ttl) => ttl
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid ATTESTATION_TTL_SECS {}: {}", ttl, e))?,
+            Err(_) => 3600,
+        };
+        if !(1..=MAX_ATTESTATION_TTL_SECS).contains(&attestation_ttl_secs) {
+            anyhow::bail!(
+                "ATTESTATION_TTL_SECS must be between 1 and {}, got {}",
+                MAX_ATTESTATION_TTL_SECS,
+                attestation_ttl_secs
+            );
+        }
+
+        let admin_token = std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        let auto_apply = if env_or("AUTO_APPLY_ENABLED", false)? {
+            let counter_threshold: f64 = env_or("AUTO_APPLY_THRESHOLD", 0.5)?;
+            if !(counter_threshold > 0.0 && counter_threshold <= 1.0) {
+                anyhow::bail!("AUTO_APPLY_THRESHOLD must be in (0, 1], got {}", counter_threshold);
+            }
+            let poll_secs: u64 = env_or("AUTO_APPLY_POLL_SECS", 30)?;
+            if poll_secs == 0 {
+                anyhow::bail!("AUTO_APPLY_POLL_SECS must be greater than 0");
+            }
+            Some(AutoApplyConfig {
+                poll_interval: Duration::from_secs(poll_secs),
+                counter_threshold,
+                max_interval: Duration::from_secs(env_or("AUTO_APPLY_INTERVAL_SECS", 3600)?),
+            })
+        } else {
+            None
+        };
+
+        #[cfg(feature = "database")]
+        let vault = match std::env::var("DATABASE_URL") {
+            Ok(database_url) => {
+                use base64::{Engine, prelude::BASE64_STANDARD};
+
+                let master_key = std::env::var("VAULT_MASTER_KEY")
+                    .map_err(|_| anyhow::anyhow!("DATABASE_URL is set but VAULT_MASTER_KEY is not"))?;
+                let master_key = BASE64_STANDARD
+                    .decode(master_key.trim())
+                    .ok()
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .ok_or_else(|| anyhow::anyhow!("VAULT_MASTER_KEY must be 32 bytes, base64-encoded"))?;
+                Some(VaultConfig {
+                    database_url,
+                    master_key,
+                })
+            }
+            Err(_) => None,
+        };
+
+        #[cfg(feature = "database")]
+        let history = if env_or("HISTORY_INDEXER_ENABLED", false)? {
+            if vault.is_none() {
+                anyhow::bail!("HISTORY_INDEXER_ENABLED requires DATABASE_URL");
+            }
+            let poll_secs: u64 = env_or("HISTORY_INDEXER_POLL_SECS", 60)?;
+            if poll_secs == 0 {
+                anyhow::bail!("HISTORY_INDEXER_POLL_SECS must be greater than 0");
+            }
+            Some(HistoryConfig {
+                poll_interval: Duration::from_secs(poll_secs),
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            rpc_url,
+            bind_addr,
+            attestation_keypair_path,
+            attestation_state_path,
+            attestation_ttl_secs,
+            admin_token,
+            auto_apply,
+            #[cfg(feature = "database")]
+            vault,
+            #[cfg(feature = "database")]
+            history,
+        })
+    }
+}
+
+/// Parse an optional environment variable, falling back to `default` when unset
+fn env_or<T>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {} {}: {}", name, value, e)),
+        Err(_) => Ok(default),
+    }
+}
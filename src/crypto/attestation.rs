@@ -0,0 +1,719 @@
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+};
+use anyhow::Context;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+/// Version of the canonical attestation message
+pub const ATTESTATION_VERSION: u8 = 1;
+/// First line of every signed attestation message
+pub const ATTESTATION_DOMAIN: &str = "privypass-attestation";
+/// Longest an attestation may stay valid for: 30 days
+pub const MAX_ATTESTATION_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Statement that a token account's confidential available balance was at
+/// least `threshold` at `slot`, valid until `expires_at` (unix seconds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationStatement {
+    pub version: u8,
+    /// Base58 ed25519 key that signed this statement
+    pub signer: String,
+    pub wallet: String,
+    pub token_account: String,
+    pub mint: String,
+    pub threshold: u64,
+    pub slot: u64,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    /// Hex-encoded random 16 bytes; identifies the attestation for revocation
+    pub nonce: String,
+}
+
+impl AttestationStatement {
+    /// Canonical message the signature covers
+    ///
+    /// UTF-8, one `key:value` per line, joined with `\n`, no trailing newline:
+    ///
+    /// ```text
+    /// privypass-attestation:v1
+    /// signer:<base58>
+    /// wallet:<base58>
+    /// token_account:<base58>
+    /// mint:<base58>
+    /// threshold:<u64>
+    /// slot:<u64>
+    /// issued_at:<unix seconds>
+    /// expires_at:<unix seconds>
+    /// nonce:<hex>
+    /// ```
+    pub fn message(&self) -> Vec<u8> {
+        [
+            format!("{}:v{}", ATTESTATION_DOMAIN, self.version),
+            format!("signer:{}", self.signer),
+            format!("wallet:{}", self.wallet),
+            format!("token_account:{}", self.token_account),
+            format!("mint:{}", self.mint),
+            format!("threshold:{}", self.threshold),
+            format!("slot:{}", self.slot),
+            format!("issued_at:{}", self.issued_at),
+            format!("expires_at:{}", self.expires_at),
+            format!("nonce:{}", self.nonce),
+        ]
+        .join("\n")
+        .into_bytes()
+    }
+}
+
+/// An attestation statement with its ed25519 signature (base58)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedAttestation {
+    pub statement: AttestationStatement,
+    pub signature: String,
+}
+
+/// Reasons an attestation is not acceptable
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum AttestationError {
+    #[error("unsupported attestation version")]
+    UnsupportedVersion,
+    #[error("attestation signature is invalid")]
+    InvalidSignature,
+    #[error("attestation has expired")]
+    Expired,
+    #[error("attestation was signed by an unknown key")]
+    UnknownKey,
+    #[error("attestation signing key has been revoked")]
+    KeyRevoked,
+    #[error("attestation has been revoked")]
+    Revoked,
+}
+
+impl AttestationError {
+    /// Stable machine-readable reason code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnsupportedVersion => "UNSUPPORTED_ATTESTATION_VERSION",
+            Self::InvalidSignature => "INVALID_ATTESTATION_SIGNATURE",
+            Self::Expired => "ATTESTATION_EXPIRED",
+            Self::UnknownKey => "UNKNOWN_ATTESTATION_KEY",
+            Self::KeyRevoked => "ATTESTATION_KEY_REVOKED",
+            Self::Revoked => "ATTESTATION_REVOKED",
+        }
+    }
+}
+
+/// Check an attestation's version, signature and expiry against `signer`
+///
+/// This is all an offline verifier needs besides the published key list and
+/// revocations from `/api/attestation/keys`.
+pub fn verify_attestation(
+    attestation: &SignedAttestation,
+    signer: &Pubkey,
+    now: i64,
+) -> Result<(), AttestationError> {
+    let statement = &attestation.statement;
+    if statement.version != ATTESTATION_VERSION {
+        return Err(AttestationError::UnsupportedVersion);
+    }
+    if statement.signer != signer.to_string() {
+        return Err(AttestationError::UnknownKey);
+    }
+
+    let signature = Signature::from_str(&attestation.signature)
+        .map_err(|_| AttestationError::InvalidSignature)?;
+    if !signature.verify(signer.as_ref(), &statement.message()) {
+        return Err(AttestationError::InvalidSignature);
+    }
+
+    if now >= statement.expires_at {
+        return Err(AttestationError::Expired);
+    }
+
+    Ok(())
+}
+
+/// Lifecycle of an attestation signing key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationKeyStatus {
+    /// Signs new attestations
+    Active,
+    /// Rotated out; attestations it signed stay valid until they expire
+    Retired,
+    /// Compromised or withdrawn; nothing it signed is valid
+    Revoked,
+}
+
+/// A signing key as published at `/api/attestation/keys`
+#[derive(Debug, Clone, Serialize)]
+pub struct AttestationKeyInfo {
+    pub public_key: String,
+    pub algorithm: &'static str,
+    pub status: AttestationKeyStatus,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<i64>,
+}
+
+struct AttestationKey {
+    keypair: Keypair,
+    status: AttestationKeyStatus,
+    created_at: i64,
+    retired_at: Option<i64>,
+}
+
+struct AttestationKeyring {
+    /// All known keys; the last `Active` one signs
+    keys: Vec<AttestationKey>,
+    /// Unexpired issued attestations: nonce -> expires_at; memory only
+    issued: HashMap<String, i64>,
+    /// Revoked attestations, kept until they would have expired: nonce -> expires_at
+    revoked: HashMap<String, i64>,
+}
+
+/// The keyring as saved to the state file
+///
+/// Issued nonces are not saved: every attestation would rewrite the file.
+/// State files from before this carry an `issued` map, which is ignored.
+#[derive(Serialize, Deserialize)]
+struct StoredKeyring {
+    keys: Vec<StoredKey>,
+    revoked: HashMap<String, i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    /// Base58 64-byte keypair
+    secret_key: String,
+    status: AttestationKeyStatus,
+    created_at: i64,
+    retired_at: Option<i64>,
+}
+
+/// Issues attestations and manages signing key rotation and revocation
+///
+/// With a state file the keys and revocations are saved after every change
+/// and survive restarts. Without one they live in memory only: a restart
+/// forgets rotated and revoked keys, so attestations they signed fail with
+/// `UnknownKey`, and revoked attestations are no longer listed.
+///
+/// Issued nonces are kept in memory only, so an attestation issued before a
+/// restart can no longer be revoked on its own; revoke its signing key.
+pub struct AttestationAuthority {
+    /// How long an attestation is valid for, in seconds
+    ttl_secs: i64,
+    keyring: RwLock<AttestationKeyring>,
+    /// File the keyring is saved to, if any
+    state_path: Option<PathBuf>,
+    /// Orders saves, so an older snapshot never overwrites a newer one
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl AttestationAuthority {
+    /// `ttl_secs` is capped at [`MAX_ATTESTATION_TTL_SECS`]
+    pub fn new(signing_key: Keypair, ttl_secs: u64) -> Self {
+        Self {
+            ttl_secs: ttl_secs.min(MAX_ATTESTATION_TTL_SECS) as i64,
+            keyring: RwLock::new(AttestationKeyring {
+                keys: vec![AttestationKey {
+                    keypair: signing_key,
+                    status: AttestationKeyStatus::Active,
+                    created_at: unix_now(),
+                    retired_at: None,
+                }],
+                issued: HashMap::new(),
+                revoked: HashMap::new(),
+            }),
+            state_path: None,
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Like [`Self::new`], but saves the keyring to `state_path`
+    ///
+    /// An existing state file is loaded and takes precedence over
+    /// `signing_key`; otherwise the file is created with `signing_key` as the
+    /// active key.
+    pub fn with_state_file(
+        signing_key: Keypair,
+        ttl_secs: u64,
+        state_path: PathBuf,
+    ) -> anyhow::Result<Self> {
+        let mut authority = Self::new(signing_key, ttl_secs);
+
+        match fs::read_to_string(&state_path) {
+            Ok(contents) => {
+                let stored: StoredKeyring = serde_json::from_str(&contents).with_context(|| {
+                    format!("Attestation state file {} is malformed", state_path.display())
+                })?;
+                let keyring = AttestationKeyring::from_stored(stored).with_context(|| {
+                    format!("Invalid attestation state file {}", state_path.display())
+                })?;
+                *authority.keyring.get_mut().unwrap() = keyring;
+                tracing::info!("Attestation keyring loaded from {}", state_path.display());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let stored = authority.keyring.get_mut().unwrap().stored();
+                write_state(&state_path, &stored).with_context(|| {
+                    format!("Failed to create attestation state file {}", state_path.display())
+                })?;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read attestation state file {}", state_path.display())
+                });
+            }
+        }
+
+        authority.state_path = Some(state_path);
+        Ok(authority)
+    }
+
+    /// Sign a statement with the active key
+    pub fn issue(
+        &self,
+        wallet: &Pubkey,
+        token_account: &Pubkey,
+        mint: &Pubkey,
+        threshold: u64,
+        slot: u64,
+    ) -> SignedAttestation {
+        let now = unix_now();
+        let mut keyring = self.keyring.write().unwrap();
+        keyring.prune(now, self.ttl_secs);
+
+        let signing_key = keyring.active();
+        let statement = AttestationStatement {
+            version: ATTESTATION_VERSION,
+            signer: signing_key.keypair.pubkey().to_string(),
+            wallet: wallet.to_string(),
+            token_account: token_account.to_string(),
+            mint: mint.to_string(),
+            threshold,
+            slot,
+            issued_at: now,
+            expires_at: now.saturating_add(self.ttl_secs),
+            nonce: hex_nonce(),
+        };
+        let signature = signing_key.keypair.sign_message(&statement.message());
+
+        keyring
+            .issued
+            .insert(statement.nonce.clone(), statement.expires_at);
+
+        SignedAttestation {
+            statement,
+            signature: signature.to_string(),
+        }
+    }
+
+    /// Check an attestation against the keyring, including revocations
+    pub fn verify(&self, attestation: &SignedAttestation) -> Result<(), AttestationError> {
+        let keyring = self.keyring.read().unwrap();
+        let key = keyring
+            .keys
+            .iter()
+            .find(|key| key.keypair.pubkey().to_string() == attestation.statement.signer)
+            .ok_or(AttestationError::UnknownKey)?;
+
+        if key.status == AttestationKeyStatus::Revoked {
+            return Err(AttestationError::KeyRevoked);
+        }
+        verify_attestation(attestation, &key.keypair.pubkey(), unix_now())?;
+        if keyring.revoked.contains_key(&attestation.statement.nonce) {
+            return Err(AttestationError::Revoked);
+        }
+
+        Ok(())
+    }
+
+    /// Generate a new active key and retire the current one
+    pub async fn rotate(&self) -> Pubkey {
+        let pubkey = self.keyring.write().unwrap().rotate_locked(unix_now());
+        self.persist().await;
+
+        tracing::info!("Attestation signing key rotated to {}", pubkey);
+        pubkey
+    }
+
+    /// Revoke a signing key, invalidating everything it signed
+    ///
+    /// Revoking the active key rotates to a fresh one first, under the same
+    /// lock, so concurrent revocations cannot both rotate. Returns false if
+    /// the key is unknown.
+    pub async fn revoke_key(&self, public_key: &Pubkey) -> bool {
+        let rotated_to = {
+            let now = unix_now();
+            let mut keyring = self.keyring.write().unwrap();
+            let Some(index) = keyring
+                .keys
+                .iter()
+                .position(|key| key.keypair.pubkey() == *public_key)
+            else {
+                return false;
+            };
+
+            let rotated_to = (keyring.keys[index].status == AttestationKeyStatus::Active)
+                .then(|| keyring.rotate_locked(now));
+
+            let key = &mut keyring.keys[index];
+            key.status = AttestationKeyStatus::Revoked;
+            key.retired_at.get_or_insert(now);
+            rotated_to
+        };
+        self.persist().await;
+
+        if let Some(pubkey) = rotated_to {
+            tracing::info!("Attestation signing key rotated to {}", pubkey);
+        }
+        tracing::warn!("Attestation signing key {} revoked", public_key);
+        true
+    }
+
+    /// Revoke a single issued attestation by nonce
+    ///
+    /// Returns false if no unexpired attestation with that nonce was issued
+    /// since the server started.
+    pub async fn revoke_attestation(&self, nonce: &str) -> bool {
+        {
+            let mut keyring = self.keyring.write().unwrap();
+            keyring.prune(unix_now(), self.ttl_secs);
+
+            let Some(expires_at) = keyring.issued.get(nonce).copied() else {
+                return false;
+            };
+            keyring.revoked.insert(nonce.to_string(), expires_at);
+        }
+        self.persist().await;
+
+        tracing::warn!("Attestation {} revoked", nonce);
+        true
+    }
+
+    /// Keys verifiers should know about: the active key, retired keys whose
+    /// attestations may still be valid, and revoked keys
+    pub fn keys(&self) -> Vec<AttestationKeyInfo> {
+        let mut keyring = self.keyring.write().unwrap();
+        keyring.prune(unix_now(), self.ttl_secs);
+
+        keyring
+            .keys
+            .iter()
+            .map(|key| AttestationKeyInfo {
+                public_key: key.keypair.pubkey().to_string(),
+                algorithm: "ed25519",
+                status: key.status,
+                created_at: key.created_at,
+                retired_at: key.retired_at,
+            })
+            .collect()
+    }
+
+    /// Nonces of revoked attestations that have not yet expired
+    pub fn revoked_attestations(&self) -> Vec<String> {
+        let keyring = self.keyring.read().unwrap();
+        keyring.revoked.keys().cloned().collect()
+    }
+
+    /// Save the keys and revocations to the state file, if configured
+    ///
+    /// The file is written on the blocking pool, outside the keyring lock.
+    /// Each save snapshots the keyring once the previous save has finished,
+    /// so the last write always carries the latest changes. The in-memory
+    /// keyring stays authoritative when the write fails, so the failure is
+    /// logged rather than returned.
+    async fn persist(&self) {
+        let Some(path) = &self.state_path else {
+            return;
+        };
+
+        let _save = self.save_lock.lock().await;
+        let stored = self.keyring.read().unwrap().stored();
+        let write_path = path.clone();
+        let result = tokio::task::spawn_blocking(move || write_state(&write_path, &stored))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+
+        if let Err(e) = result {
+            tracing::error!("Failed to save attestation state to {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl AttestationKeyring {
+    fn from_stored(stored: StoredKeyring) -> anyhow::Result<Self> {
+        let keys = stored
+            .keys
+            .into_iter()
+            .map(|key| {
+                let bytes = bs58::decode(&key.secret_key)
+                    .into_vec()
+                    .context("signing key is not valid base58")?;
+                let keypair = Keypair::try_from(bytes.as_slice())
+                    .map_err(|e| anyhow::anyhow!("invalid signing key: {}", e))?;
+                Ok(AttestationKey {
+                    keypair,
+                    status: key.status,
+                    created_at: key.created_at,
+                    retired_at: key.retired_at,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if !keys.iter().any(|key| key.status == AttestationKeyStatus::Active) {
+            anyhow::bail!("no active signing key");
+        }
+
+        Ok(Self {
+            keys,
+            issued: HashMap::new(),
+            revoked: stored.revoked,
+        })
+    }
+
+    /// The keys and revocations to save
+    fn stored(&self) -> StoredKeyring {
+        StoredKeyring {
+            keys: self
+                .keys
+                .iter()
+                .map(|key| StoredKey {
+                    secret_key: bs58::encode(key.keypair.to_bytes()).into_string(),
+                    status: key.status,
+                    created_at: key.created_at,
+                    retired_at: key.retired_at,
+                })
+                .collect(),
+            revoked: self.revoked.clone(),
+        }
+    }
+
+    /// Generate a new active key and retire the current one; the caller
+    /// holds the write lock
+    fn rotate_locked(&mut self, now: i64) -> Pubkey {
+        for key in self
+            .keys
+            .iter_mut()
+            .filter(|key| key.status == AttestationKeyStatus::Active)
+        {
+            key.status = AttestationKeyStatus::Retired;
+            key.retired_at = Some(now);
+        }
+
+        let keypair = Keypair::new();
+        let pubkey = keypair.pubkey();
+        self.keys.push(AttestationKey {
+            keypair,
+            status: AttestationKeyStatus::Active,
+            created_at: now,
+            retired_at: None,
+        });
+        pubkey
+    }
+
+    fn active(&self) -> &AttestationKey {
+        self.keys
+            .iter()
+            .rev()
+            .find(|key| key.status == AttestationKeyStatus::Active)
+            .expect("attestation keyring always has an active key")
+    }
+
+    /// Drop expired attestations and retired keys whose attestations have all
+    /// expired; revoked keys stay listed so verifiers keep rejecting them
+    fn prune(&mut self, now: i64, ttl_secs: i64) {
+        self.issued.retain(|_, expires_at| *expires_at > now);
+        self.revoked.retain(|_, expires_at| *expires_at > now);
+        self.keys.retain(|key| match (key.status, key.retired_at) {
+            (AttestationKeyStatus::Retired, Some(retired_at)) => {
+                retired_at.checked_add(ttl_secs).is_none_or(|last_expiry| last_expiry > now)
+            }
+            _ => true,
+        });
+    }
+}
+
+/// Write the keyring to `path` through a temporary file, so a crash
+/// mid-write never leaves a truncated state file behind
+fn write_state(path: &Path, stored: &StoredKeyring) -> io::Result<()> {
+    let contents = serde_json::to_vec_pretty(stored)?;
+
+    let tmp_path = path.with_extension("tmp");
+    write_private(&tmp_path, &contents)?;
+    fs::rename(&tmp_path, path)
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Write a file only the server's user can read; it holds secret keys
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+fn hex_nonce() -> String {
+    rand::random::<[u8; 16]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue(authority: &AttestationAuthority) -> SignedAttestation {
+        authority.issue(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            50,
+            1_000,
+        )
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let authority = AttestationAuthority::new(Keypair::new(), 3600);
+        let attestation = issue(&authority);
+        assert_eq!(authority.verify(&attestation), Ok(()));
+
+        // Offline: only the published key is needed
+        let signer = Pubkey::from_str(&attestation.statement.signer).unwrap();
+        let now = attestation.statement.issued_at;
+        assert_eq!(verify_attestation(&attestation, &signer, now), Ok(()));
+        assert_eq!(
+            verify_attestation(&attestation, &signer, attestation.statement.expires_at),
+            Err(AttestationError::Expired)
+        );
+
+        // Any change to the statement breaks the signature
+        let mut tampered = attestation.clone();
+        tampered.statement.threshold = 5;
+        assert_eq!(
+            verify_attestation(&tampered, &signer, now),
+            Err(AttestationError::InvalidSignature)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ttl_is_capped() {
+        let authority = AttestationAuthority::new(Keypair::new(), u64::MAX);
+        let attestation = issue(&authority);
+        let statement = &attestation.statement;
+        assert_eq!(
+            statement.expires_at - statement.issued_at,
+            MAX_ATTESTATION_TTL_SECS as i64
+        );
+
+        // Retiring the key under the capped TTL keeps it listed
+        authority.rotate().await;
+        issue(&authority);
+        assert_eq!(authority.keys().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_old_attestations_valid() {
+        let authority = AttestationAuthority::new(Keypair::new(), 3600);
+        let before = issue(&authority);
+
+        let new_key = authority.rotate().await;
+        let after = issue(&authority);
+        assert_eq!(after.statement.signer, new_key.to_string());
+        assert_ne!(before.statement.signer, after.statement.signer);
+
+        assert_eq!(authority.verify(&before), Ok(()));
+        assert_eq!(authority.verify(&after), Ok(()));
+
+        let statuses: Vec<_> = authority.keys().iter().map(|key| key.status).collect();
+        assert_eq!(
+            statuses,
+            vec![AttestationKeyStatus::Retired, AttestationKeyStatus::Active]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_state_file_survives_restart() {
+        let path = std::env::temp_dir().join(format!("attestation-{}.json", hex_nonce()));
+
+        let authority = AttestationAuthority::with_state_file(Keypair::new(), 3600, path.clone()).unwrap();
+        let before = issue(&authority);
+        let new_key = authority.rotate().await;
+        let revoked = issue(&authority);
+        assert!(authority.revoke_attestation(&revoked.statement.nonce).await);
+
+        // Issuing does not touch the file
+        let saved = std::fs::read(&path).unwrap();
+        let unrevoked = issue(&authority);
+        assert_eq!(std::fs::read(&path).unwrap(), saved);
+
+        // A different startup key is ignored once the state file exists
+        let restarted = AttestationAuthority::with_state_file(Keypair::new(), 3600, path.clone()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(restarted.verify(&before), Ok(()));
+        assert_eq!(restarted.verify(&revoked), Err(AttestationError::Revoked));
+        assert_eq!(issue(&restarted).statement.signer, new_key.to_string());
+
+        // Issued nonces are not saved, so only the signing key can revoke them
+        assert_eq!(restarted.verify(&unrevoked), Ok(()));
+        assert!(!restarted.revoke_attestation(&unrevoked.statement.nonce).await);
+    }
+
+    #[tokio::test]
+    async fn test_revocation() {
+        let authority = AttestationAuthority::new(Keypair::new(), 3600);
+        let first = issue(&authority);
+        let second = issue(&authority);
+
+        assert!(authority.revoke_attestation(&first.statement.nonce).await);
+        assert!(!authority.revoke_attestation("unknown").await);
+        assert_eq!(authority.verify(&first), Err(AttestationError::Revoked));
+        assert_eq!(authority.verify(&second), Ok(()));
+        assert_eq!(authority.revoked_attestations(), vec![first.statement.nonce.clone()]);
+
+        // Revoking the active key rotates to a new one
+        let signer = Pubkey::from_str(&second.statement.signer).unwrap();
+        assert!(authority.revoke_key(&signer).await);
+        assert_eq!(authority.verify(&second), Err(AttestationError::KeyRevoked));
+        assert_eq!(authority.verify(&issue(&authority)), Ok(()));
+
+        // Revoking it again finds it revoked and leaves the new key active
+        assert!(authority.revoke_key(&signer).await);
+        let statuses: Vec<_> = authority.keys().iter().map(|key| key.status).collect();
+        assert_eq!(
+            statuses,
+            vec![AttestationKeyStatus::Revoked, AttestationKeyStatus::Active]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use spl_token_2022::{
+    extension::confidential_transfer::instruction::{
+        ConfidentialTransferInstruction, TransferInstructionData, TransferWithFeeInstructionData,
+    },
+    instruction::{TokenInstruction, decode_instruction_data, decode_instruction_type},
+    solana_zk_sdk::encryption::{elgamal::ElGamalSecretKey, pod::elgamal::PodElGamalCiphertext},
+};
+
+use super::keys::{BalanceDecryptionError, decrypt_split_amount};
+
+/// Bit length of the low part of a confidential transfer amount
+/// Transfer amounts are split into a 16-bit lo and a 32-bit hi ciphertext
+pub const TRANSFER_AMOUNT_LO_BIT_LENGTH: u32 = 16;
+
+/// The transfer amount of a confidential transfer instruction, encrypted
+/// under the mint's auditor ElGamal pubkey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditorCiphertexts {
+    pub ciphertext_lo: PodElGamalCiphertext,
+    pub ciphertext_hi: PodElGamalCiphertext,
+    /// The instruction is `TransferWithFee`
+    pub with_fee: bool,
+}
+
+/// Read the auditor ciphertexts from Token-2022 instruction data
+/// Returns `None` unless the instruction is a confidential `Transfer` or `TransferWithFee`
+pub fn parse_auditor_ciphertexts(instruction_data: &[u8]) -> Option<AuditorCiphertexts> {
+    if !matches!(
+        TokenInstruction::unpack(instruction_data),
+        Ok(TokenInstruction::ConfidentialTransferExtension)
+    ) {
+        return None;
+    }
+    let extension_data = &instruction_data[1..];
+
+    match decode_instruction_type(extension_data).ok()? {
+        ConfidentialTransferInstruction::Transfer => {
+            let data = decode_instruction_data::<TransferInstructionData>(extension_data).ok()?;
+            Some(AuditorCiphertexts {
+                ciphertext_lo: data.transfer_amount_auditor_ciphertext_lo,
+                ciphertext_hi: data.transfer_amount_auditor_ciphertext_hi,
+                with_fee: false,
+            })
+        }
+        ConfidentialTransferInstruction::TransferWithFee => {
+            let data = decode_instruction_data::<TransferWithFeeInstructionData>(extension_data).ok()?;
+            Some(AuditorCiphertexts {
+                ciphertext_lo: data.transfer_amount_auditor_ciphertext_lo,
+                ciphertext_hi: data.transfer_amount_auditor_ciphertext_hi,
+                with_fee: true,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Decrypt a transfer amount with the auditor's ElGamal secret key
+///
+/// The lo/hi parts are each decoded with a discrete log over 32 bits, which
+/// takes on the order of a second; run it off the async runtime. Ciphertexts
+/// under another key fail with `DiscreteLogFailed`.
+pub fn decrypt_transfer_amount(
+    auditor_elgamal_secret_key: &ElGamalSecretKey,
+    ciphertexts: &AuditorCiphertexts,
+) -> Result<u64, BalanceDecryptionError> {
+    decrypt_split_amount(
+        auditor_elgamal_secret_key,
+        &ciphertexts.ciphertext_lo,
+        &ciphertexts.ciphertext_hi,
+        TRANSFER_AMOUNT_LO_BIT_LENGTH,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+    use spl_token_2022::{
+        extension::confidential_transfer::instruction::{deposit, transfer},
+        solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+    };
+    use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+
+    fn auditor_ciphertexts(auditor: &ElGamalKeypair, amount: u64) -> AuditorCiphertexts {
+        let lo = amount & ((1 << TRANSFER_AMOUNT_LO_BIT_LENGTH) - 1);
+        let hi = amount >> TRANSFER_AMOUNT_LO_BIT_LENGTH;
+        AuditorCiphertexts {
+            ciphertext_lo: auditor.pubkey().encrypt(lo).into(),
+            ciphertext_hi: auditor.pubkey().encrypt(hi).into(),
+            with_fee: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_auditor_ciphertexts() {
+        let auditor = ElGamalKeypair::new_rand();
+        let ciphertexts = auditor_ciphertexts(&auditor, 1_000);
+
+        let instructions = transfer(
+            &spl_token_2022::id(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &AeKey::new_rand().encrypt(0).into(),
+            &ciphertexts.ciphertext_lo,
+            &ciphertexts.ciphertext_hi,
+            &Pubkey::new_unique(),
+            &[],
+            ProofLocation::ContextStateAccount(&Pubkey::new_unique()),
+            ProofLocation::ContextStateAccount(&Pubkey::new_unique()),
+            ProofLocation::ContextStateAccount(&Pubkey::new_unique()),
+        )
+        .unwrap();
+
+        assert_eq!(parse_auditor_ciphertexts(&instructions[0].data), Some(ciphertexts));
+
+        // Other confidential transfer instructions carry no auditor ciphertexts
+        let deposit_ix = deposit(
+            &spl_token_2022::id(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            1_000,
+            6,
+            &Pubkey::new_unique(),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(parse_auditor_ciphertexts(&deposit_ix.data), None);
+        assert_eq!(parse_auditor_ciphertexts(&[]), None);
+    }
+
+    #[test]
+    fn test_decrypt_transfer_amount() {
+        let auditor = ElGamalKeypair::new_rand();
+
+        // Needs both halves: (15 << 16) + 4_464
+        let ciphertexts = auditor_ciphertexts(&auditor, 987_504);
+        assert_eq!(decrypt_transfer_amount(auditor.secret(), &ciphertexts), Ok(987_504));
+
+        let other = ElGamalKeypair::new_rand();
+        assert_eq!(
+            decrypt_transfer_amount(other.secret(), &ciphertexts),
+            Err(BalanceDecryptionError::DiscreteLogFailed)
+        );
+    }
+}
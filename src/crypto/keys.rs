@@ -1,8 +1,169 @@
 use anyhow::Result;
-use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+};
 use spl_token_2022::solana_zk_sdk::encryption::{
-    auth_encryption::AeKey, elgamal::ElGamalKeypair,
+    auth_encryption::{AeCiphertext, AeKey},
+    elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalSecretKey},
+    pod::{auth_encryption::PodAeCiphertext, elgamal::PodElGamalCiphertext},
 };
+use thiserror::Error;
+
+/// Bit length of the low part of a pending balance
+/// Deposits and incoming transfers are split into a 16-bit lo and a 48-bit hi ciphertext
+pub const PENDING_BALANCE_LO_BIT_LENGTH: u32 = 16;
+
+/// Errors returned when decrypting confidential balances
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceDecryptionError {
+    /// The ciphertext bytes do not encode a valid ciphertext
+    #[error("malformed balance ciphertext")]
+    InvalidCiphertext,
+    /// AES-GCM-SIV authentication failed: the ciphertext was tampered with or the key is wrong
+    #[error("balance ciphertext failed authentication (tampered or wrong key)")]
+    AuthenticationFailed,
+    /// The ElGamal plaintext is outside the range that can be decoded (or the key is wrong)
+    #[error("balance ciphertext could not be decoded")]
+    DiscreteLogFailed,
+    /// The recombined balance does not fit in a u64
+    #[error("decrypted balance overflows u64")]
+    Overflow,
+}
+
+/// Version of the key derivation messages a wallet signs
+///
+/// Version 1 is the scheme of `ElGamalKeypair::new_from_signer` and
+/// `AeKey::new_from_signer` with the token account address as public seed:
+/// the wallet signs `"ElGamalSecretKey" || token_account` and
+/// `"AeKey" || token_account`, and each key is hashed from its signature.
+///
+/// Keys rotated with `/api/account/rotate-key` use a later key epoch, whose
+/// public seed is `token_account || epoch` (u32, little endian). Epoch 0 is
+/// the original key and has no suffix.
+pub const KEY_DERIVATION_VERSION: u8 = 1;
+
+/// Errors returned when deriving keys from wallet signatures
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDerivationError {
+    /// The messages were signed under a scheme this server does not know
+    #[error("unsupported key derivation version {0}")]
+    UnsupportedVersion(u8),
+    /// The signature is not the wallet's signature over the derivation message
+    #[error("{0} signature does not verify against the wallet")]
+    InvalidSignature(&'static str),
+}
+
+/// The messages a wallet signs to derive its encryption keys for a token account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDerivationMessages {
+    pub elgamal: Vec<u8>,
+    pub aes: Vec<u8>,
+}
+
+/// Canonical derivation messages for `token_account` at key `epoch` under `version`
+pub fn key_derivation_messages(
+    version: u8,
+    token_account: &Pubkey,
+    epoch: u32,
+) -> Result<KeyDerivationMessages, KeyDerivationError> {
+    if version != 1 {
+        return Err(KeyDerivationError::UnsupportedVersion(version));
+    }
+
+    let mut public_seed = token_account.to_bytes().to_vec();
+    if epoch > 0 {
+        public_seed.extend_from_slice(&epoch.to_le_bytes());
+    }
+
+    Ok(KeyDerivationMessages {
+        elgamal: [b"ElGamalSecretKey".as_slice(), &public_seed].concat(),
+        aes: [b"AeKey".as_slice(), &public_seed].concat(),
+    })
+}
+
+/// Derive a wallet's ElGamal keypair and AES key from its signatures over the
+/// derivation messages for `token_account` at key `epoch`
+///
+/// Both signatures are verified against `wallet` first, so keys are only
+/// derived from signatures the wallet actually produced. The result matches
+/// `generate_elgamal_keypair` / `generate_aes_key` run with the wallet signer.
+pub fn derive_keys_from_signatures(
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+    version: u8,
+    epoch: u32,
+    elgamal_signature: &Signature,
+    aes_signature: &Signature,
+) -> Result<(ElGamalKeypair, AeKey), KeyDerivationError> {
+    let messages = key_derivation_messages(version, token_account, epoch)?;
+
+    // The default signature verifies against nothing, but is also rejected as key material
+    let verify = |name, signature: &Signature, message: &[u8]| {
+        if *signature == Signature::default() || !signature.verify(wallet.as_ref(), message) {
+            return Err(KeyDerivationError::InvalidSignature(name));
+        }
+        Ok(())
+    };
+    verify("elgamal", elgamal_signature, &messages.elgamal)?;
+    verify("aes", aes_signature, &messages.aes)?;
+
+    let elgamal_keypair = ElGamalKeypair::new_from_signature(elgamal_signature)
+        .map_err(|_| KeyDerivationError::InvalidSignature("elgamal"))?;
+    let aes_key = AeKey::new_from_signature(aes_signature)
+        .map_err(|_| KeyDerivationError::InvalidSignature("aes"))?;
+
+    Ok((elgamal_keypair, aes_key))
+}
+
+/// Longest an owner's custodial access signature may be valid for, in seconds
+pub const MAX_CUSTODIAL_ACCESS_SECS: i64 = 300;
+
+/// Errors returned when checking an owner's custodial access signature
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum CustodialAccessError {
+    #[error("owner signature has expired")]
+    Expired,
+    /// Signatures must be short-lived, as anyone holding one can use the keys
+    #[error("owner signature expiry is more than {max} seconds away", max = MAX_CUSTODIAL_ACCESS_SECS)]
+    ExpiryTooFar,
+    #[error("owner signature does not verify against the wallet")]
+    InvalidSignature,
+}
+
+/// Message the owner of a custodial account signs to let a request use the
+/// account's stored keys until `expires_at` (unix seconds)
+pub fn custodial_access_message(token_account: &Pubkey, expires_at: i64) -> Vec<u8> {
+    format!(
+        "privypass-custodial-access:v1\ntoken_account:{}\nexpires_at:{}",
+        token_account, expires_at
+    )
+    .into_bytes()
+}
+
+/// Check `wallet`'s signature over the custodial access message for
+/// `token_account`, and that it is unexpired and short-lived at `now`
+pub fn verify_custodial_access(
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+    expires_at: i64,
+    signature: &Signature,
+    now: i64,
+) -> Result<(), CustodialAccessError> {
+    if expires_at <= now {
+        return Err(CustodialAccessError::Expired);
+    }
+    if expires_at - now > MAX_CUSTODIAL_ACCESS_SECS {
+        return Err(CustodialAccessError::ExpiryTooFar);
+    }
+
+    let message = custodial_access_message(token_account, expires_at);
+    if !signature.verify(wallet.as_ref(), &message) {
+        return Err(CustodialAccessError::InvalidSignature);
+    }
+    Ok(())
+}
 
 /// Generate ElGamal keypair from wallet signer and token account address
 /// This ensures deterministic key generation per user per token account
@@ -35,17 +196,79 @@ pub fn generate_aes_key(
 }
 
 /// Decrypt a confidential balance using the AES key
+/// Used for the `decryptable_available_balance` field of a confidential account
 pub fn decrypt_balance(
     aes_key: &AeKey,
-    encrypted_balance: &[u8; 36], // AeCiphertext size
-) -> Result<u64> {
-    // Note: This is a simplified version
-    // In production, we'd use the full AeCiphertext struct
-    // and proper decryption methods
-    
-    // For now, we'll just return a placeholder
-    // Real implementation would use aes_key to decrypt
-    Ok(0)
+    encrypted_balance: &PodAeCiphertext,
+) -> Result<u64, BalanceDecryptionError> {
+    let ciphertext = AeCiphertext::try_from(*encrypted_balance)
+        .map_err(|_| BalanceDecryptionError::InvalidCiphertext)?;
+
+    aes_key
+        .decrypt(&ciphertext)
+        .ok_or(BalanceDecryptionError::AuthenticationFailed)
+}
+
+/// Decrypt the pending balance of a confidential account using the ElGamal secret key
+/// The lo/hi ciphertexts are decrypted separately and recombined into a single amount
+pub fn decrypt_pending_balance(
+    elgamal_secret_key: &ElGamalSecretKey,
+    pending_balance_lo: &PodElGamalCiphertext,
+    pending_balance_hi: &PodElGamalCiphertext,
+) -> Result<u64, BalanceDecryptionError> {
+    decrypt_split_amount(
+        elgamal_secret_key,
+        pending_balance_lo,
+        pending_balance_hi,
+        PENDING_BALANCE_LO_BIT_LENGTH,
+    )
+}
+
+/// Decrypt an amount encrypted as separate lo/hi ciphertexts and recombine
+/// it as `hi << lo_bit_length | lo`
+pub(crate) fn decrypt_split_amount(
+    elgamal_secret_key: &ElGamalSecretKey,
+    ciphertext_lo: &PodElGamalCiphertext,
+    ciphertext_hi: &PodElGamalCiphertext,
+    lo_bit_length: u32,
+) -> Result<u64, BalanceDecryptionError> {
+    let decrypt_part = |ciphertext: &PodElGamalCiphertext| {
+        let ciphertext = ElGamalCiphertext::try_from(*ciphertext)
+            .map_err(|_| BalanceDecryptionError::InvalidCiphertext)?;
+        elgamal_secret_key
+            .decrypt_u32(&ciphertext)
+            .ok_or(BalanceDecryptionError::DiscreteLogFailed)
+    };
+
+    let lo = decrypt_part(ciphertext_lo)?;
+    let hi = decrypt_part(ciphertext_hi)?;
+
+    hi.checked_shl(lo_bit_length)
+        .filter(|shifted| shifted >> lo_bit_length == hi)
+        .and_then(|hi| hi.checked_add(lo))
+        .ok_or(BalanceDecryptionError::Overflow)
+}
+
+/// Compute the new decryptable available balance for `apply_pending_balance`
+///
+/// Decrypts the available balance (AES) and the pending lo/hi balances
+/// (ElGamal), and re-encrypts their sum under the AES key. Returns the new
+/// available amount together with its ciphertext.
+pub fn apply_pending_to_available(
+    aes_key: &AeKey,
+    elgamal_secret_key: &ElGamalSecretKey,
+    decryptable_available_balance: &PodAeCiphertext,
+    pending_balance_lo: &PodElGamalCiphertext,
+    pending_balance_hi: &PodElGamalCiphertext,
+) -> Result<(u64, PodAeCiphertext), BalanceDecryptionError> {
+    let available = decrypt_balance(aes_key, decryptable_available_balance)?;
+    let pending = decrypt_pending_balance(elgamal_secret_key, pending_balance_lo, pending_balance_hi)?;
+
+    let new_available = available
+        .checked_add(pending)
+        .ok_or(BalanceDecryptionError::Overflow)?;
+
+    Ok((new_available, aes_key.encrypt(new_available).into()))
 }
 
 #[cfg(test)]
@@ -79,4 +302,220 @@ mod tests {
             elgamal2.pubkey().to_bytes()
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_decrypt_pending_balance() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let elgamal = generate_elgamal_keypair(&wallet, &token_account).unwrap();
+
+        // 70_000 = (1 << 16) + 4_464
+        let lo = elgamal.pubkey().encrypt(4_464u64);
+        let hi = elgamal.pubkey().encrypt(1u64);
+
+        let pending = decrypt_pending_balance(elgamal.secret(), &lo.into(), &hi.into()).unwrap();
+        assert_eq!(pending, 70_000);
+    }
+
+    #[test]
+    fn test_decrypt_balance_round_trip() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let aes_key = generate_aes_key(&wallet, &token_account).unwrap();
+
+        for amount in [0u64, 1, 1_000_000, u64::MAX] {
+            let ciphertext: PodAeCiphertext = aes_key.encrypt(amount).into();
+            assert_eq!(decrypt_balance(&aes_key, &ciphertext), Ok(amount));
+        }
+    }
+
+    #[test]
+    fn test_decrypt_balance_wrong_key() {
+        let token_account = Pubkey::new_unique();
+        let aes_key = generate_aes_key(&Keypair::new(), &token_account).unwrap();
+        let other_key = generate_aes_key(&Keypair::new(), &token_account).unwrap();
+
+        let ciphertext: PodAeCiphertext = aes_key.encrypt(42).into();
+        assert_eq!(
+            decrypt_balance(&other_key, &ciphertext),
+            Err(BalanceDecryptionError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn test_decrypt_balance_tampered() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let aes_key = generate_aes_key(&wallet, &token_account).unwrap();
+
+        let mut bytes = aes_key.encrypt(42).to_bytes();
+        bytes[0] ^= 0x01;
+        let tampered = PodAeCiphertext::from(bytes);
+
+        assert_eq!(
+            decrypt_balance(&aes_key, &tampered),
+            Err(BalanceDecryptionError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn test_derive_keys_from_signatures() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let messages = key_derivation_messages(KEY_DERIVATION_VERSION, &token_account, 0).unwrap();
+
+        let elgamal_signature = wallet.sign_message(&messages.elgamal);
+        let aes_signature = wallet.sign_message(&messages.aes);
+
+        let (elgamal, aes_key) = derive_keys_from_signatures(
+            &wallet.pubkey(),
+            &token_account,
+            KEY_DERIVATION_VERSION,
+            0,
+            &elgamal_signature,
+            &aes_signature,
+        )
+        .unwrap();
+
+        // Same keys as deriving with the wallet signer directly
+        let expected_elgamal = generate_elgamal_keypair(&wallet, &token_account).unwrap();
+        let expected_aes = generate_aes_key(&wallet, &token_account).unwrap();
+        assert_eq!(elgamal.pubkey(), expected_elgamal.pubkey());
+        assert_eq!(aes_key, expected_aes);
+    }
+
+    #[test]
+    fn test_verify_custodial_access() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let now = 1_700_000_000;
+        let expires_at = now + 60;
+        let signature = wallet.sign_message(&custodial_access_message(&token_account, expires_at));
+
+        assert_eq!(
+            verify_custodial_access(&wallet.pubkey(), &token_account, expires_at, &signature, now),
+            Ok(())
+        );
+        assert_eq!(
+            verify_custodial_access(&wallet.pubkey(), &token_account, expires_at, &signature, expires_at),
+            Err(CustodialAccessError::Expired)
+        );
+
+        // Another wallet, another account, or a changed expiry
+        assert_eq!(
+            verify_custodial_access(&Pubkey::new_unique(), &token_account, expires_at, &signature, now),
+            Err(CustodialAccessError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_custodial_access(&wallet.pubkey(), &Pubkey::new_unique(), expires_at, &signature, now),
+            Err(CustodialAccessError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_custodial_access(&wallet.pubkey(), &token_account, expires_at + 1, &signature, now),
+            Err(CustodialAccessError::InvalidSignature)
+        );
+
+        let far = now + MAX_CUSTODIAL_ACCESS_SECS + 1;
+        let signature = wallet.sign_message(&custodial_access_message(&token_account, far));
+        assert_eq!(
+            verify_custodial_access(&wallet.pubkey(), &token_account, far, &signature, now),
+            Err(CustodialAccessError::ExpiryTooFar)
+        );
+    }
+
+    #[test]
+    fn test_derive_keys_rejects_bad_signatures() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let messages = key_derivation_messages(KEY_DERIVATION_VERSION, &token_account, 0).unwrap();
+        let elgamal_signature = wallet.sign_message(&messages.elgamal);
+        let aes_signature = wallet.sign_message(&messages.aes);
+
+        // Signed by another wallet
+        let other = Keypair::new();
+        assert_eq!(
+            derive_keys_from_signatures(
+                &wallet.pubkey(),
+                &token_account,
+                KEY_DERIVATION_VERSION,
+                0,
+                &other.sign_message(&messages.elgamal),
+                &aes_signature,
+            )
+            .err(),
+            Some(KeyDerivationError::InvalidSignature("elgamal"))
+        );
+
+        // Signatures swapped between the two messages
+        assert_eq!(
+            derive_keys_from_signatures(
+                &wallet.pubkey(),
+                &token_account,
+                KEY_DERIVATION_VERSION,
+                0,
+                &aes_signature,
+                &elgamal_signature,
+            )
+            .err(),
+            Some(KeyDerivationError::InvalidSignature("elgamal"))
+        );
+
+        // Signed for another token account
+        assert_eq!(
+            derive_keys_from_signatures(
+                &wallet.pubkey(),
+                &Pubkey::new_unique(),
+                KEY_DERIVATION_VERSION,
+                0,
+                &elgamal_signature,
+                &aes_signature,
+            )
+            .err(),
+            Some(KeyDerivationError::InvalidSignature("elgamal"))
+        );
+
+        // Signed for another key epoch
+        assert_eq!(
+            derive_keys_from_signatures(
+                &wallet.pubkey(),
+                &token_account,
+                KEY_DERIVATION_VERSION,
+                1,
+                &elgamal_signature,
+                &aes_signature,
+            )
+            .err(),
+            Some(KeyDerivationError::InvalidSignature("elgamal"))
+        );
+
+        assert_eq!(
+            key_derivation_messages(2, &token_account, 0),
+            Err(KeyDerivationError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn test_apply_pending_to_available() {
+        let wallet = Keypair::new();
+        let token_account = Pubkey::new_unique();
+        let elgamal = generate_elgamal_keypair(&wallet, &token_account).unwrap();
+        let aes_key = generate_aes_key(&wallet, &token_account).unwrap();
+
+        let available: PodAeCiphertext = aes_key.encrypt(1_000).into();
+        let lo = elgamal.pubkey().encrypt(500u64);
+        let hi = elgamal.pubkey().encrypt(2u64);
+
+        let (new_available, ciphertext) = apply_pending_to_available(
+            &aes_key,
+            elgamal.secret(),
+            &available,
+            &lo.into(),
+            &hi.into(),
+        )
+        .unwrap();
+
+        // 1_000 + (2 << 16) + 500
+        assert_eq!(new_available, 132_572);
+        assert_eq!(decrypt_balance(&aes_key, &ciphertext), Ok(132_572));
+    }
+}
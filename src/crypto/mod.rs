@@ -1,5 +1,9 @@
+pub mod attestation;
+pub mod audit;
 pub mod keys;
 pub mod proof;
 
+pub use attestation::*;
+pub use audit::*;
 pub use keys::*;
 pub use proof::*;
\ No newline at end of file
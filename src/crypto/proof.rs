@@ -1,17 +1,27 @@
 use anyhow::Result;
+use base64::{Engine, prelude::BASE64_STANDARD};
 use solana_sdk::pubkey::Pubkey;
-use spl_token_2022::solana_zk_sdk::encryption::{
-    auth_encryption::AeKey,
-    elgamal::{ElGamalKeypair, ElGamalPubkey},
+use spl_token_2022::solana_zk_sdk::{
+    encryption::{
+        auth_encryption::AeKey,
+        elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+        pedersen::Pedersen,
+        pod::elgamal::{PodElGamalCiphertext, PodElGamalPubkey},
+    },
+    zk_elgamal_proof_program::proof_data::{
+        BatchedRangeProofU64Data, CiphertextCommitmentEqualityProofData, ZkProofData,
+    },
 };
 use spl_token_confidential_transfer_proof_generation::{
     transfer::TransferProofData,
+    transfer_with_fee::TransferWithFeeProofData,
     withdraw::WithdrawProofData,
 };
 use spl_token_2022::extension::confidential_transfer::{
-    account_info::{TransferAccountInfo, WithdrawAccountInfo},
-    instruction::PubkeyValidityProofData,
+    account_info::{EmptyAccountAccountInfo, TransferAccountInfo, WithdrawAccountInfo},
+    instruction::{PubkeyValidityProofData, ZeroCiphertextProofData},
 };
+use thiserror::Error;
 
 /// Generate PubkeyValidityProofData for account configuration
 /// This proves that an ElGamal public key is valid without revealing the secret key
@@ -50,6 +60,39 @@ pub fn generate_transfer_proof(
     Ok(proof_data)
 }
 
+/// Generate transfer-with-fee proof data
+/// In addition to the three transfer proofs, mints with a transfer fee need:
+/// - Percentage-with-cap proof (fee sigma: proves the fee matches the rate and cap)
+/// - Fee ciphertext validity proof (proves the fee is encrypted for the recipient
+///   and the withdraw-withheld authority)
+#[allow(clippy::too_many_arguments)]
+pub fn generate_transfer_with_fee_proof(
+    transfer_account_info: &TransferAccountInfo,
+    amount: u64,
+    sender_elgamal_keypair: &ElGamalKeypair,
+    sender_aes_key: &AeKey,
+    recipient_elgamal_pubkey: &ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<&ElGamalPubkey>,
+    withdraw_withheld_authority_elgamal_pubkey: &ElGamalPubkey,
+    fee_rate_basis_points: u16,
+    maximum_fee: u64,
+) -> Result<TransferWithFeeProofData> {
+    let proof_data = transfer_account_info
+        .generate_split_transfer_with_fee_proof_data(
+            amount,
+            sender_elgamal_keypair,
+            sender_aes_key,
+            recipient_elgamal_pubkey,
+            auditor_elgamal_pubkey,
+            withdraw_withheld_authority_elgamal_pubkey,
+            fee_rate_basis_points,
+            maximum_fee,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to generate transfer with fee proof: {:?}", e))?;
+
+    Ok(proof_data)
+}
+
 /// Generate withdraw proof data
 /// This creates the ZK proofs needed for withdrawing confidential balance to public:
 /// - Equality proof (proves encrypted amount equals plaintext)
@@ -67,29 +110,197 @@ pub fn generate_withdraw_proof(
     Ok(proof_data)
 }
 
-/// Generate a simple eligibility proof (for frontend)
-/// This is a simplified proof showing the user has >= threshold tokens
-/// In production, you might want a more sophisticated proof structure
+/// Generate empty account proof data
+/// This proves the available balance ciphertext encrypts zero, which the
+/// program requires before an account can leave confidential mode
+pub fn generate_empty_account_proof(
+    empty_account_info: &EmptyAccountAccountInfo,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<ZeroCiphertextProofData> {
+    let proof_data = empty_account_info
+        .generate_proof_data(elgamal_keypair)
+        .map_err(|e| anyhow::anyhow!("Failed to generate empty account proof: {:?}", e))?;
+
+    Ok(proof_data)
+}
+
+/// Version byte leading every serialized eligibility proof
+pub const ELIGIBILITY_PROOF_VERSION: u8 = 1;
+
+/// Zero-knowledge proof that an account's encrypted available balance is at
+/// least a public threshold, without revealing the balance
+///
+/// Built like a withdraw proof of `threshold` tokens: the on-chain available
+/// balance ciphertext minus `threshold` is shown to encrypt the same value as
+/// a fresh Pedersen commitment (equality proof), and that commitment is shown
+/// to hold a 64-bit non-negative value (range proof).
+///
+/// Wire format, base64-encoded:
+///
+/// | offset | length | field                                              |
+/// |--------|--------|----------------------------------------------------|
+/// | 0      | 1      | version (`ELIGIBILITY_PROOF_VERSION`)              |
+/// | 1      | E      | `CiphertextCommitmentEqualityProofData` (Pod bytes) |
+/// | 1 + E  | R      | `BatchedRangeProofU64Data` (Pod bytes)              |
+///
+/// The equality proof context carries the owner's ElGamal pubkey, the
+/// balance-minus-threshold ciphertext and the commitment; the range proof
+/// context carries the same commitment.
+#[derive(Clone, Copy)]
+pub struct EligibilityProof {
+    pub equality_proof_data: CiphertextCommitmentEqualityProofData,
+    pub range_proof_data: BatchedRangeProofU64Data,
+}
+
+impl EligibilityProof {
+    const EQUALITY_LEN: usize = std::mem::size_of::<CiphertextCommitmentEqualityProofData>();
+    const RANGE_LEN: usize = std::mem::size_of::<BatchedRangeProofU64Data>();
+    pub const LEN: usize = 1 + Self::EQUALITY_LEN + Self::RANGE_LEN;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.push(ELIGIBILITY_PROOF_VERSION);
+        bytes.extend_from_slice(bytemuck::bytes_of(&self.equality_proof_data));
+        bytes.extend_from_slice(bytemuck::bytes_of(&self.range_proof_data));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::LEN {
+            anyhow::bail!("expected {} bytes, got {}", Self::LEN, bytes.len());
+        }
+        if bytes[0] != ELIGIBILITY_PROOF_VERSION {
+            anyhow::bail!("unsupported proof version {}", bytes[0]);
+        }
+
+        let (equality_bytes, range_bytes) = bytes[1..].split_at(Self::EQUALITY_LEN);
+        Ok(Self {
+            equality_proof_data: bytemuck::pod_read_unaligned(equality_bytes),
+            range_proof_data: bytemuck::pod_read_unaligned(range_bytes),
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.to_bytes())
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = BASE64_STANDARD.decode(encoded)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Generate a proof that `available_balance_ciphertext` encrypts at least `threshold`
+///
+/// `available_balance` is the decrypted value of the ciphertext; the caller
+/// must check it is at least `threshold` first.
 pub fn generate_eligibility_proof(
+    available_balance_ciphertext: &PodElGamalCiphertext,
     available_balance: u64,
     threshold: u64,
-) -> Result<(bool, String)> {
-    let eligible = available_balance >= threshold;
-    
-    // Generate a simple hash-based proof
-    // In production, use proper ZK proof construction
-    let proof = if eligible {
-        format!(
-            "proof:eligible:{}:{}:{}",
-            available_balance,
-            threshold,
-            chrono::Utc::now().timestamp()
-        )
-    } else {
-        "proof:ineligible".to_string()
-    };
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<EligibilityProof> {
+    let surplus = available_balance
+        .checked_sub(threshold)
+        .ok_or_else(|| anyhow::anyhow!("available balance is below the threshold"))?;
+
+    let available_balance_ciphertext = ElGamalCiphertext::try_from(*available_balance_ciphertext)
+        .map_err(|e| anyhow::anyhow!("Invalid available balance ciphertext: {:?}", e))?;
+    let surplus_ciphertext = available_balance_ciphertext.subtract_amount(threshold);
+    let (surplus_commitment, surplus_opening) = Pedersen::new(surplus);
+
+    let equality_proof_data = CiphertextCommitmentEqualityProofData::new(
+        elgamal_keypair,
+        &surplus_ciphertext,
+        &surplus_commitment,
+        &surplus_opening,
+        surplus,
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to generate equality proof: {:?}", e))?;
+
+    let range_proof_data = BatchedRangeProofU64Data::new(
+        vec![&surplus_commitment],
+        vec![surplus],
+        vec![64],
+        vec![&surplus_opening],
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to generate range proof: {:?}", e))?;
+
+    Ok(EligibilityProof {
+        equality_proof_data,
+        range_proof_data,
+    })
+}
 
-    Ok((eligible, proof))
+/// Reasons an eligibility proof is rejected
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum EligibilityProofError {
+    /// The proof was generated for a different ElGamal key than the account's
+    #[error("proof was generated for a different account")]
+    WrongAccount,
+    /// The account's available balance ciphertext (or the threshold) differs
+    /// from the one the proof was built over
+    #[error("available balance changed since the proof was generated, or the threshold differs")]
+    StaleBalance,
+    /// The range proof is not over the commitment from the equality proof
+    #[error("range proof does not cover the committed balance")]
+    CommitmentMismatch,
+    #[error("equality proof failed verification")]
+    InvalidEqualityProof,
+    #[error("range proof failed verification")]
+    InvalidRangeProof,
+}
+
+impl EligibilityProofError {
+    /// Stable machine-readable reason code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::WrongAccount => "WRONG_ACCOUNT",
+            Self::StaleBalance => "STALE_BALANCE",
+            Self::CommitmentMismatch => "COMMITMENT_MISMATCH",
+            Self::InvalidEqualityProof => "INVALID_EQUALITY_PROOF",
+            Self::InvalidRangeProof => "INVALID_RANGE_PROOF",
+        }
+    }
+}
+
+/// Check an eligibility proof against an account's on-chain ElGamal pubkey
+/// and available balance ciphertext
+pub fn verify_eligibility_proof(
+    proof: &EligibilityProof,
+    elgamal_pubkey: &PodElGamalPubkey,
+    available_balance_ciphertext: &ElGamalCiphertext,
+    threshold: u64,
+) -> Result<(), EligibilityProofError> {
+    let equality_context = proof.equality_proof_data.context_data();
+    let range_context = proof.range_proof_data.context_data();
+
+    if equality_context.pubkey != *elgamal_pubkey {
+        return Err(EligibilityProofError::WrongAccount);
+    }
+
+    let surplus_ciphertext = available_balance_ciphertext.subtract_amount(threshold);
+    if equality_context.ciphertext != PodElGamalCiphertext::from(surplus_ciphertext) {
+        return Err(EligibilityProofError::StaleBalance);
+    }
+
+    // The whole 64-bit range must be spent on the committed surplus
+    if range_context.commitments[0] != equality_context.commitment
+        || range_context.bit_lengths[0] != 64
+    {
+        return Err(EligibilityProofError::CommitmentMismatch);
+    }
+
+    proof
+        .equality_proof_data
+        .verify_proof()
+        .map_err(|_| EligibilityProofError::InvalidEqualityProof)?;
+    proof
+        .range_proof_data
+        .verify_proof()
+        .map_err(|_| EligibilityProofError::InvalidRangeProof)?;
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -109,12 +320,137 @@ mod tests {
 
     #[test]
     fn test_eligibility_proof() {
-        let (eligible, proof) = generate_eligibility_proof(100, 50).unwrap();
-        assert!(eligible);
-        assert!(proof.contains("eligible"));
+        let elgamal = ElGamalKeypair::new_rand();
+        let available_balance: PodElGamalCiphertext = elgamal.pubkey().encrypt(100u64).into();
+
+        let proof = generate_eligibility_proof(&available_balance, 100, 50, &elgamal).unwrap();
+        assert!(proof.equality_proof_data.verify_proof().is_ok());
+        assert!(proof.range_proof_data.verify_proof().is_ok());
+
+        // The proof binds the balance-minus-threshold ciphertext, not the balance
+        let surplus_ciphertext = ElGamalCiphertext::try_from(available_balance)
+            .unwrap()
+            .subtract_amount(50u64);
+        assert_eq!(
+            proof.equality_proof_data.context_data().ciphertext,
+            PodElGamalCiphertext::from(surplus_ciphertext)
+        );
+
+        // Balance below the threshold cannot be proven
+        assert!(generate_eligibility_proof(&available_balance, 30, 50, &elgamal).is_err());
+    }
+
+    #[test]
+    fn test_eligibility_proof_encoding() {
+        let elgamal = ElGamalKeypair::new_rand();
+        let available_balance: PodElGamalCiphertext = elgamal.pubkey().encrypt(1_000u64).into();
+        let proof = generate_eligibility_proof(&available_balance, 1_000, 1, &elgamal).unwrap();
+
+        let encoded = proof.to_base64();
+        let decoded = EligibilityProof::from_base64(&encoded).unwrap();
+        assert_eq!(decoded.to_bytes(), proof.to_bytes());
+        assert!(decoded.range_proof_data.verify_proof().is_ok());
 
-        let (not_eligible, proof2) = generate_eligibility_proof(30, 50).unwrap();
-        assert!(!not_eligible);
-        assert!(proof2.contains("ineligible"));
+        let mut bytes = proof.to_bytes();
+        bytes[0] = ELIGIBILITY_PROOF_VERSION + 1;
+        assert!(EligibilityProof::from_bytes(&bytes).is_err());
+        assert!(EligibilityProof::from_bytes(&bytes[..10]).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_transfer_with_fee_proof() {
+        let sender = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+        let recipient = ElGamalKeypair::new_rand();
+        let withheld_authority = ElGamalKeypair::new_rand();
+
+        let transfer_account_info = TransferAccountInfo {
+            available_balance: sender.pubkey().encrypt(1_000u64).into(),
+            decryptable_available_balance: aes_key.encrypt(1_000).into(),
+        };
+
+        let proof_result = generate_transfer_with_fee_proof(
+            &transfer_account_info,
+            500,
+            &sender,
+            &aes_key,
+            recipient.pubkey(),
+            None,
+            withheld_authority.pubkey(),
+            100, // 1%
+            10,
+        );
+        assert!(proof_result.is_ok());
+
+        // Cannot transfer more than the available balance
+        let over_balance = generate_transfer_with_fee_proof(
+            &transfer_account_info,
+            2_000,
+            &sender,
+            &aes_key,
+            recipient.pubkey(),
+            None,
+            withheld_authority.pubkey(),
+            100,
+            10,
+        );
+        assert!(over_balance.is_err());
+    }
+
+    #[test]
+    fn test_empty_account_proof() {
+        let elgamal = ElGamalKeypair::new_rand();
+
+        let empty_account_info = EmptyAccountAccountInfo {
+            available_balance: elgamal.pubkey().encrypt(0u64).into(),
+        };
+        let proof_data = generate_empty_account_proof(&empty_account_info, &elgamal).unwrap();
+        assert!(proof_data.verify_proof().is_ok());
+
+        // A non-zero balance yields a proof the program would reject
+        let funded_account_info = EmptyAccountAccountInfo {
+            available_balance: elgamal.pubkey().encrypt(10u64).into(),
+        };
+        let proof_data = generate_empty_account_proof(&funded_account_info, &elgamal).unwrap();
+        assert!(proof_data.verify_proof().is_err());
+    }
+
+    #[test]
+    fn test_verify_eligibility_proof() {
+        let elgamal = ElGamalKeypair::new_rand();
+        let elgamal_pubkey = PodElGamalPubkey::from(*elgamal.pubkey());
+        let ciphertext = elgamal.pubkey().encrypt(100u64);
+        let proof = generate_eligibility_proof(&ciphertext.into(), 100, 60, &elgamal).unwrap();
+
+        assert_eq!(verify_eligibility_proof(&proof, &elgamal_pubkey, &ciphertext, 60), Ok(()));
+
+        // Claiming a higher threshold than was proven
+        assert_eq!(
+            verify_eligibility_proof(&proof, &elgamal_pubkey, &ciphertext, 90),
+            Err(EligibilityProofError::StaleBalance)
+        );
+
+        // The balance changed after the proof was generated
+        let new_ciphertext = elgamal.pubkey().encrypt(100u64);
+        assert_eq!(
+            verify_eligibility_proof(&proof, &elgamal_pubkey, &new_ciphertext, 60),
+            Err(EligibilityProofError::StaleBalance)
+        );
+
+        // A proof for someone else's account
+        let other = PodElGamalPubkey::from(*ElGamalKeypair::new_rand().pubkey());
+        assert_eq!(
+            verify_eligibility_proof(&proof, &other, &ciphertext, 60),
+            Err(EligibilityProofError::WrongAccount)
+        );
+
+        // A range proof over a different commitment
+        let mut forged = proof;
+        let other_proof = generate_eligibility_proof(&ciphertext.into(), 100, 10, &elgamal).unwrap();
+        forged.range_proof_data = other_proof.range_proof_data;
+        assert_eq!(
+            verify_eligibility_proof(&forged, &elgamal_pubkey, &ciphertext, 60),
+            Err(EligibilityProofError::CommitmentMismatch)
+        );
+    }
+}
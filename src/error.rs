@@ -0,0 +1,280 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_request::{RpcError, RpcResponseErrorData},
+};
+use solana_sdk::{pubkey::Pubkey, transaction::Transaction};
+use thiserror::Error;
+
+use crate::crypto::{BalanceDecryptionError, CustodialAccessError, KeyDerivationError};
+
+/// Error returned by every API handler
+///
+/// Serialized as `{ "success": false, "code": ..., "error": ..., "details": ... }`
+/// where `code` is stable and machine-readable and `error` is a human message.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("invalid {field}: {reason}")]
+    InvalidInput { field: &'static str, reason: String },
+
+    #[error("missing or invalid admin token")]
+    Unauthorized,
+
+    #[error("account {0} not found")]
+    AccountNotFound(Pubkey),
+
+    #[error("account {account} is not usable: {reason}")]
+    InvalidAccount { account: Pubkey, reason: String },
+
+    #[error("failed to decrypt balance: {0}")]
+    Decryption(#[from] BalanceDecryptionError),
+
+    #[error("failed to derive encryption keys: {0}")]
+    KeyDerivation(#[from] KeyDerivationError),
+
+    #[error("stored keys access denied: {0}")]
+    CustodialAccess(#[from] CustodialAccessError),
+
+    #[error("failed to generate proof: {0}")]
+    ProofGeneration(String),
+
+    /// Details stay in the logs; transport errors include the RPC URL,
+    /// which may carry a provider API key
+    #[error("RPC request failed")]
+    Rpc,
+
+    #[error("transaction failed: {message}")]
+    TransactionFailed {
+        message: String,
+        signature: Option<String>,
+        logs: Vec<String>,
+    },
+
+    /// Details stay in the logs; database text is not for clients
+    #[cfg(feature = "database")]
+    #[error("key storage error")]
+    Vault(crate::vault::VaultError),
+
+    /// Details stay in the logs, like `Vault`
+    #[cfg(feature = "database")]
+    #[error("history storage error")]
+    History(sqlx::Error),
+
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    success: bool,
+    code: &'static str,
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    /// Reject a request field
+    pub fn invalid_input(field: &'static str, reason: impl ToString) -> Self {
+        Self::InvalidInput {
+            field,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Reject an on-chain account that exists but cannot be used
+    pub fn invalid_account(account: Pubkey, reason: impl ToString) -> Self {
+        Self::InvalidAccount {
+            account,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Wrap an unexpected failure, keeping the cause for the logs
+    pub fn internal(context: &str, error: impl std::fmt::Debug) -> Self {
+        tracing::error!("{}: {:?}", context, error);
+        Self::Internal(context.to_string())
+    }
+
+    /// Wrap a failed RPC call that did not involve sending a transaction
+    pub fn rpc(error: ClientError) -> Self {
+        tracing::error!("RPC request failed: {:?}", error);
+        Self::Rpc
+    }
+
+    /// Wrap a failed `send_and_confirm_transaction`, keeping the signature
+    /// and any program logs from preflight simulation
+    ///
+    /// The client error itself only goes to the logs, as in [`ApiError::rpc`].
+    pub fn transaction(transaction: &Transaction, error: ClientError) -> Self {
+        let signature = transaction.signatures.first().map(|s| s.to_string());
+        let logs = match error.kind() {
+            ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+                ..
+            }) => result.logs.clone().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        tracing::error!(
+            "Transaction {} failed: {:?}",
+            signature.as_deref().unwrap_or("<unsigned>"),
+            error
+        );
+
+        Self::TransactionFailed {
+            message: "not confirmed; see logs".to_string(),
+            signature,
+            logs,
+        }
+    }
+
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidInput { .. } => "INVALID_INPUT",
+            Self::Unauthorized => "UNAUTHORIZED",
+            Self::AccountNotFound(_) => "ACCOUNT_NOT_FOUND",
+            Self::InvalidAccount { .. } => "INVALID_ACCOUNT",
+            Self::Decryption(_) => "DECRYPTION_FAILED",
+            Self::KeyDerivation(_) => "KEY_DERIVATION_FAILED",
+            Self::CustodialAccess(_) => "CUSTODIAL_ACCESS_DENIED",
+            Self::ProofGeneration(_) => "PROOF_GENERATION_FAILED",
+            Self::Rpc => "RPC_ERROR",
+            Self::TransactionFailed { .. } => "TRANSACTION_FAILED",
+            #[cfg(feature = "database")]
+            Self::Vault(_) => "VAULT_ERROR",
+            #[cfg(feature = "database")]
+            Self::History(_) => "HISTORY_ERROR",
+            Self::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidInput { .. } => StatusCode::BAD_REQUEST,
+            Self::Unauthorized | Self::KeyDerivation(_) | Self::CustodialAccess(_) => StatusCode::UNAUTHORIZED,
+            Self::AccountNotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidAccount { .. } | Self::Decryption(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Rpc | Self::TransactionFailed { .. } => StatusCode::BAD_GATEWAY,
+            Self::ProofGeneration(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            #[cfg(feature = "database")]
+            Self::Vault(_) | Self::History(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::InvalidInput { field, .. } => Some(serde_json::json!({ "field": field })),
+            Self::AccountNotFound(account) | Self::InvalidAccount { account, .. } => {
+                Some(serde_json::json!({ "account": account.to_string() }))
+            }
+            Self::TransactionFailed { signature, logs, .. } => Some(serde_json::json!({
+                "signature": signature,
+                "logs": logs,
+            })),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "database")]
+impl From<crate::vault::VaultError> for ApiError {
+    fn from(error: crate::vault::VaultError) -> Self {
+        tracing::error!("Key vault error: {}", error);
+        Self::Vault(error)
+    }
+}
+
+#[cfg(feature = "database")]
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        tracing::error!("History database error: {}", error);
+        Self::History(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            success: false,
+            code: self.code(),
+            error: self.to_string(),
+            details: self.details(),
+        };
+
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_and_status() {
+        let err = ApiError::invalid_input("wallet_address", "invalid base58");
+        assert_eq!(err.code(), "INVALID_INPUT");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        let err = ApiError::AccountNotFound(Pubkey::new_unique());
+        assert_eq!(err.code(), "ACCOUNT_NOT_FOUND");
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+
+        let err = ApiError::from(BalanceDecryptionError::AuthenticationFailed);
+        assert_eq!(err.code(), "DECRYPTION_FAILED");
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err = ApiError::from(KeyDerivationError::InvalidSignature("elgamal"));
+        assert_eq!(err.code(), "KEY_DERIVATION_FAILED");
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+
+        let err = ApiError::from(CustodialAccessError::Expired);
+        assert_eq!(err.code(), "CUSTODIAL_ACCESS_DENIED");
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[cfg(feature = "database")]
+    #[test]
+    fn test_vault_errors_are_generic() {
+        let err = ApiError::from(crate::vault::VaultError::Decryption(Pubkey::new_unique()));
+        assert_eq!(err.code(), "VAULT_ERROR");
+        assert_eq!(err.to_string(), "key storage error");
+
+        let err = ApiError::from(sqlx::Error::RowNotFound);
+        assert_eq!(err.code(), "HISTORY_ERROR");
+        assert_eq!(err.to_string(), "history storage error");
+    }
+
+    #[test]
+    fn test_rpc_errors_hide_the_url() {
+        let url_error = || ClientError::from(ClientErrorKind::Custom("https://rpc.example/?api-key=secret".into()));
+
+        let err = ApiError::rpc(url_error());
+        assert_eq!(err.code(), "RPC_ERROR");
+        assert!(!err.to_string().contains("api-key"));
+
+        let err = ApiError::transaction(&Transaction::default(), url_error());
+        assert_eq!(err.code(), "TRANSACTION_FAILED");
+        assert!(!err.to_string().contains("api-key"));
+        assert!(!err.details().unwrap().to_string().contains("api-key"));
+    }
+
+    #[test]
+    fn test_transaction_failure_details() {
+        let err = ApiError::TransactionFailed {
+            message: "custom program error: 0x1".to_string(),
+            signature: Some("5xSig".to_string()),
+            logs: vec!["Program log: insufficient funds".to_string()],
+        };
+
+        let details = err.details().unwrap();
+        assert_eq!(details["signature"], "5xSig");
+        assert_eq!(details["logs"][0], "Program log: insufficient funds");
+    }
+}
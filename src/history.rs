@@ -0,0 +1,894 @@
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_2022::{
+    extension::confidential_transfer::instruction::{
+        ApplyPendingBalanceData, ConfidentialTransferInstruction, ConfigureAccountInstructionData,
+        DepositInstructionData, TransferInstructionData, TransferWithFeeInstructionData, WithdrawInstructionData,
+    },
+    id as token_2022_program_id,
+    instruction::{TokenInstruction, decode_instruction_data, decode_instruction_type},
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, pod::auth_encryption::PodAeCiphertext},
+};
+use sqlx::{
+    Row,
+    sqlite::{SqlitePool, SqliteRow},
+};
+use std::str::FromStr;
+
+use crate::{
+    config::HistoryConfig,
+    crypto::decrypt_balance,
+    error::ApiError,
+    solana::{
+        ConfirmedTransaction, ResolvedInstruction, SIGNATURES_PAGE_LIMIT, get_confirmed_transaction,
+        get_signatures_page,
+    },
+    state::AppState,
+};
+
+/// Most signature pages fetched per account in one poll; the scan resumes on the next
+const MAX_SIGNATURE_PAGES_PER_POLL: usize = 5;
+/// Most transactions indexed per account in one poll; the rest wait for the next
+const MAX_TRANSACTIONS_PER_POLL: u32 = 200;
+
+/// What a confidential instruction did to an indexed account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    /// Configured for confidential transfers, with an available balance of zero
+    Configure,
+    /// Public tokens moved into the pending balance
+    Deposit,
+    /// Pending balance credited to the available balance
+    ApplyPendingBalance,
+    /// Confidential transfer out of the available balance
+    TransferOut,
+    /// Confidential transfer into the pending balance
+    TransferIn,
+    /// Available balance moved back to public tokens
+    Withdraw,
+}
+
+impl HistoryKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Configure => "configure",
+            Self::Deposit => "deposit",
+            Self::ApplyPendingBalance => "apply_pending_balance",
+            Self::TransferOut => "transfer_out",
+            Self::TransferIn => "transfer_in",
+            Self::Withdraw => "withdraw",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "configure" => Self::Configure,
+            "deposit" => Self::Deposit,
+            "apply_pending_balance" => Self::ApplyPendingBalance,
+            "transfer_out" => Self::TransferOut,
+            "transfer_in" => Self::TransferIn,
+            "withdraw" => Self::Withdraw,
+            _ => return None,
+        })
+    }
+}
+
+/// A confidential instruction as it affects one token account
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedEntry {
+    pub kind: HistoryKind,
+    /// The other token account of a transfer
+    pub counterparty: Option<Pubkey>,
+    /// Amount carried in plaintext (deposit, withdraw)
+    pub amount: Option<u64>,
+    /// The new available balance the instruction wrote, under the owner's AES key
+    pub decryptable_balance: Option<PodAeCiphertext>,
+}
+
+/// A history entry, as stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedEntry {
+    /// Row id, increasing in chain order; 0 until stored
+    pub id: i64,
+    pub signature: Signature,
+    /// Position among the transaction's top-level instructions
+    pub instruction_index: usize,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub entry: ParsedEntry,
+    /// Decrypted available balance after the instruction; filled in for the
+    /// owner when read, never stored
+    pub balance_after: Option<u64>,
+}
+
+/// Parse a top-level Token-2022 confidential instruction that touches `token_account`
+///
+/// Returns `None` for other programs and instructions, and for instructions
+/// on other accounts.
+pub fn parse_confidential_instruction(
+    token_account: &Pubkey,
+    instruction: &ResolvedInstruction,
+) -> Option<ParsedEntry> {
+    if instruction.program_id != token_2022_program_id()
+        || !matches!(
+            TokenInstruction::unpack(&instruction.data),
+            Ok(TokenInstruction::ConfidentialTransferExtension)
+        )
+    {
+        return None;
+    }
+    let data = &instruction.data[1..];
+
+    // Instructions on a single account take it first
+    let own_account = instruction.accounts.first() == Some(token_account);
+    let entry = |kind| ParsedEntry {
+        kind,
+        counterparty: None,
+        amount: None,
+        decryptable_balance: None,
+    };
+
+    match decode_instruction_type(data).ok()? {
+        ConfidentialTransferInstruction::ConfigureAccount if own_account => {
+            let data = decode_instruction_data::<ConfigureAccountInstructionData>(data).ok()?;
+            Some(ParsedEntry {
+                decryptable_balance: Some(data.decryptable_zero_balance),
+                ..entry(HistoryKind::Configure)
+            })
+        }
+        ConfidentialTransferInstruction::Deposit if own_account => {
+            let data = decode_instruction_data::<DepositInstructionData>(data).ok()?;
+            Some(ParsedEntry {
+                amount: Some(data.amount.into()),
+                ..entry(HistoryKind::Deposit)
+            })
+        }
+        ConfidentialTransferInstruction::ApplyPendingBalance if own_account => {
+            let data = decode_instruction_data::<ApplyPendingBalanceData>(data).ok()?;
+            Some(ParsedEntry {
+                decryptable_balance: Some(data.new_decryptable_available_balance),
+                ..entry(HistoryKind::ApplyPendingBalance)
+            })
+        }
+        ConfidentialTransferInstruction::Withdraw if own_account => {
+            let data = decode_instruction_data::<WithdrawInstructionData>(data).ok()?;
+            Some(ParsedEntry {
+                amount: Some(data.amount.into()),
+                decryptable_balance: Some(data.new_decryptable_available_balance),
+                ..entry(HistoryKind::Withdraw)
+            })
+        }
+        ConfidentialTransferInstruction::Transfer => {
+            let data = decode_instruction_data::<TransferInstructionData>(data).ok()?;
+            parse_transfer(token_account, instruction, data.new_source_decryptable_available_balance)
+        }
+        ConfidentialTransferInstruction::TransferWithFee => {
+            let data = decode_instruction_data::<TransferWithFeeInstructionData>(data).ok()?;
+            parse_transfer(token_account, instruction, data.new_source_decryptable_available_balance)
+        }
+        _ => None,
+    }
+}
+
+/// A transfer is outgoing for its source and incoming for its destination
+fn parse_transfer(
+    token_account: &Pubkey,
+    instruction: &ResolvedInstruction,
+    new_source_balance: PodAeCiphertext,
+) -> Option<ParsedEntry> {
+    // Transfer accounts start with source, mint, destination
+    let [source, _, destination, ..] = instruction.accounts[..] else {
+        return None;
+    };
+
+    if source == *token_account {
+        Some(ParsedEntry {
+            kind: HistoryKind::TransferOut,
+            counterparty: Some(destination),
+            amount: None,
+            decryptable_balance: Some(new_source_balance),
+        })
+    } else if destination == *token_account {
+        // The amount is only encrypted for the recipient in the proof
+        // context; it shows up in the apply that credits it
+        Some(ParsedEntry {
+            kind: HistoryKind::TransferIn,
+            counterparty: Some(source),
+            amount: None,
+            decryptable_balance: None,
+        })
+    } else {
+        None
+    }
+}
+
+/// Decrypt the available balance an entry wrote, if it wrote one under `aes_key`
+///
+/// Entries from before a key rotation were written under the previous key
+/// and stay undecrypted.
+pub fn decrypt_balance_after(aes_key: &AeKey, entry: &ParsedEntry) -> Option<u64> {
+    decrypt_balance(aes_key, entry.decryptable_balance.as_ref()?).ok()
+}
+
+/// Fill in amounts implied by consecutive available balances, oldest entry first
+///
+/// An apply adds the pending credits to the available balance and an outgoing
+/// transfer takes its amount from it, so knowing the balance before and after
+/// either gives the amount. Returns the last known available balance, to carry
+/// into the next batch.
+pub fn derive_amounts(entries: &mut [IndexedEntry], mut previous_balance: Option<u64>) -> Option<u64> {
+    for indexed in entries {
+        if indexed.entry.amount.is_none() {
+            indexed.entry.amount = match (indexed.entry.kind, previous_balance, indexed.balance_after) {
+                (HistoryKind::ApplyPendingBalance, Some(before), Some(after)) => after.checked_sub(before),
+                (HistoryKind::TransferOut, Some(before), Some(after)) => before.checked_sub(after),
+                _ => None,
+            };
+        }
+
+        // Deposits and incoming transfers only touch the pending balance
+        if indexed.entry.decryptable_balance.is_some() {
+            previous_balance = indexed.balance_after;
+        }
+    }
+
+    previous_balance
+}
+
+/// SQLite storage for the confidential history of registered token accounts
+///
+/// Shares the key vault's database. Only what the chain shows in the clear is
+/// stored: the encrypted balances each instruction wrote, and the amounts of
+/// deposits and withdrawals. Balances and the amounts that follow from them
+/// are decrypted when the owner reads the history.
+pub struct HistoryStore {
+    pool: SqlitePool,
+}
+
+impl HistoryStore {
+    /// Open the history tables, creating them if needed
+    pub async fn new(pool: SqlitePool) -> Result<Self, sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS history_accounts (
+                token_account TEXT PRIMARY KEY,
+                owner TEXT NOT NULL,
+                last_signature TEXT,
+                scan_before TEXT,
+                registered_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS history_entries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                token_account TEXT NOT NULL,
+                signature TEXT NOT NULL,
+                instruction_index INTEGER NOT NULL,
+                slot INTEGER NOT NULL,
+                block_time INTEGER,
+                kind TEXT NOT NULL,
+                counterparty TEXT,
+                amount INTEGER,
+                decryptable_balance TEXT,
+                UNIQUE (token_account, signature, instruction_index)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS history_entries_account ON history_entries (token_account, id)")
+            .execute(&pool)
+            .await?;
+        // Signatures found by a scan, newest first by id, waiting to be indexed
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS history_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                token_account TEXT NOT NULL,
+                signature TEXT NOT NULL,
+                UNIQUE (token_account, signature)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Start indexing an account from its first transaction; returns false if
+    /// it was already registered
+    pub async fn register(&self, token_account: &Pubkey, owner: &Pubkey) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO history_accounts (token_account, owner) VALUES (?, ?)
+             ON CONFLICT (token_account) DO NOTHING",
+        )
+        .bind(token_account.to_string())
+        .bind(owner.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stop indexing an account and delete its history; returns whether it was registered
+    pub async fn unregister(&self, token_account: &Pubkey) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for table in ["history_entries", "history_queue"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE token_account = ?", table))
+                .bind(token_account.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        let result = sqlx::query("DELETE FROM history_accounts WHERE token_account = ?")
+            .bind(token_account.to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Registered accounts and the last transaction indexed for each
+    pub async fn accounts(&self) -> Result<Vec<(Pubkey, Option<Signature>)>, sqlx::Error> {
+        let rows = sqlx::query("SELECT token_account, last_signature FROM history_accounts")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let token_account = parse_column(row, "token_account", |value: String| Pubkey::from_str(&value))?;
+                let last_signature = row
+                    .try_get::<Option<String>, _>("last_signature")?
+                    .and_then(|value| Signature::from_str(&value).ok());
+                Ok((token_account, last_signature))
+            })
+            .collect()
+    }
+
+    /// Where an unfinished scan of the account resumes: the oldest signature it found
+    pub async fn scan_cursor(&self, token_account: &Pubkey) -> Result<Option<Signature>, sqlx::Error> {
+        let row = sqlx::query("SELECT scan_before FROM history_accounts WHERE token_account = ?")
+            .bind(token_account.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(match row {
+            Some(row) => row
+                .try_get::<Option<String>, _>("scan_before")?
+                .and_then(|value| Signature::from_str(&value).ok()),
+            None => None,
+        })
+    }
+
+    /// Queue one page of a scan, newest first, and save where the scan
+    /// resumes (`None` once it is finished)
+    ///
+    /// Returns false, queuing nothing, if the account is not registered.
+    pub async fn enqueue(
+        &self,
+        token_account: &Pubkey,
+        signatures: &[Signature],
+        scan_before: Option<Signature>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query("UPDATE history_accounts SET scan_before = ? WHERE token_account = ?")
+            .bind(scan_before.map(|signature| signature.to_string()))
+            .bind(token_account.to_string())
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        for signature in signatures {
+            sqlx::query(
+                "INSERT INTO history_queue (token_account, signature) VALUES (?, ?)
+                 ON CONFLICT (token_account, signature) DO NOTHING",
+            )
+            .bind(token_account.to_string())
+            .bind(signature.to_string())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Queued signatures of an account, oldest first
+    pub async fn queued(&self, token_account: &Pubkey, limit: u32) -> Result<Vec<Signature>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT signature FROM history_queue
+             WHERE token_account = ?
+             ORDER BY id DESC LIMIT ?",
+        )
+        .bind(token_account.to_string())
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| parse_column(row, "signature", |value: String| Signature::from_str(&value)))
+            .collect()
+    }
+
+    /// Owner the account was registered with, if it is registered
+    pub async fn owner(&self, token_account: &Pubkey) -> Result<Option<Pubkey>, sqlx::Error> {
+        let row = sqlx::query("SELECT owner FROM history_accounts WHERE token_account = ?")
+            .bind(token_account.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| parse_column(&row, "owner", |value: String| Pubkey::from_str(&value)))
+            .transpose()
+    }
+
+    /// The latest entry that wrote an available balance, older than `before_id` if given
+    pub async fn last_balance_entry(
+        &self,
+        token_account: &Pubkey,
+        before_id: Option<i64>,
+    ) -> Result<Option<IndexedEntry>, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT * FROM history_entries
+             WHERE token_account = ? AND id < ? AND decryptable_balance IS NOT NULL
+             ORDER BY id DESC LIMIT 1",
+        )
+        .bind(token_account.to_string())
+        .bind(before_id.unwrap_or(i64::MAX))
+        .fetch_optional(&self.pool)
+        .await?;
+        row.as_ref().map(entry_from_row).transpose()
+    }
+
+    /// Store the entries of one transaction, mark it indexed and take it off
+    /// the queue
+    ///
+    /// All happen in one database transaction, so a crash never indexes a
+    /// transaction twice or skips one. Returns false, storing nothing, if the
+    /// account was unregistered in the meantime.
+    pub async fn record(
+        &self,
+        token_account: &Pubkey,
+        signature: &Signature,
+        entries: &[IndexedEntry],
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query("UPDATE history_accounts SET last_signature = ? WHERE token_account = ?")
+            .bind(signature.to_string())
+            .bind(token_account.to_string())
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM history_queue WHERE token_account = ? AND signature = ?")
+            .bind(token_account.to_string())
+            .bind(signature.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        for indexed in entries {
+            // u64 amounts are stored bit-for-bit in SQLite's signed integers
+            sqlx::query(
+                "INSERT INTO history_entries
+                    (token_account, signature, instruction_index, slot, block_time, kind,
+                     counterparty, amount, decryptable_balance)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT (token_account, signature, instruction_index) DO NOTHING",
+            )
+            .bind(token_account.to_string())
+            .bind(indexed.signature.to_string())
+            .bind(indexed.instruction_index as i64)
+            .bind(indexed.slot as i64)
+            .bind(indexed.block_time)
+            .bind(indexed.entry.kind.as_str())
+            .bind(indexed.entry.counterparty.map(|counterparty| counterparty.to_string()))
+            .bind(indexed.entry.amount.map(|amount| amount as i64))
+            .bind(indexed.entry.decryptable_balance.map(|balance| balance.to_string()))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Entries of an account older than `before_id` (all when `None`), newest first
+    pub async fn entries(
+        &self,
+        token_account: &Pubkey,
+        before_id: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<IndexedEntry>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT * FROM history_entries
+             WHERE token_account = ? AND id < ?
+             ORDER BY id DESC LIMIT ?",
+        )
+        .bind(token_account.to_string())
+        .bind(before_id.unwrap_or(i64::MAX))
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(entry_from_row).collect()
+    }
+}
+
+fn entry_from_row(row: &SqliteRow) -> Result<IndexedEntry, sqlx::Error> {
+    Ok(IndexedEntry {
+        id: row.try_get("id")?,
+        signature: parse_column(row, "signature", |value: String| Signature::from_str(&value))?,
+        instruction_index: row.try_get::<i64, _>("instruction_index")? as usize,
+        slot: row.try_get::<i64, _>("slot")? as u64,
+        block_time: row.try_get("block_time")?,
+        entry: ParsedEntry {
+            kind: parse_column(row, "kind", |value: String| {
+                HistoryKind::parse(&value).ok_or(format!("unknown history kind {}", value))
+            })?,
+            counterparty: parse_column(row, "counterparty", |value: Option<String>| {
+                value.map(|value| Pubkey::from_str(&value)).transpose()
+            })?,
+            amount: row.try_get::<Option<i64>, _>("amount")?.map(|amount| amount as u64),
+            decryptable_balance: parse_column(row, "decryptable_balance", |value: Option<String>| {
+                value.map(|value| PodAeCiphertext::from_str(&value)).transpose()
+            })?,
+        },
+        balance_after: None,
+    })
+}
+
+/// Read a column and parse it, reporting parse failures as decode errors
+fn parse_column<'r, V, T, E>(
+    row: &'r SqliteRow,
+    column: &str,
+    parse: impl FnOnce(V) -> Result<T, E>,
+) -> Result<T, sqlx::Error>
+where
+    V: sqlx::Decode<'r, sqlx::Sqlite> + sqlx::Type<sqlx::Sqlite>,
+    E: std::fmt::Display,
+{
+    parse(row.try_get(column)?).map_err(|e| sqlx::Error::ColumnDecode {
+        index: column.to_string(),
+        source: e.to_string().into(),
+    })
+}
+
+/// Spawn the history indexer, polling registered accounts until shutdown
+pub fn spawn(state: AppState, config: HistoryConfig) -> tokio::task::JoinHandle<()> {
+    tracing::info!("History indexer started (poll {:?})", config.poll_interval);
+
+    tokio::spawn(async move {
+        // The store is opened whenever the indexer is configured
+        let Some(store) = state.history.clone() else {
+            tracing::error!("History indexer has no history store; stopping");
+            return;
+        };
+
+        let mut ticker = tokio::time::interval(config.poll_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            let accounts = match store.accounts().await {
+                Ok(accounts) => accounts,
+                Err(e) => {
+                    tracing::warn!("History indexer could not list accounts: {}", e);
+                    continue;
+                }
+            };
+            for (token_account, last_signature) in accounts {
+                match index_account(&state, &store, &token_account, last_signature).await {
+                    Ok(0) => {}
+                    Ok(indexed) => tracing::info!("Indexed {} history entries for {}", indexed, token_account),
+                    Err(e) => tracing::warn!("History indexing for {} failed: {}", token_account, e),
+                }
+            }
+        }
+    })
+}
+
+/// Index the transactions of an account since `last_signature`, oldest first
+///
+/// A scan pages back from the newest signature to `last_signature`, queuing
+/// what it finds, at most `MAX_SIGNATURE_PAGES_PER_POLL` pages per poll and
+/// resuming from the stored cursor. Once it is finished the queue is indexed
+/// oldest first, `MAX_TRANSACTIONS_PER_POLL` per poll, and the next scan
+/// starts when it is empty. Returns how many entries were stored.
+async fn index_account(
+    state: &AppState,
+    store: &HistoryStore,
+    token_account: &Pubkey,
+    last_signature: Option<Signature>,
+) -> Result<usize, ApiError> {
+    let client = &state.rpc;
+
+    // 1. Scan for new signatures, unless a finished scan is still queued
+    let mut scan_before = store.scan_cursor(token_account).await?;
+    let mut queued = match scan_before {
+        Some(_) => Vec::new(),
+        None => store.queued(token_account, MAX_TRANSACTIONS_PER_POLL).await?,
+    };
+    if queued.is_empty() {
+        for _ in 0..MAX_SIGNATURE_PAGES_PER_POLL {
+            let page = get_signatures_page(client, token_account, scan_before, last_signature)
+                .await
+                .map_err(ApiError::rpc)?;
+            let signatures: Vec<_> = page.into_iter().map(|(signature, _)| signature).collect();
+
+            // A short page reached `last_signature`
+            scan_before = if signatures.len() < SIGNATURES_PAGE_LIMIT {
+                None
+            } else {
+                signatures.last().copied()
+            };
+            if !store.enqueue(token_account, &signatures, scan_before).await? {
+                // Unregistered since the poll started
+                return Ok(0);
+            }
+            if scan_before.is_none() {
+                break;
+            }
+        }
+        if scan_before.is_some() {
+            return Ok(0);
+        }
+        queued = store.queued(token_account, MAX_TRANSACTIONS_PER_POLL).await?;
+    }
+
+    // 2. Index the queue, oldest first
+    let mut indexed = 0;
+    for signature in queued {
+        let entries = match get_confirmed_transaction(client, &signature)
+            .await
+            .map_err(ApiError::rpc)?
+        {
+            Some(transaction) if transaction.failed => Vec::new(),
+            Some(transaction) => transaction_entries(token_account, &transaction),
+            // Not served yet; retry from here on the next poll
+            None => {
+                tracing::debug!("Transaction {} not available yet", signature);
+                break;
+            }
+        };
+        if !store.record(token_account, &signature, &entries).await? {
+            // Unregistered since the poll started
+            break;
+        }
+        indexed += entries.len();
+    }
+
+    Ok(indexed)
+}
+
+/// The history entries of one transaction for `token_account`
+fn transaction_entries(token_account: &Pubkey, transaction: &ConfirmedTransaction) -> Vec<IndexedEntry> {
+    transaction
+        .instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(instruction_index, instruction)| {
+            let entry = parse_confidential_instruction(token_account, instruction)?;
+            Some(IndexedEntry {
+                id: 0,
+                signature: transaction.signature,
+                instruction_index,
+                slot: transaction.slot,
+                block_time: transaction.block_time,
+                entry,
+                balance_after: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::instruction::Instruction;
+    use spl_token_2022::{
+        extension::confidential_transfer::instruction::{apply_pending_balance, deposit, transfer},
+        solana_zk_sdk::encryption::elgamal::ElGamalKeypair,
+    };
+    use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    fn resolved(instruction: &Instruction) -> ResolvedInstruction {
+        ResolvedInstruction {
+            program_id: instruction.program_id,
+            accounts: instruction.accounts.iter().map(|meta| meta.pubkey).collect(),
+            data: instruction.data.clone(),
+        }
+    }
+
+    fn indexed(kind: HistoryKind, amount: Option<u64>, balance_after: Option<u64>) -> IndexedEntry {
+        let aes_key = AeKey::new_rand();
+        IndexedEntry {
+            id: 0,
+            signature: Signature::new_unique(),
+            instruction_index: 0,
+            slot: 1,
+            block_time: None,
+            entry: ParsedEntry {
+                kind,
+                counterparty: None,
+                amount,
+                decryptable_balance: balance_after.map(|balance| aes_key.encrypt(balance).into()),
+            },
+            balance_after,
+        }
+    }
+
+    #[test]
+    fn test_parse_confidential_instruction() {
+        let token_account = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let aes_key = AeKey::new_rand();
+        let auditor = ElGamalKeypair::new_rand();
+
+        let deposit_ix = deposit(
+            &spl_token_2022::id(),
+            &token_account,
+            &Pubkey::new_unique(),
+            1_000,
+            6,
+            &Pubkey::new_unique(),
+            &[],
+        )
+        .unwrap();
+        let entry = parse_confidential_instruction(&token_account, &resolved(&deposit_ix)).unwrap();
+        assert_eq!(entry.kind, HistoryKind::Deposit);
+        assert_eq!(entry.amount, Some(1_000));
+        assert_eq!(parse_confidential_instruction(&other, &resolved(&deposit_ix)), None);
+
+        let apply_ix = apply_pending_balance(
+            &spl_token_2022::id(),
+            &token_account,
+            1,
+            &aes_key.encrypt(1_000).into(),
+            &Pubkey::new_unique(),
+            &[],
+        )
+        .unwrap();
+        let entry = parse_confidential_instruction(&token_account, &resolved(&apply_ix)).unwrap();
+        assert_eq!(entry.kind, HistoryKind::ApplyPendingBalance);
+        assert_eq!(decrypt_balance_after(&aes_key, &entry), Some(1_000));
+        assert_eq!(decrypt_balance_after(&AeKey::new_rand(), &entry), None);
+
+        // A transfer is outgoing for its source and incoming for its destination
+        let transfer_ixs = transfer(
+            &spl_token_2022::id(),
+            &token_account,
+            &Pubkey::new_unique(),
+            &other,
+            &aes_key.encrypt(400).into(),
+            &auditor.pubkey().encrypt(400_u64).into(),
+            &auditor.pubkey().encrypt(0_u64).into(),
+            &Pubkey::new_unique(),
+            &[],
+            ProofLocation::ContextStateAccount(&Pubkey::new_unique()),
+            ProofLocation::ContextStateAccount(&Pubkey::new_unique()),
+            ProofLocation::ContextStateAccount(&Pubkey::new_unique()),
+        )
+        .unwrap();
+        let outgoing = parse_confidential_instruction(&token_account, &resolved(&transfer_ixs[0])).unwrap();
+        assert_eq!(outgoing.kind, HistoryKind::TransferOut);
+        assert_eq!(outgoing.counterparty, Some(other));
+        assert_eq!(decrypt_balance_after(&aes_key, &outgoing), Some(400));
+
+        let incoming = parse_confidential_instruction(&other, &resolved(&transfer_ixs[0])).unwrap();
+        assert_eq!(incoming.kind, HistoryKind::TransferIn);
+        assert_eq!(incoming.counterparty, Some(token_account));
+        assert_eq!(incoming.decryptable_balance, None);
+    }
+
+    #[test]
+    fn test_derive_amounts() {
+        let mut entries = vec![
+            indexed(HistoryKind::Configure, None, Some(0)),
+            indexed(HistoryKind::Deposit, Some(1_000), None),
+            indexed(HistoryKind::TransferIn, None, None),
+            indexed(HistoryKind::ApplyPendingBalance, None, Some(1_500)),
+            indexed(HistoryKind::TransferOut, None, Some(1_200)),
+            indexed(HistoryKind::Withdraw, Some(200), Some(1_000)),
+        ];
+
+        assert_eq!(derive_amounts(&mut entries, None), Some(1_000));
+        let amounts: Vec<_> = entries.iter().map(|indexed| indexed.entry.amount).collect();
+        assert_eq!(amounts, [None, Some(1_000), None, Some(500), Some(300), Some(200)]);
+
+        // Without the balance before, an apply's credits are unknown
+        let mut entries = vec![indexed(HistoryKind::ApplyPendingBalance, None, Some(1_500))];
+        assert_eq!(derive_amounts(&mut entries, None), Some(1_500));
+        assert_eq!(entries[0].entry.amount, None);
+
+        // An undecrypted balance breaks the chain
+        let mut entries = vec![
+            indexed(HistoryKind::Withdraw, Some(100), Some(900)),
+            indexed(HistoryKind::ApplyPendingBalance, None, None),
+            indexed(HistoryKind::TransferOut, None, Some(700)),
+        ];
+        entries[1].entry.decryptable_balance = Some(AeKey::new_rand().encrypt(1_000).into());
+        assert_eq!(derive_amounts(&mut entries, Some(1_000)), Some(700));
+        assert_eq!(entries[2].entry.amount, None);
+    }
+
+    #[tokio::test]
+    async fn test_store_records_and_pages() {
+        // One connection: every in-memory connection is its own database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let store = HistoryStore::new(pool).await.unwrap();
+        let token_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        // Nothing is recorded for unregistered accounts
+        let deposit = indexed(HistoryKind::Deposit, Some(1_000), None);
+        assert!(!store.record(&token_account, &deposit.signature, &[deposit.clone()]).await.unwrap());
+
+        assert!(store.register(&token_account, &owner).await.unwrap());
+        assert!(!store.register(&token_account, &owner).await.unwrap());
+        assert_eq!(store.owner(&token_account).await.unwrap(), Some(owner));
+
+        let entries = [
+            deposit,
+            indexed(HistoryKind::ApplyPendingBalance, None, Some(1_000)),
+            indexed(HistoryKind::Withdraw, Some(u64::MAX), None),
+        ];
+        for entry in &entries {
+            assert!(store.record(&token_account, &entry.signature, &[entry.clone()]).await.unwrap());
+        }
+        assert_eq!(
+            store.accounts().await.unwrap(),
+            [(token_account, Some(entries[2].signature))]
+        );
+
+        // Newest first, continuing below the last id of each page
+        let page = store.entries(&token_account, None, 2).await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].entry, entries[2].entry);
+        assert_eq!(page[1].entry, entries[1].entry);
+        let rest = store.entries(&token_account, Some(page[1].id), 2).await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].signature, entries[0].signature);
+
+        // Balances are stored encrypted only
+        let last = store.last_balance_entry(&token_account, None).await.unwrap().unwrap();
+        assert_eq!(last.entry, entries[1].entry);
+        assert_eq!(last.balance_after, None);
+        assert!(store.last_balance_entry(&token_account, Some(last.id)).await.unwrap().is_none());
+
+        assert!(store.unregister(&token_account).await.unwrap());
+        assert!(store.entries(&token_account, None, 10).await.unwrap().is_empty());
+        assert_eq!(store.owner(&token_account).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_store_queues_scans() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let store = HistoryStore::new(pool).await.unwrap();
+        let token_account = Pubkey::new_unique();
+        let signatures: Vec<_> = (0..4).map(|_| Signature::new_unique()).collect();
+
+        assert!(!store.enqueue(&token_account, &signatures, None).await.unwrap());
+        assert!(store.register(&token_account, &Pubkey::new_unique()).await.unwrap());
+
+        // Pages arrive newest first; the cursor is the oldest signature so far
+        assert!(store.enqueue(&token_account, &signatures[..2], Some(signatures[1])).await.unwrap());
+        assert_eq!(store.scan_cursor(&token_account).await.unwrap(), Some(signatures[1]));
+        assert!(store.enqueue(&token_account, &signatures[2..], None).await.unwrap());
+        assert_eq!(store.scan_cursor(&token_account).await.unwrap(), None);
+
+        let queued = store.queued(&token_account, 10).await.unwrap();
+        assert_eq!(queued, [signatures[3], signatures[2], signatures[1], signatures[0]]);
+        assert_eq!(store.queued(&token_account, 1).await.unwrap(), [signatures[3]]);
+
+        // Recording a transaction takes it off the queue
+        assert!(store.record(&token_account, &signatures[3], &[]).await.unwrap());
+        assert_eq!(store.queued(&token_account, 1).await.unwrap(), [signatures[2]]);
+
+        assert!(store.unregister(&token_account).await.unwrap());
+        assert!(store.queued(&token_account, 10).await.unwrap().is_empty());
+    }
+}
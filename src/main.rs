@@ -2,14 +2,24 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use std::net::SocketAddr;
 use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber;
 
+mod auto_apply;
+mod config;
 mod crypto;
+mod error;
+#[cfg(feature = "database")]
+mod history;
 mod models;
 mod routes;
 mod solana;
+mod state;
+#[cfg(feature = "database")]
+mod vault;
+
+use config::Config;
+use state::AppState;
 
 
 #[tokio::main]
@@ -20,42 +30,132 @@ async fn main() {
     // Load environment variables
     dotenv::dotenv().ok();
 
+    // Build shared state (RPC client, fee payer, key vault); refuse to start without a payer
+    let state = match Config::from_env() {
+        Ok(config) => AppState::new(config).await,
+        Err(e) => Err(e),
+    }
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to initialize application state: {:#}", e);
+        std::process::exit(1);
+    });
+    report_payer(&state).await;
+
+    // Close the proof context accounts of unsigned-mode requests left open
+    solana::spawn_proof_sweeper(state.rpc.clone(), state.payer.clone(), state.proof_sweeper.clone());
+
+    // Opt-in: apply pending balances of registered accounts in the background
+    if let Some(auto_apply_config) = state.config.auto_apply.clone() {
+        auto_apply::restore(&state).await;
+        auto_apply::spawn(state.clone(), auto_apply_config);
+    }
+
+    // Opt-in: index the confidential history of registered accounts
+    #[cfg(feature = "database")]
+    if let Some(history_config) = state.config.history.clone() {
+        history::spawn(state.clone(), history_config);
+    }
+
     // Build our application with routes
     let app = Router::new()
         // Health check
         .route("/health", get(health_check))
         
-        // Account management
+        // Mint management (admin)
+        .route("/api/mint/create", post(routes::mint::create_mint))
+
+        // Key derivation: messages the owner's wallet signs
+        .route("/api/keys/messages", post(routes::keys::get_key_derivation_messages))
+        .route("/api/keys/access-message", post(routes::keys::get_custodial_access_message))
+
+        // Account management (approve/pending are admin)
         .route("/api/account/create", post(routes::account::create_confidential_account))
         .route("/api/account/balance", post(routes::account::get_balance))
+        .route("/api/account/approve", post(routes::account::approve_account))
+        .route("/api/account/pending", post(routes::account::list_pending_accounts))
+        .route("/api/account/empty", post(routes::account::empty_account))
+        .route("/api/account/rotate-key", post(routes::rotation::rotate_key))
+
+        // Account settings: which kinds of incoming transfers are accepted
+        .route(
+            "/api/account/credits/confidential/enable",
+            post(routes::settings::enable_confidential_credits),
+        )
+        .route(
+            "/api/account/credits/confidential/disable",
+            post(routes::settings::disable_confidential_credits),
+        )
+        .route(
+            "/api/account/credits/non-confidential/enable",
+            post(routes::settings::enable_non_confidential_credits),
+        )
+        .route(
+            "/api/account/credits/non-confidential/disable",
+            post(routes::settings::disable_non_confidential_credits),
+        )
         
         // Confidential operations
         .route("/api/deposit", post(routes::deposit::deposit_tokens))
         .route("/api/apply", post(routes::deposit::apply_pending_balance))
         .route("/api/transfer", post(routes::transfer::confidential_transfer))
         .route("/api/withdraw", post(routes::withdraw::withdraw_tokens))
+
+        // Background auto-apply of pending balances (admin)
+        .route("/api/apply/auto/register", post(routes::auto_apply::register_auto_apply))
+        .route("/api/apply/auto/unregister", post(routes::auto_apply::unregister_auto_apply))
+
+        // Auditing: decrypt transfer amounts with the mint's auditor key
+        .route("/api/auditor/transfers", post(routes::auditor::audit_transfers))
+
+        // Non-custodial mode: broadcast wallet-signed transactions
+        .route("/api/tx/submit", post(routes::tx::submit_transactions))
         
-        // Proof generation (for frontend verification)
+        // Eligibility proofs
         .route("/api/proof/generate", post(routes::account::generate_proof))
-        
+        .route("/api/proof/verify", post(routes::proof::verify_proof))
+
+        // Eligibility attestations: published keys, rotation and revocation
+        .route("/api/attestation/keys", get(routes::attestation::attestation_keys))
+        .route("/api/attestation/rotate", post(routes::attestation::rotate_attestation_key))
+        .route("/api/attestation/revoke", post(routes::attestation::revoke_attestation));
+
+    // Confidential transaction history, stored in the database (register/unregister are admin)
+    #[cfg(feature = "database")]
+    let app = app
+        .route("/api/account/history", post(routes::history::get_account_history))
+        .route("/api/account/history/register", post(routes::history::register_history))
+        .route("/api/account/history/unregister", post(routes::history::unregister_history));
+
+    let app = app
         // CORS layer
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
                 .allow_methods(Any)
                 .allow_headers(Any),
-        );
+        )
+        .with_state(state.clone());
 
     // Run server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
+    let addr = state.config.bind_addr;
     tracing::info!("Private Pass Backend listening on {}", addr);
-    
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
 }
 
 async fn health_check() -> &'static str {
     "OK"
 }
+
+/// Log the payer pubkey and lamport balance at boot
+async fn report_payer(state: &AppState) {
+    use solana_sdk::signer::Signer;
+
+    let payer = &state.payer;
+    match state.rpc.get_balance(&payer.pubkey()).await {
+        Ok(0) => tracing::warn!("Payer {} has no lamports; transactions will fail", payer.pubkey()),
+        Ok(lamports) => tracing::info!("Payer {} balance: {} lamports", payer.pubkey(), lamports),
+        Err(e) => tracing::warn!("Could not fetch balance for payer {}: {:?}", payer.pubkey(), e),
+    }
+}
@@ -1,21 +1,58 @@
 use serde::{Deserialize, Serialize};
 use solana_sdk::pubkey::Pubkey;
 
+use crate::crypto::{AttestationKeyInfo, SignedAttestation};
+
 // Request/Response models
 
+/// How the backend finishes an operation once its transactions are built
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionMode {
+    /// Sign with the server payer and broadcast; rejected unless the payer
+    /// owns the account, since the owner must sign
+    #[default]
+    Submit,
+    /// Return the payer-partially-signed transactions (base64) for the user's wallet to sign
+    Unsigned,
+}
+
+/// The owner's wallet signatures over the key derivation messages from
+/// /api/keys/messages; the server derives the ElGamal and AES keys from them
+///
+/// May be omitted for custodial accounts whose keys are in the key vault, if
+/// the request carries the admin token or the owner's signature from
+/// /api/keys/access-message.
+#[derive(Debug, Deserialize)]
+pub struct KeySignatures {
+    pub version: u8,
+    // Key epoch the messages were built for; 0 until the key is rotated
+    #[serde(default)]
+    pub epoch: u32,
+    // Base58 signatures
+    pub elgamal_signature: String,
+    pub aes_signature: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateAccountRequest {
     pub wallet_address: String,
     pub mint_address: String,
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
+    #[serde(default)]
+    pub mode: SubmissionMode,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CreateAccountResponse {
     pub success: bool,
     pub token_account: String,
-    pub signature: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,85 +60,187 @@ pub struct DepositRequest {
     pub wallet_address: String,
     pub token_account: String,
     pub amount: u64,
-    pub decimals: u8,
+    // Optional: checked against the mint's decimals when provided
+    #[serde(default)]
+    pub decimals: Option<u8>,
+    #[serde(default)]
+    pub mode: SubmissionMode,
 }
 
 #[derive(Debug, Serialize)]
 pub struct DepositResponse {
     pub success: bool,
-    pub signature: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ApplyPendingRequest {
     pub wallet_address: String,
     pub token_account: String,
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
+    #[serde(default)]
+    pub mode: SubmissionMode,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ApplyPendingResponse {
     pub success: bool,
-    pub signature: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+    // Number of pending credits applied
+    pub pending_balance_credit_counter: u64,
+    pub new_available_balance: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TransferRequest {
     pub sender_wallet: String,
     pub sender_token_account: String,
+    // Signed by the sender's wallet
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
     pub recipient_token_account: String,
     pub recipient_elgamal_pubkey: String,
     pub amount: u64,
+    #[serde(default)]
+    pub mode: SubmissionMode,
 }
 
 #[derive(Debug, Serialize)]
 pub struct TransferResponse {
     pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+    // Fee charged on mints with a transfer fee (withheld from the recipient)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u64>,
+    // Transfer amount encrypted under the mint's auditor key (base64), if one is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auditor_ciphertext_lo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auditor_ciphertext_hi: Option<String>,
+    // Proof context accounts that could not be closed (rent not recovered)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unclosed_proof_accounts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditTransfersRequest {
+    // Auditor ElGamal secret key (base58); used for this request only, never stored
+    pub auditor_elgamal_secret_key: String,
+    // Audit the transfers of one transaction...
+    #[serde(default)]
+    pub signature: Option<String>,
+    // ...or of every transaction involving `mint` in `start_slot..=end_slot`
+    #[serde(default)]
+    pub mint: Option<String>,
+    #[serde(default)]
+    pub start_slot: Option<u64>,
+    #[serde(default)]
+    pub end_slot: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditedTransfer {
     pub signature: String,
+    pub slot: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<i64>,
+    // Position of the transfer among the transaction's top-level instructions
+    pub instruction_index: usize,
+    pub source_token_account: String,
+    pub mint: String,
+    pub destination_token_account: String,
+    pub with_fee: bool,
+    // Decrypted transfer amount, when it is encrypted under the auditor key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+    pub auditor_ciphertext_lo: String,
+    pub auditor_ciphertext_hi: String,
+    // Why the amount could not be decrypted
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct AuditTransfersResponse {
+    pub success: bool,
+    pub transactions_scanned: usize,
+    pub transfers: Vec<AuditedTransfer>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WithdrawRequest {
     pub wallet_address: String,
     pub token_account: String,
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
     pub amount: u64,
-    pub decimals: u8,
+    // Optional: checked against the mint's decimals when provided
+    #[serde(default)]
+    pub decimals: Option<u8>,
+    #[serde(default)]
+    pub mode: SubmissionMode,
 }
 
 #[derive(Debug, Serialize)]
 pub struct WithdrawResponse {
     pub success: bool,
-    pub signature: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+    // Proof context accounts that could not be closed (rent not recovered)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unclosed_proof_accounts: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GenerateProofRequest {
     pub wallet_address: String,
     pub token_account: String,
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
     pub threshold: u64,
+    // Also return a server-signed attestation that can be checked offline
+    #[serde(default)]
+    pub attest: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct GenerateProofResponse {
     pub success: bool,
-    pub proof: String,
-    pub public_inputs: Vec<String>,
     pub eligible: bool,
+    // Base64 `EligibilityProof`; omitted when the balance is below the threshold
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub proof: Option<String>,
+    pub token_account: String,
+    pub threshold: u64,
+    // Slot the available balance ciphertext was read at
+    pub slot: u64,
+    // Signed statement of eligibility, when requested and eligible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<SignedAttestation>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GetBalanceRequest {
     pub wallet_address: String,
     pub token_account: String,
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
 }
 
 #[derive(Debug, Serialize)]
@@ -110,6 +249,428 @@ pub struct GetBalanceResponse {
     pub available_balance: u64,
     pub pending_balance: u64,
     pub decrypted_available: Option<u64>,
+    pub pending_balance_credit_counter: u64,
+    // Account settings controlled by the owner and the mint authority
+    pub approved: bool,
+    pub allow_confidential_credits: bool,
+    pub allow_non_confidential_credits: bool,
+    // Raw on-chain ciphertexts (base64) so clients can verify the decrypted values
+    pub available_balance_ciphertext: String,
+    pub decryptable_available_balance: String,
+    pub pending_balance_lo: String,
+    pub pending_balance_hi: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitTransactionsRequest {
+    // Base64 wire-format transactions signed by the user's wallet
+    pub transactions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubmitTransactionsResponse {
+    pub success: bool,
+    pub signatures: Vec<String>,
+    // Proof context accounts that could not be closed (rent not recovered);
+    // the server retries them once their blockhash expires
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unclosed_proof_accounts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateMintRequest {
+    // Mint and confidential-transfer authority
+    pub authority: String,
+    pub decimals: u8,
+    #[serde(default)]
+    pub auto_approve_new_accounts: bool,
+    // Base58 ElGamal pubkey that can decrypt every transfer amount
+    pub auditor_elgamal_pubkey: Option<String>,
+    pub transfer_fee: Option<TransferFeeParams>,
+    pub metadata: Option<TokenMetadataParams>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferFeeParams {
+    pub basis_points: u16,
+    pub maximum_fee: u64,
+    // Base58 ElGamal pubkey that withheld confidential fees are encrypted under
+    pub withdraw_withheld_authority_elgamal_pubkey: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenMetadataParams {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateMintResponse {
+    pub success: bool,
+    pub mint: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveAccountRequest {
+    pub token_account: String,
+    #[serde(default)]
+    pub mode: SubmissionMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApproveAccountResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Base64 transactions for the mint authority to sign when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListPendingAccountsRequest {
+    pub mint_address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListPendingAccountsResponse {
+    pub success: bool,
+    pub mint: String,
+    // Configured confidential accounts still waiting for approval
+    pub pending_accounts: Vec<PendingAccount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PendingAccount {
+    pub token_account: String,
+    pub owner: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmptyAccountRequest {
+    pub wallet_address: String,
+    pub token_account: String,
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
+    // Also close the token account and return its rent to the owner
+    #[serde(default)]
+    pub close_account: bool,
+    #[serde(default)]
+    pub mode: SubmissionMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmptyAccountResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+    // The token account was closed; in unsigned mode, it is closed once the
+    // returned transactions land
+    pub closed: bool,
+    // Proof context accounts that could not be closed (rent not recovered)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unclosed_proof_accounts: Vec<String>,
+}
+
+/// A step of an ElGamal key rotation, in the order the steps run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationStep {
+    /// Apply pending credits under the old keys
+    ApplyPending,
+    /// Withdraw the confidential available balance to the public balance
+    Withdraw,
+    /// Prove the available balance is zero and empty the account
+    Empty,
+    /// Move the public balance to the holding account and close the token account
+    Park,
+    /// Recreate the token account and configure it with the new ElGamal pubkey
+    Reconfigure,
+    /// Move the parked balance back, deposit it and apply it under the new keys
+    Restore,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateKeyRequest {
+    pub wallet_address: String,
+    pub token_account: String,
+    // Signatures for the current keys; omit for custodial accounts
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
+    // Signatures for the new keys, over the messages of a new epoch; required
+    // with `key_signatures`, omitted for custodial accounts (new keys go to the vault)
+    #[serde(default)]
+    pub new_key_signatures: Option<KeySignatures>,
+    // Custodial accounts only: begin a new rotation when none is in progress;
+    // omit it when resuming or polling for completion
+    #[serde(default)]
+    pub start: bool,
+    #[serde(default)]
+    pub mode: SubmissionMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotationStepResult {
+    pub step: RotationStep,
+    pub signature: String,
+    pub detail: String,
+    // Proof context accounts that could not be closed (rent not recovered)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unclosed_proof_accounts: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateKeyResponse {
+    pub success: bool,
+    pub completed: bool,
+    // Steps confirmed by this request, in order (mode `submit`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<RotationStepResult>,
+    // Step the returned transactions perform (mode `unsigned`); call again
+    // once they are confirmed to get the next one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_step: Option<RotationStep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_step_detail: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+    // Token account that holds the public balance while the account is recreated
+    pub holding_account: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_elgamal_pubkey: Option<String>,
+    // Step that failed and why (mode `submit`); the rotation resumes from it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_step: Option<RotationStep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<&'static str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCreditsRequest {
+    pub wallet_address: String,
+    pub token_account: String,
+    #[serde(default)]
+    pub mode: SubmissionMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateCreditsResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    // Base64 transactions, in submission order, when mode is `unsigned`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyProofRequest {
+    // Base64 `EligibilityProof` from /api/proof/generate
+    pub proof: String,
+    pub token_account: String,
+    pub threshold: u64,
+    // Slot the proof was generated at; the account is read at this slot or later
+    pub slot: u64,
+    // Optional: the attestation issued with the proof, checked against the
+    // server's keyring and revocations
+    #[serde(default)]
+    pub attestation: Option<SignedAttestation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyProofResponse {
+    pub success: bool,
+    pub valid: bool,
+    // Machine-readable reason the proof was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    // Slot the account was read at, when it was read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified_slot: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttestationKeysResponse {
+    pub success: bool,
+    pub keys: Vec<AttestationKeyInfo>,
+    // Nonces of revoked attestations that have not yet expired
+    pub revoked_attestations: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateAttestationKeyResponse {
+    pub success: bool,
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeAttestationRequest {
+    // Revoke a signing key and everything it signed
+    pub public_key: Option<String>,
+    // Revoke a single attestation
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeAttestationResponse {
+    pub success: bool,
+    pub revoked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterAutoApplyRequest {
+    // Must be owned by the server payer
+    pub token_account: String,
+    // Base58 keys the worker decrypts and re-encrypts the balance with; when
+    // both are omitted, taken from the key vault or derived from the payer
+    #[serde(default)]
+    pub elgamal_secret_key: Option<String>,
+    #[serde(default)]
+    pub aes_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterAutoApplyResponse {
+    pub success: bool,
+    pub token_account: String,
+    // Whether an existing registration was replaced
+    pub replaced: bool,
+    // Saved to the key vault; if false, the registration is lost on restart
+    pub persisted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnregisterAutoApplyRequest {
+    pub token_account: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnregisterAutoApplyResponse {
+    pub success: bool,
+    pub unregistered: bool,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Deserialize)]
+pub struct RegisterHistoryRequest {
+    // Token-2022 account with confidential transfers configured
+    pub token_account: String,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Serialize)]
+pub struct RegisterHistoryResponse {
+    pub success: bool,
+    pub token_account: String,
+    pub owner: String,
+    // Whether the account was already being indexed
+    pub already_registered: bool,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Deserialize)]
+pub struct UnregisterHistoryRequest {
+    pub token_account: String,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Serialize)]
+pub struct UnregisterHistoryResponse {
+    pub success: bool,
+    pub unregistered: bool,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Deserialize)]
+pub struct AccountHistoryRequest {
+    pub wallet_address: String,
+    pub token_account: String,
+    // Optional for custodial accounts, with the admin token or the owner's signature
+    #[serde(default)]
+    pub key_signatures: Option<KeySignatures>,
+    // `next_cursor` of the previous page
+    #[serde(default)]
+    pub before: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub signature: String,
+    pub slot: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<i64>,
+    pub instruction_index: usize,
+    // configure, deposit, apply_pending_balance, transfer_out, transfer_in or withdraw
+    pub kind: &'static str,
+    // The other token account of a transfer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    // Plaintext, decrypted or derived from consecutive balances; unknown for
+    // incoming transfers, whose amount arrives with the next apply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+    // Available balance after the instruction, when it changed it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_balance_after: Option<u64>,
+    // The same balance encrypted under the owner's AES key (base64)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decryptable_balance_after: Option<String>,
+}
+
+#[cfg(feature = "database")]
+#[derive(Debug, Serialize)]
+pub struct AccountHistoryResponse {
+    pub success: bool,
+    // Newest first
+    pub entries: Vec<HistoryEntry>,
+    // Pass as `before` for the next (older) page; absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyDerivationMessagesRequest {
+    pub token_account: String,
+    #[serde(default)]
+    pub epoch: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyDerivationMessagesResponse {
+    pub success: bool,
+    pub version: u8,
+    pub epoch: u32,
+    // Base64 messages for the owner's wallet to sign (`signMessage`)
+    pub elgamal_message: String,
+    pub aes_message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CustodialAccessMessageRequest {
+    pub token_account: String,
+    // Unix seconds the signature stays valid until; at most 300 seconds ahead
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustodialAccessMessageResponse {
+    pub success: bool,
+    // Base64 message for the owner's wallet to sign (`signMessage`); send the
+    // signature as `X-Owner-Signature` and `expires_at` as `X-Owner-Signature-Expires`
+    pub message: String,
+    pub expires_at: i64,
+}
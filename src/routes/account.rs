@@ -1,44 +1,81 @@
-use axum::{Json, http::StatusCode};
+use axum::{Json, extract::State, http::HeaderMap};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
 use solana_sdk::{
+    instruction::Instruction,
     pubkey::Pubkey,
-    signature::Keypair,
     signer::Signer,
     system_instruction,
     transaction::Transaction,
 };
 use spl_associated_token_account::get_associated_token_address_with_program_id;
 use spl_token_2022::{
-    extension::{ExtensionType, confidential_transfer::instruction::configure_account},
+    extension::{
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount, ConfidentialTransferMint,
+            account_info::EmptyAccountAccountInfo,
+            instruction::{
+                approve_account as approve_account_instruction, configure_account,
+                empty_account as empty_account_instruction,
+            },
+        },
+    },
     id as token_2022_program_id,
-    instruction::reallocate,
+    instruction::{close_account, reallocate},
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::AeKey,
+            elgamal::{ElGamalCiphertext, ElGamalKeypair},
+        },
+        zk_elgamal_proof_program::instruction::ProofInstruction,
+    },
+    state::{Account, Mint},
 };
 use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
-use std::str::FromStr;
 
 use crate::{
-    crypto::{generate_aes_key, generate_elgamal_keypair, generate_pubkey_validity_proof, generate_eligibility_proof},
+    crypto::{
+        EligibilityProof, decrypt_balance, decrypt_pending_balance, generate_eligibility_proof,
+        generate_empty_account_proof, generate_pubkey_validity_proof, verify_eligibility_proof,
+    },
+    error::ApiError,
     models::*,
-    solana::create_rpc_client,
+    routes::{
+        PreparedInstructions, authorize_custodial, derive_user_keys, fetch_account, fetch_account_with_slot,
+        parse_pubkey, require_admin,
+        tx::{encode_transactions, require_payer_owner, unsigned_transactions},
+        user_keys,
+    },
+    solana::{ProofContextGuard, create_proof_context, get_account_info},
+    state::AppState,
 };
 
 /// Create a confidential transfer enabled token account
+///
+/// Without `key_signatures` the account is custodial: the request needs the
+/// admin token or the owner's signature (see `authorize_custodial`), and keys
+/// are only generated for an account that does not exist yet.
 pub async fn create_confidential_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<CreateAccountRequest>,
-) -> Result<Json<CreateAccountResponse>, StatusCode> {
+) -> Result<Json<CreateAccountResponse>, ApiError> {
     tracing::info!("Creating CT account for wallet: {}", payload.wallet_address);
 
     // Parse addresses
-    let wallet_pubkey = Pubkey::from_str(&payload.wallet_address)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let mint_pubkey = Pubkey::from_str(&payload.mint_address)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let mint_pubkey = parse_pubkey("mint_address", &payload.mint_address)?;
 
     // Get RPC client
-    let client = create_rpc_client();
+    let client = &state.rpc;
 
-    // For demo: Load a payer keypair (in production, user signs transactions)
-    // This is just for funding the account creation
-    let payer = load_payer_keypair().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Fee payer loaded at startup (the owner still signs owner-authorized instructions)
+    let payer = &state.payer;
 
     // Get associated token account address
     let token_account = get_associated_token_address_with_program_id(
@@ -47,15 +84,83 @@ pub async fn create_confidential_account(
         &token_2022_program_id(),
     );
 
-    // Generate ElGamal and AES keys for the user
-    // In production, the user's wallet would do this client-side
-    // For this demo backend, we simulate it
-    let user_keypair = Keypair::new(); // Simulate user's keypair
-    let elgamal_keypair = generate_elgamal_keypair(&user_keypair, &token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let aes_key = generate_aes_key(&user_keypair, &token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Derive the user's ElGamal and AES keys from their wallet signatures, or
+    // generate fresh keys and keep them in the key vault for a custodial account
+    let (elgamal_keypair, aes_key) = match &payload.key_signatures {
+        Some(signatures) => derive_user_keys(&wallet_pubkey, &token_account, signatures)?,
+        None => {
+            authorize_custodial(&state, &headers, &wallet_pubkey, &token_account)?;
+            if get_account_info(client, &token_account)
+                .await
+                .map_err(ApiError::rpc)?
+                .is_some()
+            {
+                return Err(ApiError::invalid_account(
+                    token_account,
+                    "already exists; custodial keys are only generated for a new account",
+                ));
+            }
+            new_custodial_keys(&state, &wallet_pubkey, &token_account).await?
+        }
+    };
+
+    // Create the token account and configure it for confidential transfers
+    let instructions = confidential_account_instructions(
+        &payer.pubkey(),
+        &wallet_pubkey,
+        &mint_pubkey,
+        &token_account,
+        &elgamal_keypair,
+        &aes_key,
+        true,
+    )?;
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+
+    // Get recent blockhash
+    let recent_blockhash = client
+        .get_latest_blockhash()
+        .await
+        .map_err(ApiError::rpc)?;
+    transaction.partial_sign(&[payer.as_ref()], recent_blockhash);
+
+    // Non-custodial mode: the owner's wallet signs and submits via /api/tx/submit
+    if payload.mode == SubmissionMode::Unsigned {
+        return Ok(Json(CreateAccountResponse {
+            success: true,
+            token_account: token_account.to_string(),
+            signature: None,
+            transactions: encode_transactions(&[transaction])?,
+        }));
+    }
+
+    // Send transaction
+    let signature = client
+        .send_and_confirm_transaction(&transaction)
+        .await
+        .map_err(|e| ApiError::transaction(&transaction, e))?;
+
+    Ok(Json(CreateAccountResponse {
+        success: true,
+        token_account: token_account.to_string(),
+        signature: Some(signature.to_string()),
+        transactions: Vec::new(),
+    }))
+}
 
+/// Build the instructions that configure `token_account` for confidential
+/// transfers under the given keys, creating the associated token account
+/// first when `create_token_account` is set
+///
+/// The owner signs; the payer funds the account and the reallocation.
+pub(crate) fn confidential_account_instructions(
+    payer: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_account: &Pubkey,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    create_token_account: bool,
+) -> Result<Vec<Instruction>, ApiError> {
     // Maximum pending balance credit counter
     let maximum_pending_balance_credit_counter = 65536u64;
 
@@ -63,137 +168,691 @@ pub async fn create_confidential_account(
     let decryptable_balance = aes_key.encrypt(0);
 
     // Generate pubkey validity proof
-    let proof_data = generate_pubkey_validity_proof(&elgamal_keypair)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Create transaction
-    let mut transaction = Transaction::new_with_payer(
-        &[
-            // Create associated token account
-            spl_associated_token_account::instruction::create_associated_token_account(
-                &payer.pubkey(),
-                &wallet_pubkey,
-                &mint_pubkey,
-                &token_2022_program_id(),
-            ),
-            
-            // Reallocate for confidential transfer extension
-            reallocate(
-                &token_2022_program_id(),
-                &token_account,
-                &payer.pubkey(),
-                &wallet_pubkey,
-                &[&wallet_pubkey],
-                &[ExtensionType::ConfidentialTransferAccount],
-            )
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
-        ],
-        Some(&payer.pubkey()),
+    let proof_data = generate_pubkey_validity_proof(elgamal_keypair)
+        .map_err(|e| ApiError::ProofGeneration(e.to_string()))?;
+
+    let mut instructions = Vec::new();
+    if create_token_account {
+        // Create associated token account
+        instructions.push(spl_associated_token_account::instruction::create_associated_token_account(
+            payer,
+            wallet,
+            mint,
+            &token_2022_program_id(),
+        ));
+    }
+
+    // Reallocate for confidential transfer extension
+    instructions.push(
+        reallocate(
+            &token_2022_program_id(),
+            token_account,
+            payer,
+            wallet,
+            &[wallet],
+            &[ExtensionType::ConfidentialTransferAccount],
+        )
+        .map_err(|e| ApiError::internal("Failed to create reallocate instruction", e))?,
     );
 
-    // Configure account for confidential transfers
+    // Configure account for confidential transfers; the proof is verified by
+    // the instruction right after it
     let proof_location = ProofLocation::InstructionOffset(
         1.try_into().unwrap(),
         spl_token_confidential_transfer_proof_extraction::instruction::ProofData::InstructionData(&proof_data),
     );
 
-    let configure_instructions = configure_account(
+    instructions.extend(
+        configure_account(
+            &token_2022_program_id(),
+            token_account,
+            mint,
+            &decryptable_balance.into(),
+            maximum_pending_balance_credit_counter,
+            wallet,
+            &[],
+            proof_location,
+        )
+        .map_err(|e| ApiError::internal("Failed to create configure account instruction", e))?,
+    );
+
+    Ok(instructions)
+}
+
+/// Generate keys for a new custodial account and store them in the key vault
+///
+/// Stored keys are never replaced: if an earlier attempt stored keys for the
+/// account but its transaction did not land, those keys are used again.
+#[cfg(feature = "database")]
+pub(crate) async fn new_custodial_keys(
+    state: &AppState,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+    let vault = state.vault.as_ref().ok_or_else(|| {
+        ApiError::invalid_input("key_signatures", "required: no key vault is configured")
+    })?;
+
+    let elgamal_keypair = ElGamalKeypair::new_rand();
+    let aes_key = AeKey::new_rand();
+    // Stored before the account is configured, so the keys are never lost
+    if vault.insert(token_account, wallet, &elgamal_keypair, &aes_key).await? {
+        tracing::info!("Stored custodial keys for {}", token_account);
+        return Ok((elgamal_keypair, aes_key));
+    }
+
+    if vault.owner(token_account).await? != Some(*wallet) {
+        return Err(ApiError::invalid_account(*token_account, "keys are stored for another owner"));
+    }
+    tracing::info!("Reusing stored custodial keys for {}", token_account);
+    vault
+        .load(token_account)
+        .await?
+        .ok_or_else(|| ApiError::internal("Stored custodial keys were removed", token_account))
+}
+
+#[cfg(not(feature = "database"))]
+pub(crate) async fn new_custodial_keys(
+    _state: &AppState,
+    _wallet: &Pubkey,
+    _token_account: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+    Err(ApiError::invalid_input(
+        "key_signatures",
+        "required: the key vault needs the `database` feature",
+    ))
+}
+
+/// Delete the stored keys of a closed custodial account
+///
+/// The account is already closed, so a failure is logged rather than returned.
+#[cfg(feature = "database")]
+async fn remove_custodial_keys(state: &AppState, token_account: &Pubkey) {
+    let Some(vault) = &state.vault else {
+        return;
+    };
+    match vault.remove(token_account).await {
+        Ok(true) => tracing::info!("Removed custodial keys of closed account {}", token_account),
+        Ok(false) => {}
+        Err(e) => tracing::error!("Failed to remove custodial keys of {}: {}", token_account, e),
+    }
+}
+
+#[cfg(not(feature = "database"))]
+async fn remove_custodial_keys(_state: &AppState, _token_account: &Pubkey) {}
+
+/// Get balance of a confidential transfer account
+///
+/// Decrypts the available balance with the owner's AES key and the pending
+/// lo/hi balances with the owner's ElGamal secret key.
+pub async fn get_balance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<GetBalanceRequest>,
+) -> Result<Json<GetBalanceResponse>, ApiError> {
+    tracing::info!("Getting balance for token account: {}", payload.token_account);
+
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    let client = &state.rpc;
+
+    // Get account data
+    let account_data = fetch_account(client, &token_account).await?;
+
+    // Parse the ConfidentialTransferAccount extension
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    let ct_extension = token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
+
+    // Load the owner's ElGamal and AES keys (wallet signatures or key vault)
+    let (elgamal_keypair, aes_key) =
+        user_keys(&state, &headers, &wallet_pubkey, &token_account, payload.key_signatures.as_ref()).await?;
+
+    // Decrypt the available balance
+    let available_balance = decrypt_balance(&aes_key, &ct_extension.decryptable_available_balance)?;
+
+    // Decrypt the pending balance
+    let pending_balance = decrypt_pending_balance(
+        elgamal_keypair.secret(),
+        &ct_extension.pending_balance_lo,
+        &ct_extension.pending_balance_hi,
+    )?;
+
+    Ok(Json(GetBalanceResponse {
+        success: true,
+        available_balance,
+        pending_balance,
+        decrypted_available: Some(available_balance),
+        pending_balance_credit_counter: ct_extension.pending_balance_credit_counter.into(),
+        approved: ct_extension.approved.into(),
+        allow_confidential_credits: ct_extension.allow_confidential_credits.into(),
+        allow_non_confidential_credits: ct_extension.allow_non_confidential_credits.into(),
+        available_balance_ciphertext: ct_extension.available_balance.to_string(),
+        decryptable_available_balance: ct_extension.decryptable_available_balance.to_string(),
+        pending_balance_lo: ct_extension.pending_balance_lo.to_string(),
+        pending_balance_hi: ct_extension.pending_balance_hi.to_string(),
+    }))
+}
+
+/// Approve a configured confidential account (admin)
+///
+/// Mints with `auto_approve_new_accounts = false` require the mint's
+/// confidential-transfer authority to approve each account before it can be
+/// used. If that authority is not the server payer, use `mode: "unsigned"`
+/// and have the authority sign the returned transaction.
+pub async fn approve_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ApproveAccountRequest>,
+) -> Result<Json<ApproveAccountResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+    tracing::info!("Approving CT account: {}", payload.token_account);
+
+    // 1. Parse and validate inputs
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    let client = &state.rpc;
+    let payer = &state.payer;
+
+    // 2. Check the account is configured and still pending
+    let account_data = fetch_account(client, &token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    let ct_extension = token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if bool::from(ct_extension.approved) {
+        return Err(ApiError::invalid_account(token_account, "account is already approved"));
+    }
+
+    // 3. Read the mint's confidential-transfer authority
+    let mint_pubkey = token_account_data.base.mint;
+    let mint_data = fetch_account(client, &mint_pubkey).await?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data.data)
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "not a Token-2022 mint"))?;
+    let ct_mint = mint
+        .get_extension::<ConfidentialTransferMint>()
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "confidential transfers not enabled on mint"))?;
+    let authority = Option::<Pubkey>::from(ct_mint.authority)
+        .ok_or_else(|| ApiError::invalid_account(mint_pubkey, "mint has no confidential transfer authority"))?;
+
+    if payload.mode == SubmissionMode::Submit && authority != payer.pubkey() {
+        return Err(ApiError::invalid_input(
+            "mode",
+            format!("mint authority {} must sign; use unsigned mode", authority),
+        ));
+    }
+
+    // 4. Build the approve transaction
+    let approve_ix = approve_account_instruction(
         &token_2022_program_id(),
         &token_account,
         &mint_pubkey,
-        &decryptable_balance.into(),
-        maximum_pending_balance_credit_counter,
-        &wallet_pubkey,
+        &authority,
         &[],
-        proof_location,
     )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    transaction.message.instructions.extend(configure_instructions);
+    .map_err(|e| ApiError::internal("Failed to create approve account instruction", e))?;
 
-    // Get recent blockhash
+    let mut transaction = Transaction::new_with_payer(&[approve_ix], Some(&payer.pubkey()));
     let recent_blockhash = client
         .get_latest_blockhash()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    transaction.sign(&[&payer], recent_blockhash);
+        .map_err(ApiError::rpc)?;
+    transaction.partial_sign(&[payer.as_ref()], recent_blockhash);
+
+    // Non-custodial mode: the mint authority signs and submits via /api/tx/submit
+    if payload.mode == SubmissionMode::Unsigned {
+        return Ok(Json(ApproveAccountResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&[transaction])?,
+        }));
+    }
 
-    // Send transaction
     let signature = client
         .send_and_confirm_transaction(&transaction)
         .await
-        .map_err(|e| {
-            tracing::error!("Transaction failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(|e| ApiError::transaction(&transaction, e))?;
 
-    Ok(Json(CreateAccountResponse {
+    tracing::info!("CT account {} approved: {}", token_account, signature);
+
+    Ok(Json(ApproveAccountResponse {
         success: true,
-        token_account: token_account.to_string(),
-        signature: signature.to_string(),
-        error: None,
+        signature: Some(signature.to_string()),
+        transactions: Vec::new(),
     }))
 }
 
-/// Get balance of a confidential transfer account
-pub async fn get_balance(
-    Json(payload): Json<GetBalanceRequest>,
-) -> Result<Json<GetBalanceResponse>, StatusCode> {
-    tracing::info!("Getting balance for token account: {}", payload.token_account);
+/// List configured-but-unapproved confidential accounts for a mint (admin)
+pub async fn list_pending_accounts(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ListPendingAccountsRequest>,
+) -> Result<Json<ListPendingAccountsResponse>, ApiError> {
+    require_admin(&state, &headers)?;
 
-    let token_account = Pubkey::from_str(&payload.token_account)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mint_pubkey = parse_pubkey("mint_address", &payload.mint_address)?;
 
-    let client = create_rpc_client();
+    // Token accounts store their mint in the first 32 bytes
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            0,
+            &mint_pubkey.to_bytes(),
+        ))]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
 
-    // Get account data
-    let account_data = client
-        .get_account(&token_account)
+    let accounts = state
+        .rpc
+        .get_program_accounts_with_config(&token_2022_program_id(), config)
         .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+        .map_err(ApiError::rpc)?;
 
-    // Parse token account
-    // In production, parse the ConfidentialTransferAccount extension
-    // and decrypt the available/pending balances
-    
-    // For now, return placeholder
-    Ok(Json(GetBalanceResponse {
+    let pending_accounts = accounts
+        .iter()
+        .filter_map(|(pubkey, account)| {
+            let token_account = StateWithExtensions::<Account>::unpack(&account.data).ok()?;
+            let ct_extension = token_account.get_extension::<ConfidentialTransferAccount>().ok()?;
+            (!bool::from(ct_extension.approved)).then(|| PendingAccount {
+                token_account: pubkey.to_string(),
+                owner: token_account.base.owner.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(Json(ListPendingAccountsResponse {
+        success: true,
+        mint: mint_pubkey.to_string(),
+        pending_accounts,
+    }))
+}
+
+/// Empty a confidential account and optionally close it
+///
+/// The program only lets an account leave confidential mode once its
+/// available balance is provably zero, so this verifies a zero-ciphertext
+/// proof into a context account, submits `empty_account`, and (with
+/// `close_account`) closes the token account to return its rent to the owner.
+/// Withdraw the remaining confidential balance and apply any pending balance first.
+///
+/// Closing a custodial account in submit mode deletes its keys from the key
+/// vault once confirmed; in unsigned mode the server cannot tell whether the
+/// close landed, so the keys are kept.
+pub async fn empty_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<EmptyAccountRequest>,
+) -> Result<Json<EmptyAccountResponse>, ApiError> {
+    tracing::info!("Emptying CT account: {}", payload.token_account);
+
+    // 1. Parse and validate inputs
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    let client = &state.rpc;
+    let payer = &state.payer;
+    let payer_pubkey = payer.pubkey();
+    require_payer_owner(payload.mode, &wallet_pubkey, &payer_pubkey)?;
+
+    // 2. Read the account and check it can be emptied
+    let account_data = fetch_account(client, &token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    let ct_extension = token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
+
+    // Credits since the last apply are still encrypted in the pending balance
+    let pending_credits = u64::from(ct_extension.pending_balance_credit_counter);
+    if pending_credits > 0 {
+        return Err(ApiError::invalid_account(
+            token_account,
+            format!(
+                "{} pending credit(s) not yet applied; call /api/apply and withdraw before emptying",
+                pending_credits
+            ),
+        ));
+    }
+
+    if payload.close_account && token_account_data.base.amount > 0 {
+        return Err(ApiError::invalid_input(
+            "close_account",
+            format!(
+                "account still holds a public balance of {}",
+                token_account_data.base.amount
+            ),
+        ));
+    }
+
+    // 3. Load the owner's ElGamal and AES keys (wallet signatures or key vault)
+    let (elgamal_keypair, aes_key) =
+        user_keys(&state, &headers, &wallet_pubkey, &token_account, payload.key_signatures.as_ref()).await?;
+
+    let available_balance = decrypt_balance(&aes_key, &ct_extension.decryptable_available_balance)?;
+    if available_balance > 0 {
+        return Err(ApiError::invalid_account(
+            token_account,
+            format!(
+                "confidential available balance is {}; withdraw it before emptying",
+                available_balance
+            ),
+        ));
+    }
+
+    // 4. Prove the available balance is zero and verify it into a context
+    // account, then build empty_account (and close_account), signed by the owner
+    let PreparedInstructions {
+        proof_contexts,
+        mut instructions,
+    } = prepare_empty_account(client, &payer_pubkey, &token_account, &wallet_pubkey, ct_extension, &elgamal_keypair)
+        .await?;
+
+    if payload.close_account {
+        instructions.push(
+            close_account(
+                &token_2022_program_id(),
+                &token_account,
+                &wallet_pubkey,
+                &wallet_pubkey,
+                &[],
+            )
+            .map_err(|e| ApiError::internal("Failed to create close account instruction", e))?,
+        );
+    }
+
+    let recent_blockhash = client
+        .get_latest_blockhash()
+        .await
+        .map_err(ApiError::rpc)?;
+
+    let mut empty_tx = Transaction::new_with_payer(&instructions, Some(&payer_pubkey));
+    empty_tx.partial_sign(&[payer.as_ref()], recent_blockhash);
+
+    // Non-custodial mode: the owner's wallet signs everything and submits it
+    // in order via /api/tx/submit, paying for the proof context account; the
+    // server closes it afterwards and returns the rent
+    if payload.mode == SubmissionMode::Unsigned {
+        let transactions =
+            unsigned_transactions(&state, &wallet_pubkey, &proof_contexts, empty_tx, recent_blockhash);
+
+        return Ok(Json(EmptyAccountResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&transactions)?,
+            // The close instruction is in the empty transaction
+            closed: payload.close_account,
+            unclosed_proof_accounts: Vec::new(),
+        }));
+    }
+
+    let proof_txs: Vec<Vec<Transaction>> = proof_contexts
+        .iter()
+        .map(|context| context.transactions(payer, recent_blockhash))
+        .collect();
+
+    // 5. Verify the proof and empty the account; the guard closes the proof
+    // account afterwards even if the empty transaction fails
+    let mut proof_guard = ProofContextGuard::new(client, payer.as_ref());
+    let result = async {
+        for (context, context_txs) in proof_contexts.iter().zip(&proof_txs) {
+            proof_guard.track(context);
+            for proof_tx in context_txs {
+                client
+                    .send_and_confirm_transaction(proof_tx)
+                    .await
+                    .map_err(|e| ApiError::transaction(proof_tx, e))?;
+            }
+            tracing::info!("{} proof account created: {}", context.label, context.pubkey());
+        }
+
+        client
+            .send_and_confirm_transaction(&empty_tx)
+            .await
+            .map_err(|e| ApiError::transaction(&empty_tx, e))
+    }
+    .await;
+
+    let unclosed_proof_accounts = proof_guard.close_all().await;
+    let signature = result?;
+
+    tracing::info!(
+        "CT account {} emptied{}: {}",
+        token_account,
+        if payload.close_account { " and closed" } else { "" },
+        signature
+    );
+
+    // The keys of a closed custodial account protect nothing any more
+    if payload.close_account && payload.key_signatures.is_none() {
+        remove_custodial_keys(&state, &token_account).await;
+    }
+
+    Ok(Json(EmptyAccountResponse {
         success: true,
-        available_balance: 0,
-        pending_balance: 0,
-        decrypted_available: Some(0),
-        error: None,
+        signature: Some(signature.to_string()),
+        transactions: Vec::new(),
+        closed: payload.close_account,
+        unclosed_proof_accounts: unclosed_proof_accounts.iter().map(ToString::to_string).collect(),
     }))
 }
 
-/// Generate eligibility proof
+/// Prove a confidential account's available balance is zero, build the proof
+/// context account and the owner-signed `empty_account` instruction
+pub(crate) async fn prepare_empty_account(
+    client: &RpcClient,
+    payer: &Pubkey,
+    token_account: &Pubkey,
+    owner: &Pubkey,
+    ct_extension: &ConfidentialTransferAccount,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<PreparedInstructions, ApiError> {
+    let empty_account_info = EmptyAccountAccountInfo::new(ct_extension);
+    let proof_data = generate_empty_account_proof(&empty_account_info, elgamal_keypair)
+        .map_err(|e| ApiError::ProofGeneration(e.to_string()))?;
+
+    let zero_balance = create_proof_context(
+        client,
+        payer,
+        "zero balance",
+        ProofInstruction::VerifyZeroCiphertext,
+        &proof_data,
+    )
+    .await
+    .map_err(ApiError::rpc)?;
+
+    let instructions = empty_account_instruction(
+        &token_2022_program_id(),
+        token_account,
+        owner,
+        &[],
+        ProofLocation::ContextStateAccount(&zero_balance.pubkey()),
+    )
+    .map_err(|e| ApiError::internal("Failed to create empty account instruction", e))?;
+
+    Ok(PreparedInstructions {
+        proof_contexts: vec![zero_balance],
+        instructions,
+    })
+}
+
+/// Prove that the account's available balance ciphertext encrypts at least
+/// `threshold`, verifying the proof before it is returned or attested
+///
+/// `available_balance` is decrypted from the decryptable available balance,
+/// which the owner writes in their own instructions and the program never
+/// checks against the ciphertext. A wrong value gives a proof that fails
+/// verification, reported as an out-of-date decryptable balance.
+fn checked_eligibility_proof(
+    token_account: &Pubkey,
+    ct_extension: &ConfidentialTransferAccount,
+    available_balance: u64,
+    threshold: u64,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<EligibilityProof, ApiError> {
+    let proof = generate_eligibility_proof(
+        &ct_extension.available_balance,
+        available_balance,
+        threshold,
+        elgamal_keypair,
+    )
+    .map_err(|e| ApiError::ProofGeneration(e.to_string()))?;
+
+    let available_balance_ciphertext = ElGamalCiphertext::try_from(ct_extension.available_balance)
+        .map_err(|_| ApiError::invalid_account(*token_account, "malformed available balance ciphertext"))?;
+    verify_eligibility_proof(
+        &proof,
+        &ct_extension.elgamal_pubkey,
+        &available_balance_ciphertext,
+        threshold,
+    )
+    .map_err(|e| {
+        tracing::warn!("Eligibility proof for {} failed verification: {}", token_account, e);
+        ApiError::invalid_account(
+            *token_account,
+            "decryptable available balance is out of date with the available balance ciphertext",
+        )
+    })?;
+
+    Ok(proof)
+}
+
+/// Generate a zero-knowledge proof that the account's confidential available
+/// balance is at least `threshold`
+///
+/// The proof is built over the on-chain available balance ciphertext read at
+/// the returned slot and does not reveal the balance; see `EligibilityProof`
+/// for the serialized format. With `attest`, an eligible result also carries a
+/// signed, expiring attestation (see `AttestationStatement`). The proof is
+/// verified first, so an out-of-date decryptable balance is rejected rather
+/// than attested.
 pub async fn generate_proof(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<GenerateProofRequest>,
-) -> Result<Json<GenerateProofResponse>, StatusCode> {
-    tracing::info!("Generating proof for wallet: {}", payload.wallet_address);
+) -> Result<Json<GenerateProofResponse>, ApiError> {
+    tracing::info!(
+        "Generating eligibility proof for {} (threshold {})",
+        payload.token_account,
+        payload.threshold
+    );
+
+    // 1. Parse and validate inputs
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    // 2. Read the available balance ciphertext and the slot it was read at
+    let (account_data, slot) = fetch_account_with_slot(&state.rpc, &token_account, None).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    let ct_extension = token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
+
+    // 3. Load the owner's ElGamal and AES keys (wallet signatures or key vault)
+    let (elgamal_keypair, aes_key) =
+        user_keys(&state, &headers, &wallet_pubkey, &token_account, payload.key_signatures.as_ref()).await?;
+
+    // 4. Prove available balance - threshold >= 0 over the on-chain ciphertext
+    let available_balance = decrypt_balance(&aes_key, &ct_extension.decryptable_available_balance)?;
+    let eligible = available_balance >= payload.threshold;
 
-    // In production, get actual balance and generate real ZK proof
-    // For demo, use simplified proof
-    let available_balance = 100u64; // Placeholder
+    let proof = if eligible {
+        let proof = checked_eligibility_proof(
+            &token_account,
+            ct_extension,
+            available_balance,
+            payload.threshold,
+            &elgamal_keypair,
+        )?;
+        Some(proof.to_base64())
+    } else {
+        None
+    };
 
-    let (eligible, proof) = generate_eligibility_proof(available_balance, payload.threshold)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 5. Sign an expiring attestation of the result, if requested; only a
+    // verified proof gets this far
+    let attestation = (payload.attest && proof.is_some()).then(|| {
+        state.attestations.issue(
+            &wallet_pubkey,
+            &token_account,
+            &token_account_data.base.mint,
+            payload.threshold,
+            slot,
+        )
+    });
 
     Ok(Json(GenerateProofResponse {
         success: true,
-        proof,
-        public_inputs: vec![
-            payload.wallet_address,
-            payload.threshold.to_string(),
-        ],
         eligible,
-        error: None,
+        proof,
+        token_account: token_account.to_string(),
+        threshold: payload.threshold,
+        slot,
+        attestation,
     }))
 }
 
-// Helper function to load payer keypair
-fn load_payer_keypair() -> anyhow::Result<Keypair> {
-    // In production, load from secure key storage
-    // For demo, generate a new one or load from env
-    Ok(Keypair::new())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+    use spl_token_2022::solana_zk_sdk::encryption::pod::elgamal::{PodElGamalCiphertext, PodElGamalPubkey};
+
+    fn confidential_account(elgamal_keypair: &ElGamalKeypair, available_balance: u64) -> ConfidentialTransferAccount {
+        let mut extension = ConfidentialTransferAccount::zeroed();
+        extension.elgamal_pubkey = PodElGamalPubkey::from(*elgamal_keypair.pubkey());
+        extension.available_balance = PodElGamalCiphertext::from(elgamal_keypair.pubkey().encrypt(available_balance));
+        extension
+    }
+
+    #[test]
+    fn test_checked_eligibility_proof() {
+        let elgamal_keypair = ElGamalKeypair::new_rand();
+        let extension = confidential_account(&elgamal_keypair, 100);
+
+        assert!(checked_eligibility_proof(&Pubkey::new_unique(), &extension, 100, 50, &elgamal_keypair).is_ok());
+    }
+
+    #[test]
+    fn test_stale_decryptable_balance_is_not_attested() {
+        // The owner wrote a decryptable balance of 1000 over a ciphertext of 10
+        let elgamal_keypair = ElGamalKeypair::new_rand();
+        let extension = confidential_account(&elgamal_keypair, 10);
+        let token_account = Pubkey::new_unique();
+
+        let result = checked_eligibility_proof(&token_account, &extension, 1000, 50, &elgamal_keypair);
+        assert!(matches!(
+            result,
+            Err(ApiError::InvalidAccount { account, .. }) if account == token_account
+        ));
+    }
+}
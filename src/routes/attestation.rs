@@ -0,0 +1,63 @@
+use axum::{Json, extract::State, http::HeaderMap};
+
+use crate::{
+    error::ApiError,
+    models::*,
+    routes::{parse_pubkey, require_admin},
+    state::AppState,
+};
+
+/// Publish the attestation verification keys and revoked attestations
+///
+/// Offline verifiers should refresh this list periodically: attestations
+/// signed by a `revoked` key, or whose nonce is listed, must be rejected.
+pub async fn attestation_keys(State(state): State<AppState>) -> Json<AttestationKeysResponse> {
+    Json(AttestationKeysResponse {
+        success: true,
+        keys: state.attestations.keys(),
+        revoked_attestations: state.attestations.revoked_attestations(),
+    })
+}
+
+/// Rotate to a new attestation signing key (admin)
+pub async fn rotate_attestation_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<RotateAttestationKeyResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    let public_key = state.attestations.rotate().await;
+
+    Ok(Json(RotateAttestationKeyResponse {
+        success: true,
+        public_key: public_key.to_string(),
+    }))
+}
+
+/// Revoke a signing key or a single attestation (admin)
+pub async fn revoke_attestation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<RevokeAttestationRequest>,
+) -> Result<Json<RevokeAttestationResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    let revoked = match (&payload.public_key, &payload.nonce) {
+        (Some(public_key), None) => {
+            let public_key = parse_pubkey("public_key", public_key)?;
+            state.attestations.revoke_key(&public_key).await
+        }
+        (None, Some(nonce)) => state.attestations.revoke_attestation(nonce).await,
+        _ => {
+            return Err(ApiError::invalid_input(
+                "public_key",
+                "provide exactly one of public_key or nonce",
+            ));
+        }
+    };
+
+    Ok(Json(RevokeAttestationResponse {
+        success: true,
+        revoked,
+    }))
+}
@@ -0,0 +1,236 @@
+use axum::{Json, extract::State};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, StateWithExtensions, confidential_transfer::ConfidentialTransferMint},
+    id as token_2022_program_id,
+    solana_zk_sdk::encryption::{elgamal::ElGamalPubkey, pod::elgamal::PodElGamalPubkey},
+    state::Mint,
+};
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    crypto::{BalanceDecryptionError, decrypt_transfer_amount, parse_auditor_ciphertexts},
+    error::ApiError,
+    models::*,
+    routes::{fetch_account, parse_elgamal_secret_key, parse_pubkey},
+    solana::{SIGNATURES_PAGE_LIMIT, get_confirmed_transaction, get_signatures_in_slot_range},
+    state::AppState,
+};
+
+/// Most transactions a single slot-range audit reads
+const MAX_AUDIT_TRANSACTIONS: usize = 500;
+/// Most signature pages a slot-range audit reads while looking for the range
+const MAX_AUDIT_SIGNATURE_PAGES: usize = 10;
+
+/// Decrypt confidential transfer amounts with a mint's auditor key
+///
+/// Every confidential `Transfer` / `TransferWithFee` carries the amount
+/// encrypted under the mint's auditor ElGamal pubkey, as a 16-bit lo and a
+/// 32-bit hi ciphertext. This reads them from the top-level instructions of
+/// one transaction (`signature`), or of every transaction that touched `mint`
+/// within `start_slot..=end_slot`, decrypts both parts and recombines them.
+///
+/// The key must be the auditor key currently configured on the mint; a slot
+/// range audit of another mint is rejected, and transfers of other mints are
+/// listed with an `error` without being decrypted. Transfers made before the
+/// mint's auditor key was changed are listed with an `error` as well. Failed
+/// transactions are skipped.
+pub async fn audit_transfers(
+    State(state): State<AppState>,
+    Json(payload): Json<AuditTransfersRequest>,
+) -> Result<Json<AuditTransfersResponse>, ApiError> {
+    // 1. Parse and validate inputs (the secret key is never logged)
+    let auditor_secret_key =
+        parse_elgamal_secret_key("auditor_elgamal_secret_key", &payload.auditor_elgamal_secret_key)?;
+    let auditor_pubkey = PodElGamalPubkey::from(ElGamalPubkey::new(&auditor_secret_key));
+
+    let client = &state.rpc;
+
+    // 2. Find the transactions to audit
+    let (signatures, mint_filter) = match (&payload.signature, &payload.mint) {
+        (Some(signature), None) => {
+            tracing::info!("Auditing confidential transfers of transaction {}", signature);
+            let signature =
+                Signature::from_str(signature).map_err(|e| ApiError::invalid_input("signature", e))?;
+            (vec![signature], None)
+        }
+        (None, Some(mint)) => {
+            let mint = parse_pubkey("mint", mint)?;
+            let (start_slot, end_slot) = match (payload.start_slot, payload.end_slot) {
+                (Some(start_slot), Some(end_slot)) if start_slot <= end_slot => (start_slot, end_slot),
+                (Some(_), Some(_)) => {
+                    return Err(ApiError::invalid_input("end_slot", "must not be before start_slot"));
+                }
+                _ => {
+                    return Err(ApiError::invalid_input(
+                        "start_slot",
+                        "start_slot and end_slot are required with mint",
+                    ));
+                }
+            };
+            tracing::info!(
+                "Auditing confidential transfers of mint {} in slots {}..={}",
+                mint,
+                start_slot,
+                end_slot
+            );
+
+            if mint_auditor_pubkey(client, &mint).await? != Some(auditor_pubkey) {
+                return Err(ApiError::invalid_input(
+                    "auditor_elgamal_secret_key",
+                    format!("not the auditor key of mint {}", mint),
+                ));
+            }
+
+            let signatures = get_signatures_in_slot_range(
+                client,
+                &mint,
+                start_slot,
+                end_slot,
+                MAX_AUDIT_TRANSACTIONS + 1,
+                MAX_AUDIT_SIGNATURE_PAGES,
+            )
+            .await
+            .map_err(ApiError::rpc)?
+            .ok_or_else(|| {
+                ApiError::invalid_input(
+                    "start_slot",
+                    format!(
+                        "more than {} transactions since start_slot; audit a more recent range",
+                        MAX_AUDIT_SIGNATURE_PAGES * SIGNATURES_PAGE_LIMIT
+                    ),
+                )
+            })?;
+            if signatures.len() > MAX_AUDIT_TRANSACTIONS {
+                return Err(ApiError::invalid_input(
+                    "end_slot",
+                    format!(
+                        "more than {} transactions in the slot range; narrow it",
+                        MAX_AUDIT_TRANSACTIONS
+                    ),
+                ));
+            }
+
+            (signatures.into_iter().map(|(signature, _)| signature).collect(), Some(mint))
+        }
+        _ => {
+            return Err(ApiError::invalid_input(
+                "signature",
+                "provide either signature, or mint with start_slot and end_slot",
+            ));
+        }
+    };
+
+    // 3. Fetch each transaction and collect its confidential transfers
+    let mut found = Vec::new();
+    for signature in &signatures {
+        let transaction = get_confirmed_transaction(client, signature)
+            .await
+            .map_err(ApiError::rpc)?
+            .ok_or_else(|| ApiError::invalid_input("signature", format!("transaction {} not found", signature)))?;
+
+        if transaction.failed {
+            tracing::debug!("Skipping failed transaction {}", signature);
+            continue;
+        }
+
+        for (instruction_index, instruction) in transaction.instructions.iter().enumerate() {
+            if instruction.program_id != token_2022_program_id() {
+                continue;
+            }
+            let Some(ciphertexts) = parse_auditor_ciphertexts(&instruction.data) else {
+                continue;
+            };
+            // Transfer accounts start with source, mint, destination
+            let [source, mint, destination, ..] = instruction.accounts[..] else {
+                continue;
+            };
+            if mint_filter.is_some_and(|filter| filter != mint) {
+                continue;
+            }
+
+            found.push((
+                AuditedTransfer {
+                    signature: signature.to_string(),
+                    slot: transaction.slot,
+                    block_time: transaction.block_time,
+                    instruction_index,
+                    source_token_account: source.to_string(),
+                    mint: mint.to_string(),
+                    destination_token_account: destination.to_string(),
+                    with_fee: ciphertexts.with_fee,
+                    amount: None,
+                    auditor_ciphertext_lo: ciphertexts.ciphertext_lo.to_string(),
+                    auditor_ciphertext_hi: ciphertexts.ciphertext_hi.to_string(),
+                    error: None,
+                },
+                ciphertexts,
+                mint,
+            ));
+        }
+    }
+
+    // 4. Check the key against each mint's auditor key: under a wrong key,
+    // every transfer would cost two failed discrete-log searches
+    let mut auditor_mints: HashMap<Pubkey, bool> = mint_filter.map(|mint| (mint, true)).into_iter().collect();
+    for (_, _, mint) in &found {
+        if auditor_mints.contains_key(mint) {
+            continue;
+        }
+        let is_auditor = match mint_auditor_pubkey(client, mint).await {
+            Ok(pubkey) => pubkey == Some(auditor_pubkey),
+            // Closed mints
+            Err(ApiError::AccountNotFound(_)) => false,
+            Err(e) => return Err(e),
+        };
+        auditor_mints.insert(*mint, is_auditor);
+    }
+
+    // 5. Decrypt off the async runtime: each amount takes two discrete logs
+    let transfers = tokio::task::spawn_blocking(move || {
+        found
+            .into_iter()
+            .map(|(mut transfer, ciphertexts, mint)| {
+                if !auditor_mints[&mint] {
+                    transfer.error = Some("not the mint's auditor key".to_string());
+                    return transfer;
+                }
+                match decrypt_transfer_amount(&auditor_secret_key, &ciphertexts) {
+                    Ok(amount) => transfer.amount = Some(amount),
+                    Err(BalanceDecryptionError::DiscreteLogFailed) => {
+                        transfer.error = Some("not encrypted under this auditor key".to_string());
+                    }
+                    Err(e) => transfer.error = Some(e.to_string()),
+                }
+                transfer
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| ApiError::internal("Auditor decryption task failed", e))?;
+
+    tracing::info!(
+        "Audited {} confidential transfer(s) in {} transaction(s)",
+        transfers.len(),
+        signatures.len()
+    );
+
+    Ok(Json(AuditTransfersResponse {
+        success: true,
+        transactions_scanned: signatures.len(),
+        transfers,
+    }))
+}
+
+/// The auditor ElGamal pubkey configured on a mint, if any
+async fn mint_auditor_pubkey(client: &RpcClient, mint_pubkey: &Pubkey) -> Result<Option<PodElGamalPubkey>, ApiError> {
+    let mint_data = fetch_account(client, mint_pubkey).await?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data.data)
+        .map_err(|_| ApiError::invalid_account(*mint_pubkey, "not a Token-2022 mint"))?;
+    let ct_mint = mint
+        .get_extension::<ConfidentialTransferMint>()
+        .map_err(|_| ApiError::invalid_account(*mint_pubkey, "confidential transfers not enabled on mint"))?;
+
+    Ok(Option::<PodElGamalPubkey>::from(ct_mint.auditor_elgamal_pubkey))
+}
@@ -0,0 +1,120 @@
+use axum::{Json, extract::State, http::HeaderMap};
+use solana_sdk::signer::Signer;
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, StateWithExtensions, confidential_transfer::ConfidentialTransferAccount},
+    solana_zk_sdk::encryption::{elgamal::ElGamalKeypair, pod::elgamal::PodElGamalPubkey},
+    state::Account,
+};
+
+use crate::{
+    auto_apply,
+    crypto::{decrypt_balance, generate_aes_key, generate_elgamal_keypair},
+    error::ApiError,
+    models::*,
+    routes::{
+        fetch_account, parse_aes_key, parse_elgamal_secret_key, parse_pubkey, require_admin, stored_keys,
+    },
+    state::AppState,
+};
+
+/// Register an account for background apply of its pending balance (admin)
+///
+/// The worker signs as the account owner, so only accounts owned by the
+/// server payer qualify. Keys default to the ones in the key vault, then to
+/// the ones derived from the payer's own signatures over the derivation
+/// messages. They are checked against the
+/// account before they are accepted: the ElGamal key must match the
+/// configured pubkey and the AES key must decrypt the available balance.
+///
+/// Registrations are saved to the key vault and restored at startup; without
+/// one they are lost on restart, which the response reports as `persisted: false`.
+pub async fn register_auto_apply(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<RegisterAutoApplyRequest>,
+) -> Result<Json<RegisterAutoApplyResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    if state.config.auto_apply.is_none() {
+        return Err(ApiError::invalid_input(
+            "token_account",
+            "auto-apply is disabled on this server (set AUTO_APPLY_ENABLED)",
+        ));
+    }
+
+    // 1. Parse and validate inputs
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let (elgamal_keypair, aes_key) = match (&payload.elgamal_secret_key, &payload.aes_key) {
+        (Some(elgamal_secret_key), Some(aes_key)) => (
+            ElGamalKeypair::new(parse_elgamal_secret_key("elgamal_secret_key", elgamal_secret_key)?),
+            parse_aes_key("aes_key", aes_key)?,
+        ),
+        // Keys from the vault, or else the payer owns the account and can
+        // sign the derivation messages itself
+        (None, None) => match stored_keys(&state, &token_account).await? {
+            Some(keys) => keys,
+            None => (
+                generate_elgamal_keypair(&state.payer, &token_account)
+                    .map_err(|e| ApiError::internal("Failed to derive encryption keys", e))?,
+                generate_aes_key(&state.payer, &token_account)
+                    .map_err(|e| ApiError::internal("Failed to derive encryption keys", e))?,
+            ),
+        },
+        _ => {
+            return Err(ApiError::invalid_input(
+                "elgamal_secret_key",
+                "provide both elgamal_secret_key and aes_key, or neither",
+            ));
+        }
+    };
+
+    // 2. Check the account is payer-owned and the keys are its own
+    let account_data = fetch_account(&state.rpc, &token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    let extension = token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != state.payer.pubkey() {
+        return Err(ApiError::invalid_account(
+            token_account,
+            "auto-apply requires the server payer to own the account",
+        ));
+    }
+    if PodElGamalPubkey::from(*elgamal_keypair.pubkey()) != extension.elgamal_pubkey {
+        return Err(ApiError::invalid_input(
+            "elgamal_secret_key",
+            "does not match the account's ElGamal pubkey",
+        ));
+    }
+    decrypt_balance(&aes_key, &extension.decryptable_available_balance)?;
+
+    // 3. Hand the keys to the worker, and save them to the key vault
+    let (replaced, persisted) = auto_apply::register(&state, token_account, elgamal_keypair, aes_key).await?;
+    tracing::info!("Registered {} for auto-apply", token_account);
+
+    Ok(Json(RegisterAutoApplyResponse {
+        success: true,
+        token_account: token_account.to_string(),
+        replaced,
+        persisted,
+    }))
+}
+
+/// Stop background apply for an account and drop its keys (admin)
+pub async fn unregister_auto_apply(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<UnregisterAutoApplyRequest>,
+) -> Result<Json<UnregisterAutoApplyResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let unregistered = auto_apply::unregister(&state, &token_account).await?;
+
+    Ok(Json(UnregisterAutoApplyResponse {
+        success: true,
+        unregistered,
+    }))
+}
@@ -1,19 +1,39 @@
-use axum::{Json, http::StatusCode};
+use axum::{Json, extract::State, http::HeaderMap};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
+    instruction::Instruction,
     pubkey::Pubkey,
-    signature::Keypair,
+    signature::Signature,
     signer::Signer,
     transaction::Transaction,
 };
 use spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, StateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount,
+            instruction::{
+                ConfidentialTransferInstruction, apply_pending_balance as apply_pending_balance_instruction,
+                deposit,
+            },
+        },
+    },
     id as token_2022_program_id,
-    instruction::deposit,
+    instruction::{TokenInstruction, decode_instruction_type},
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalSecretKey},
+    state::{Account, Mint},
 };
-use std::str::FromStr;
 
 use crate::{
+    crypto::apply_pending_to_available,
+    error::ApiError,
     models::*,
-    solana::create_rpc_client,
+    routes::{
+        fetch_account, parse_pubkey,
+        tx::{encode_transactions, require_payer_owner},
+        user_keys,
+    },
+    state::AppState,
 };
 
 /// Deposit tokens from public balance to confidential pending balance
@@ -27,8 +47,9 @@ use crate::{
 /// 3. Call apply_pending_balance() → moves to confidential available balance
 /// 4. Now can use for confidential transfers
 pub async fn deposit_tokens(
+    State(state): State<AppState>,
     Json(payload): Json<DepositRequest>,
-) -> Result<Json<DepositResponse>, StatusCode> {
+) -> Result<Json<DepositResponse>, ApiError> {
     tracing::info!(
         "Depositing {} tokens for wallet: {}",
         payload.amount,
@@ -36,34 +57,65 @@ pub async fn deposit_tokens(
     );
 
     // 1. Parse and validate inputs
-    let wallet_pubkey = Pubkey::from_str(&payload.wallet_address)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let token_account = Pubkey::from_str(&payload.token_account)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
 
     // 2. Get RPC client
-    let client = create_rpc_client();
+    let client = &state.rpc;
 
-    // 3. Load payer keypair (in production, user signs this)
-    let payer = load_payer_keypair().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 3. Get the fee payer loaded at startup
+    let payer = &state.payer;
+    require_payer_owner(payload.mode, &wallet_pubkey, &payer.pubkey())?;
 
-    // 4. Create deposit instruction
+    // 4. Read the token account to find its mint and public balance
+    let account_data = fetch_account(client, &token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
+    if token_account_data.base.amount < payload.amount {
+        return Err(ApiError::invalid_input(
+            "amount",
+            format!("exceeds public balance of {}", token_account_data.base.amount),
+        ));
+    }
+
+    // 5. Read the mint for its decimals
+    let mint_pubkey = token_account_data.base.mint;
+    let mint_data = fetch_account(client, &mint_pubkey).await?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data.data)
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "not a Token-2022 mint"))?;
+    let decimals = mint.base.decimals;
+
+    if payload.decimals.is_some_and(|requested| requested != decimals) {
+        return Err(ApiError::invalid_input(
+            "decimals",
+            format!("mint {} has {} decimals", mint_pubkey, decimals),
+        ));
+    }
+
+    // 6. Create deposit instruction
     // This moves tokens from public balance → confidential pending balance
     let deposit_ix = deposit(
         &token_2022_program_id(),
         &token_account,
-        &token_account,  // Mint (derived from token account in real impl)
+        &mint_pubkey,
         payload.amount,
-        payload.decimals,
+        decimals,
         &wallet_pubkey,
         &[],  // Additional signers
     )
-    .map_err(|e| {
-        tracing::error!("Failed to create deposit instruction: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .map_err(|e| ApiError::internal("Failed to create deposit instruction", e))?;
 
-    // 5. Build and send transaction
+    // 7. Build and send transaction
     let mut transaction = Transaction::new_with_payer(
         &[deposit_ix],
         Some(&payer.pubkey()),
@@ -72,24 +124,30 @@ pub async fn deposit_tokens(
     let recent_blockhash = client
         .get_latest_blockhash()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(ApiError::rpc)?;
     
-    transaction.sign(&[&payer], recent_blockhash);
+    transaction.partial_sign(&[payer.as_ref()], recent_blockhash);
+
+    // Non-custodial mode: the owner's wallet signs and submits via /api/tx/submit
+    if payload.mode == SubmissionMode::Unsigned {
+        return Ok(Json(DepositResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&[transaction])?,
+        }));
+    }
 
     let signature = client
         .send_and_confirm_transaction(&transaction)
         .await
-        .map_err(|e| {
-            tracing::error!("Deposit transaction failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(|e| ApiError::transaction(&transaction, e))?;
 
     tracing::info!("Deposit successful: {}", signature);
 
     Ok(Json(DepositResponse {
         success: true,
-        signature: signature.to_string(),
-        error: None,
+        signature: Some(signature.to_string()),
+        transactions: Vec::new(),
     }))
 }
 
@@ -98,72 +156,54 @@ pub async fn deposit_tokens(
 /// After depositing, funds sit in "pending" state. This instruction moves them
 /// to "available" state where they can be used for confidential transfers.
 /// 
-/// This requires decrypting the pending balance using ElGamal/AES keys.
+/// The pending lo/hi balances are decrypted with the ElGamal key and added to
+/// the AES-decrypted available balance to produce the new decryptable balance.
+/// The observed `pending_balance_credit_counter` is passed along: if a credit
+/// lands between reading the account and applying, the program records the
+/// mismatch and this handler reports it instead of returning success. In
+/// unsigned mode /api/tx/submit runs the same check after confirmation.
 pub async fn apply_pending_balance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<ApplyPendingRequest>,
-) -> Result<Json<ApplyPendingResponse>, StatusCode> {
+) -> Result<Json<ApplyPendingResponse>, ApiError> {
     tracing::info!(
         "Applying pending balance for wallet: {}",
         payload.wallet_address
     );
 
     // 1. Parse and validate inputs
-    let wallet_pubkey = Pubkey::from_str(&payload.wallet_address)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let token_account = Pubkey::from_str(&payload.token_account)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
 
     // 2. Get RPC client
-    let client = create_rpc_client();
+    let client = &state.rpc;
 
-    // 3. Load payer keypair
-    let payer = load_payer_keypair().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 3. Get the fee payer loaded at startup
+    let payer = &state.payer;
+    require_payer_owner(payload.mode, &wallet_pubkey, &payer.pubkey())?;
 
-    // 4. Get account state to read pending balance
-    let account_data = client
-        .get_account(&token_account)
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+    // 4. Read the ConfidentialTransferAccount extension for the pending balance
+    let extension = read_confidential_account(client, &token_account).await?;
 
-    // 5. Parse the ConfidentialTransferAccount extension
-    use spl_token_2022::extension::{BaseStateWithExtensions, confidential_transfer::ConfidentialTransferAccount};
-    
-    let token_account_data = spl_token_2022::state::Account::unpack(&account_data.data)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let extension = token_account_data
-        .get_extension::<ConfidentialTransferAccount>()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 5. Load the owner's ElGamal and AES keys (wallet signatures or key vault)
+    let (elgamal_keypair, aes_key) =
+        user_keys(&state, &headers, &wallet_pubkey, &token_account, payload.key_signatures.as_ref()).await?;
 
-    // 6. Generate ElGamal and AES keys (deterministically)
-    // In production, user would provide these or we'd derive from their signature
-    use crate::crypto::{generate_elgamal_keypair, generate_aes_key};
-    
-    // For demo: simulate user wallet
-    let user_wallet = Keypair::new();
-    let elgamal_keypair = generate_elgamal_keypair(&user_wallet, &token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let aes_key = generate_aes_key(&user_wallet, &token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // 7. Create apply pending balance instruction
-    use spl_token_2022::instruction::apply_pending_balance;
-    
-    let apply_ix = apply_pending_balance(
-        &token_2022_program_id(),
+    // 6. Create apply pending balance instruction, pinned to the observed counter
+    let PendingApply {
+        instruction: apply_ix,
+        pending_balance_credit_counter,
+        new_available_balance,
+    } = prepare_apply_pending(
         &token_account,
-        None,  // Expected pending balance count (None = don't check)
+        &wallet_pubkey,
+        &extension,
         elgamal_keypair.secret(),
         &aes_key,
-        &wallet_pubkey,
-        &[],
-    )
-    .map_err(|e| {
-        tracing::error!("Failed to create apply pending balance instruction: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    )?;
 
-    // 8. Build and send transaction
+    // 7. Build and send transaction
     let mut transaction = Transaction::new_with_payer(
         &[apply_ix],
         Some(&payer.pubkey()),
@@ -172,30 +212,240 @@ pub async fn apply_pending_balance(
     let recent_blockhash = client
         .get_latest_blockhash()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(ApiError::rpc)?;
     
-    transaction.sign(&[&payer], recent_blockhash);
+    transaction.partial_sign(&[payer.as_ref()], recent_blockhash);
 
-    let signature = client
-        .send_and_confirm_transaction(&transaction)
-        .await
-        .map_err(|e| {
-            tracing::error!("Apply pending balance transaction failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    // Non-custodial mode: the owner's wallet signs and submits via /api/tx/submit
+    if payload.mode == SubmissionMode::Unsigned {
+        return Ok(Json(ApplyPendingResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&[transaction])?,
+            pending_balance_credit_counter,
+            new_available_balance,
+        }));
+    }
+
+    let signature =
+        send_apply_pending(client, &transaction, &token_account, pending_balance_credit_counter)
+            .await?;
 
     tracing::info!("Apply pending balance successful: {}", signature);
 
     Ok(Json(ApplyPendingResponse {
         success: true,
-        signature: signature.to_string(),
-        error: None,
+        signature: Some(signature.to_string()),
+        transactions: Vec::new(),
+        pending_balance_credit_counter,
+        new_available_balance,
     }))
 }
 
-// Helper function to load payer keypair
-fn load_payer_keypair() -> anyhow::Result<Keypair> {
-    // In production, load from secure storage or environment
-    // For demo, generate a new one (in real usage, this would be funded)
-    Ok(Keypair::new())
-}
\ No newline at end of file
+/// An apply pending balance instruction, pinned to the credits it covers
+pub(crate) struct PendingApply {
+    pub instruction: Instruction,
+    /// Number of pending credits applied
+    pub pending_balance_credit_counter: u64,
+    pub new_available_balance: u64,
+}
+
+/// Build the apply pending balance instruction for the account's current
+/// pending credits
+///
+/// Decrypts available + pending, re-encrypts the sum under the AES key and
+/// pins the instruction to the observed `pending_balance_credit_counter`.
+pub(crate) fn prepare_apply_pending(
+    token_account: &Pubkey,
+    owner: &Pubkey,
+    extension: &ConfidentialTransferAccount,
+    elgamal_secret_key: &ElGamalSecretKey,
+    aes_key: &AeKey,
+) -> Result<PendingApply, ApiError> {
+    let pending_balance_credit_counter = u64::from(extension.pending_balance_credit_counter);
+    if pending_balance_credit_counter == 0 {
+        return Err(ApiError::invalid_account(*token_account, "no pending balance to apply"));
+    }
+
+    let (new_available_balance, new_decryptable_available_balance) = apply_pending_to_available(
+        aes_key,
+        elgamal_secret_key,
+        &extension.decryptable_available_balance,
+        &extension.pending_balance_lo,
+        &extension.pending_balance_hi,
+    )?;
+
+    let instruction = apply_pending_balance_instruction(
+        &token_2022_program_id(),
+        token_account,
+        pending_balance_credit_counter,
+        &new_decryptable_available_balance,
+        owner,
+        &[],
+    )
+    .map_err(|e| ApiError::internal("Failed to create apply pending balance instruction", e))?;
+
+    Ok(PendingApply {
+        instruction,
+        pending_balance_credit_counter,
+        new_available_balance,
+    })
+}
+
+/// Send a signed apply pending balance transaction and check that it applied
+/// exactly `pending_balance_credit_counter` credits
+///
+/// Aborts before sending if a credit already landed since the instruction
+/// was built. After confirmation, a mismatch between the expected and actual
+/// counters recorded by the program means a credit raced the apply.
+///
+/// That error comes too late to undo anything: the apply has landed, every
+/// pending credit including the raced ones is in the available balance, and
+/// the decryptable available balance on chain is short by the raced amounts.
+/// Transfers and withdraws fail their proofs until it is re-synced, so
+/// recovery means writing back `new_available_balance` plus the raced
+/// amounts (from /api/account/history) as the decryptable balance of a later
+/// apply.
+pub(crate) async fn send_apply_pending(
+    client: &RpcClient,
+    transaction: &Transaction,
+    token_account: &Pubkey,
+    pending_balance_credit_counter: u64,
+) -> Result<Signature, ApiError> {
+    let current_counter = u64::from(
+        read_confidential_account(client, token_account)
+            .await?
+            .pending_balance_credit_counter,
+    );
+    if current_counter != pending_balance_credit_counter {
+        return Err(ApiError::invalid_account(
+            *token_account,
+            format!(
+                "pending balance changed while applying ({} -> {} credits); retry",
+                pending_balance_credit_counter, current_counter
+            ),
+        ));
+    }
+
+    let signature = client
+        .send_and_confirm_transaction(transaction)
+        .await
+        .map_err(|e| ApiError::transaction(transaction, e))?;
+
+    check_pending_applied(client, token_account, &signature).await?;
+
+    Ok(signature)
+}
+
+/// Check that a confirmed apply pending balance transaction applied exactly
+/// the credits it expected
+///
+/// The program does not fail on a mismatch; it records the expected and
+/// actual counters, and a difference means the decryptable available balance
+/// it wrote is out of date.
+pub(crate) async fn check_pending_applied(
+    client: &RpcClient,
+    token_account: &Pubkey,
+    signature: &Signature,
+) -> Result<(), ApiError> {
+    let extension = read_confidential_account(client, token_account).await?;
+    let expected = u64::from(extension.expected_pending_balance_credit_counter);
+    let actual = u64::from(extension.actual_pending_balance_credit_counter);
+    if expected != actual {
+        tracing::error!(
+            "Apply {} on {} raced a credit: expected {} credits, applied {}",
+            signature,
+            token_account,
+            expected,
+            actual
+        );
+        return Err(ApiError::TransactionFailed {
+            message: format!(
+                "a credit landed during apply (expected {} credits, applied {}); \
+                 the decryptable available balance is out of date",
+                expected, actual
+            ),
+            signature: Some(signature.to_string()),
+            logs: Vec::new(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Token accounts whose pending balance `transaction` applies, for
+/// /api/tx/submit to check with [`check_pending_applied`]
+pub(crate) fn applied_token_accounts(transaction: &Transaction) -> Vec<Pubkey> {
+    let message = &transaction.message;
+    message
+        .instructions
+        .iter()
+        .filter(|instruction| {
+            message.account_keys.get(usize::from(instruction.program_id_index)) == Some(&token_2022_program_id())
+                && matches!(
+                    TokenInstruction::unpack(&instruction.data),
+                    Ok(TokenInstruction::ConfidentialTransferExtension)
+                )
+                && matches!(
+                    decode_instruction_type(&instruction.data[1..]),
+                    Ok(ConfidentialTransferInstruction::ApplyPendingBalance)
+                )
+        })
+        .filter_map(|instruction| {
+            let index = instruction.accounts.first()?;
+            message.account_keys.get(usize::from(*index)).copied()
+        })
+        .collect()
+}
+
+/// Read the ConfidentialTransferAccount extension of a token account
+pub(crate) async fn read_confidential_account(
+    client: &RpcClient,
+    token_account: &Pubkey,
+) -> Result<ConfidentialTransferAccount, ApiError> {
+    let account_data = fetch_account(client, token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(*token_account, "not a Token-2022 account"))?;
+    let extension = token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(*token_account, "confidential transfers not configured"))?;
+
+    Ok(*extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::Keypair;
+
+    #[test]
+    fn test_applied_token_accounts() {
+        let token_account = Pubkey::new_unique();
+        let owner = Keypair::new();
+        let apply_ix = apply_pending_balance_instruction(
+            &token_2022_program_id(),
+            &token_account,
+            1,
+            &AeKey::new_rand().encrypt(5).into(),
+            &owner.pubkey(),
+            &[],
+        )
+        .unwrap();
+        let deposit_ix = deposit(
+            &token_2022_program_id(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            5,
+            6,
+            &owner.pubkey(),
+            &[],
+        )
+        .unwrap();
+
+        let transaction = Transaction::new_with_payer(&[deposit_ix.clone(), apply_ix], Some(&owner.pubkey()));
+        assert_eq!(applied_token_accounts(&transaction), vec![token_account]);
+
+        let transaction = Transaction::new_with_payer(&[deposit_ix], Some(&owner.pubkey()));
+        assert!(applied_token_accounts(&transaction).is_empty());
+    }
+}
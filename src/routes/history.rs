@@ -0,0 +1,177 @@
+use axum::{Json, extract::State, http::HeaderMap};
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, StateWithExtensions, confidential_transfer::ConfidentialTransferAccount},
+    state::Account,
+};
+
+use crate::{
+    error::ApiError,
+    history::{HistoryStore, IndexedEntry, decrypt_balance_after, derive_amounts},
+    models::*,
+    routes::{fetch_account, parse_pubkey, require_admin, user_keys},
+    state::AppState,
+};
+
+/// Entries per page when the request sets no limit
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+/// Most entries per page
+const MAX_HISTORY_LIMIT: u32 = 200;
+
+/// Confidential transaction history of a registered account, newest first
+///
+/// Reading needs the owner's keys: their `key_signatures`, or for custodial
+/// accounts the admin token or the owner's signature headers. The keys
+/// decrypt the balances each entry wrote, and the amounts of applies and
+/// outgoing transfers follow from consecutive balances; neither is stored.
+/// Pages continue with `before = next_cursor`.
+pub async fn get_account_history(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<AccountHistoryRequest>,
+) -> Result<Json<AccountHistoryResponse>, ApiError> {
+    tracing::info!("Getting history for token account: {}", payload.token_account);
+
+    // 1. Parse and validate inputs
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let limit = match payload.limit {
+        None => DEFAULT_HISTORY_LIMIT,
+        Some(limit) if (1..=MAX_HISTORY_LIMIT).contains(&limit) => limit,
+        Some(_) => {
+            return Err(ApiError::invalid_input(
+                "limit",
+                format!("must be between 1 and {}", MAX_HISTORY_LIMIT),
+            ));
+        }
+    };
+    let store = history_store(&state)?;
+
+    // 2. Check the account is indexed for this owner
+    let owner = store
+        .owner(&token_account)
+        .await?
+        .ok_or_else(|| ApiError::invalid_account(token_account, "not registered for history indexing"))?;
+    if owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
+
+    // 3. Resolve the owner's AES key
+    let (_, aes_key) = user_keys(
+        &state,
+        &headers,
+        &wallet_pubkey,
+        &token_account,
+        payload.key_signatures.as_ref(),
+    )
+    .await?;
+
+    // 4. Read one entry past the page to know whether another follows
+    let mut entries = store.entries(&token_account, payload.before, limit + 1).await?;
+    let has_more = entries.len() > limit as usize;
+    entries.truncate(limit as usize);
+
+    // 5. Decrypt balances and derive amounts, oldest entry first
+    let oldest_id = entries.last().map(|indexed| indexed.id).or(payload.before);
+    let previous_balance = store
+        .last_balance_entry(&token_account, oldest_id)
+        .await?
+        .and_then(|indexed| decrypt_balance_after(&aes_key, &indexed.entry));
+
+    entries.reverse();
+    for indexed in entries.iter_mut() {
+        indexed.balance_after = decrypt_balance_after(&aes_key, &indexed.entry);
+    }
+    derive_amounts(&mut entries, previous_balance);
+    entries.reverse();
+
+    let next_cursor = if has_more {
+        entries.last().map(|indexed| indexed.id)
+    } else {
+        None
+    };
+
+    Ok(Json(AccountHistoryResponse {
+        success: true,
+        entries: entries.into_iter().map(history_entry).collect(),
+        next_cursor,
+    }))
+}
+
+/// Start indexing an account's confidential history (admin)
+///
+/// Indexing starts from the account's first transaction.
+pub async fn register_history(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<RegisterHistoryRequest>,
+) -> Result<Json<RegisterHistoryResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    // 1. Parse and validate inputs
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let store = history_store(&state)?;
+
+    // 2. Check the account is configured for confidential transfers
+    let account_data = fetch_account(&state.rpc, &token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+    let owner = token_account_data.base.owner;
+
+    // 3. Hand the account to the indexer
+    let registered = store.register(&token_account, &owner).await?;
+    tracing::info!("Registered {} for history indexing", token_account);
+
+    Ok(Json(RegisterHistoryResponse {
+        success: true,
+        token_account: token_account.to_string(),
+        owner: owner.to_string(),
+        already_registered: !registered,
+    }))
+}
+
+/// Stop indexing an account and delete its stored history (admin)
+pub async fn unregister_history(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<UnregisterHistoryRequest>,
+) -> Result<Json<UnregisterHistoryResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let unregistered = history_store(&state)?.unregister(&token_account).await?;
+
+    Ok(Json(UnregisterHistoryResponse {
+        success: true,
+        unregistered,
+    }))
+}
+
+fn history_store(state: &AppState) -> Result<&HistoryStore, ApiError> {
+    state.history.as_deref().ok_or_else(|| {
+        ApiError::invalid_input(
+            "token_account",
+            "history indexing is disabled on this server (set HISTORY_INDEXER_ENABLED)",
+        )
+    })
+}
+
+fn history_entry(indexed: IndexedEntry) -> HistoryEntry {
+    HistoryEntry {
+        id: indexed.id,
+        signature: indexed.signature.to_string(),
+        slot: indexed.slot,
+        block_time: indexed.block_time,
+        instruction_index: indexed.instruction_index,
+        kind: indexed.entry.kind.as_str(),
+        counterparty: indexed.entry.counterparty.map(|counterparty| counterparty.to_string()),
+        amount: indexed.entry.amount,
+        available_balance_after: indexed.balance_after,
+        decryptable_balance_after: indexed.entry.decryptable_balance.map(|balance| balance.to_string()),
+    }
+}
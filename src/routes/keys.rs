@@ -0,0 +1,48 @@
+use axum::Json;
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use crate::{
+    crypto::{KEY_DERIVATION_VERSION, custodial_access_message, key_derivation_messages},
+    error::ApiError,
+    models::*,
+    routes::parse_pubkey,
+};
+
+/// Return the messages the owner's wallet signs to derive its encryption keys
+///
+/// The signatures go in the `key_signatures` field of the account, balance,
+/// apply, transfer, withdraw, empty and proof endpoints. The server verifies
+/// them against the wallet and derives the keys without ever holding the
+/// wallet secret.
+pub async fn get_key_derivation_messages(
+    Json(payload): Json<KeyDerivationMessagesRequest>,
+) -> Result<Json<KeyDerivationMessagesResponse>, ApiError> {
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let messages = key_derivation_messages(KEY_DERIVATION_VERSION, &token_account, payload.epoch)?;
+
+    Ok(Json(KeyDerivationMessagesResponse {
+        success: true,
+        version: KEY_DERIVATION_VERSION,
+        epoch: payload.epoch,
+        elgamal_message: BASE64_STANDARD.encode(&messages.elgamal),
+        aes_message: BASE64_STANDARD.encode(&messages.aes),
+    }))
+}
+
+/// Return the message the owner's wallet signs to let requests use the
+/// stored keys of a custodial account until `expires_at`
+///
+/// Requests without `key_signatures` carry the signature in the
+/// `X-Owner-Signature` header and `expires_at` in `X-Owner-Signature-Expires`.
+pub async fn get_custodial_access_message(
+    Json(payload): Json<CustodialAccessMessageRequest>,
+) -> Result<Json<CustodialAccessMessageResponse>, ApiError> {
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+    let message = custodial_access_message(&token_account, payload.expires_at);
+
+    Ok(Json(CustodialAccessMessageResponse {
+        success: true,
+        message: BASE64_STANDARD.encode(message),
+        expires_at: payload.expires_at,
+    }))
+}
@@ -0,0 +1,212 @@
+use axum::{Json, extract::State, http::HeaderMap};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_token_2022::{
+    extension::{
+        ExtensionType,
+        confidential_transfer::instruction::initialize_mint as initialize_confidential_transfer_mint,
+        confidential_transfer_fee::instruction::initialize_confidential_transfer_fee_config,
+        metadata_pointer::instruction::initialize as initialize_metadata_pointer,
+        transfer_fee::instruction::initialize_transfer_fee_config,
+    },
+    id as token_2022_program_id,
+    instruction::initialize_mint,
+    state::Mint,
+};
+use spl_token_metadata_interface::state::TokenMetadata;
+
+use crate::{
+    error::ApiError,
+    models::*,
+    routes::{parse_elgamal_pubkey, parse_pubkey, require_admin},
+    state::AppState,
+};
+
+/// Create a Token-2022 mint with confidential transfers enabled (admin)
+///
+/// Extensions:
+/// - ConfidentialTransferMint (always) - authority, auto-approve flag, optional auditor
+/// - TransferFeeConfig + ConfidentialTransferFeeConfig (optional)
+/// - MetadataPointer + TokenMetadata stored on the mint itself (optional)
+///
+/// The server generates the mint keypair and pays for the account. The
+/// authority controls minting, account approval and fee configuration, and
+/// must be given explicitly: the server payer only becomes the authority
+/// when asked for by its address.
+pub async fn create_mint(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateMintRequest>,
+) -> Result<Json<CreateMintResponse>, ApiError> {
+    require_admin(&state, &headers)?;
+
+    // 1. Parse and validate inputs
+    let client = &state.rpc;
+    let payer = &state.payer;
+
+    let authority = parse_pubkey("authority", &payload.authority)?;
+    let auditor_elgamal_pubkey = payload
+        .auditor_elgamal_pubkey
+        .as_deref()
+        .map(|pubkey| parse_elgamal_pubkey("auditor_elgamal_pubkey", pubkey))
+        .transpose()?;
+    let withheld_authority_elgamal_pubkey = payload
+        .transfer_fee
+        .as_ref()
+        .map(|fee| {
+            parse_elgamal_pubkey(
+                "transfer_fee.withdraw_withheld_authority_elgamal_pubkey",
+                &fee.withdraw_withheld_authority_elgamal_pubkey,
+            )
+        })
+        .transpose()?;
+
+    // Initializing metadata requires the mint authority's signature
+    if payload.metadata.is_some() && authority != payer.pubkey() {
+        return Err(ApiError::invalid_input(
+            "metadata",
+            "metadata can only be initialized when the server payer is the mint authority",
+        ));
+    }
+
+    let mint_keypair = Keypair::new();
+    let mint_pubkey = mint_keypair.pubkey();
+    tracing::info!("Creating confidential mint {} (authority {})", mint_pubkey, authority);
+
+    // 2. Size the mint account for its extensions
+    let mut extensions = vec![ExtensionType::ConfidentialTransferMint];
+    if payload.transfer_fee.is_some() {
+        extensions.push(ExtensionType::TransferFeeConfig);
+        extensions.push(ExtensionType::ConfidentialTransferFeeConfig);
+    }
+    if payload.metadata.is_some() {
+        extensions.push(ExtensionType::MetadataPointer);
+    }
+
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&extensions)
+        .map_err(|e| ApiError::internal("Failed to calculate mint size", e))?;
+
+    // Metadata is variable length and reallocated on initialize; fund it up front
+    let token_metadata = payload.metadata.as_ref().map(|metadata| TokenMetadata {
+        name: metadata.name.clone(),
+        symbol: metadata.symbol.clone(),
+        uri: metadata.uri.clone(),
+        ..Default::default()
+    });
+    let metadata_space = match &token_metadata {
+        Some(metadata) => metadata
+            .tlv_size_of()
+            .map_err(|e| ApiError::internal("Failed to calculate metadata size", e))?,
+        None => 0,
+    };
+
+    let rent = client
+        .get_minimum_balance_for_rent_exemption(space + metadata_space)
+        .await
+        .map_err(ApiError::rpc)?;
+
+    // 3. Build instructions: create account, extensions, then initialize_mint
+    let mut instructions = vec![
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint_pubkey,
+            rent,
+            space as u64,
+            &token_2022_program_id(),
+        ),
+        initialize_confidential_transfer_mint(
+            &token_2022_program_id(),
+            &mint_pubkey,
+            Some(authority),
+            payload.auto_approve_new_accounts,
+            auditor_elgamal_pubkey.map(Into::into),
+        )
+        .map_err(|e| ApiError::internal("Failed to create confidential mint instruction", e))?,
+    ];
+
+    if let (Some(fee), Some(withheld_authority_elgamal_pubkey)) =
+        (&payload.transfer_fee, withheld_authority_elgamal_pubkey)
+    {
+        instructions.push(
+            initialize_transfer_fee_config(
+                &token_2022_program_id(),
+                &mint_pubkey,
+                Some(&authority),
+                Some(&authority),
+                fee.basis_points,
+                fee.maximum_fee,
+            )
+            .map_err(|e| ApiError::internal("Failed to create transfer fee instruction", e))?,
+        );
+        instructions.push(
+            initialize_confidential_transfer_fee_config(
+                &token_2022_program_id(),
+                &mint_pubkey,
+                Some(authority),
+                &withheld_authority_elgamal_pubkey.into(),
+            )
+            .map_err(|e| ApiError::internal("Failed to create confidential fee instruction", e))?,
+        );
+    }
+
+    if payload.metadata.is_some() {
+        instructions.push(
+            initialize_metadata_pointer(
+                &token_2022_program_id(),
+                &mint_pubkey,
+                Some(authority),
+                Some(mint_pubkey),
+            )
+            .map_err(|e| ApiError::internal("Failed to create metadata pointer instruction", e))?,
+        );
+    }
+
+    instructions.push(
+        initialize_mint(
+            &token_2022_program_id(),
+            &mint_pubkey,
+            &authority,
+            None,
+            payload.decimals,
+        )
+        .map_err(|e| ApiError::internal("Failed to create initialize mint instruction", e))?,
+    );
+
+    if let Some(metadata) = token_metadata {
+        instructions.push(spl_token_metadata_interface::instruction::initialize(
+            &token_2022_program_id(),
+            &mint_pubkey,
+            &authority,
+            &mint_pubkey,
+            &authority,
+            metadata.name,
+            metadata.symbol,
+            metadata.uri,
+        ));
+    }
+
+    // 4. Sign with payer and the new mint keypair, then send
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+
+    let recent_blockhash = client
+        .get_latest_blockhash()
+        .await
+        .map_err(ApiError::rpc)?;
+    transaction.sign(&[payer.as_ref(), &mint_keypair], recent_blockhash);
+
+    let signature = client
+        .send_and_confirm_transaction(&transaction)
+        .await
+        .map_err(|e| ApiError::transaction(&transaction, e))?;
+
+    tracing::info!("Confidential mint {} created: {}", mint_pubkey, signature);
+
+    Ok(Json(CreateMintResponse {
+        success: true,
+        mint: mint_pubkey.to_string(),
+        signature: signature.to_string(),
+    }))
+}
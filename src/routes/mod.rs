@@ -1,9 +1,286 @@
 pub mod deposit;
 pub mod account;
+pub mod attestation;
+pub mod auditor;
+pub mod auto_apply;
+#[cfg(feature = "database")]
+pub mod history;
+pub mod keys;
+pub mod mint;
+pub mod proof;
+pub mod rotation;
+pub mod settings;
 pub mod transfer;
+pub mod tx;
 pub mod withdraw;
 
 pub use deposit::*;
 pub use account::*;
+pub use attestation::*;
+pub use auditor::*;
+pub use auto_apply::*;
+#[cfg(feature = "database")]
+pub use history::*;
+pub use keys::*;
+pub use mint::*;
+pub use proof::*;
+pub use rotation::*;
+pub use settings::*;
 pub use transfer::*;
+pub use tx::*;
 pub use withdraw::*;
+
+use axum::http::{HeaderMap, header::AUTHORIZATION};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcAccountInfoConfig};
+use solana_sdk::{account::Account, instruction::Instruction, pubkey::Pubkey, signature::Signature};
+use spl_token_2022::solana_zk_sdk::encryption::{
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalPubkey, ElGamalSecretKey},
+};
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    crypto::{derive_keys_from_signatures, unix_now, verify_custodial_access},
+    error::ApiError,
+    models::KeySignatures,
+    solana::{ProofContextAccount, get_account_info},
+    state::AppState,
+};
+
+/// Owner-signed instructions together with the proof context accounts they
+/// reference, which must be created before and closed after sending them
+pub(crate) struct PreparedInstructions {
+    pub proof_contexts: Vec<ProofContextAccount>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// Parse a base58 address from a request field
+pub(crate) fn parse_pubkey(field: &'static str, value: &str) -> Result<Pubkey, ApiError> {
+    Pubkey::from_str(value).map_err(|e| ApiError::invalid_input(field, e))
+}
+
+/// Parse a base58 ElGamal public key from a request field
+pub(crate) fn parse_elgamal_pubkey(field: &'static str, value: &str) -> Result<ElGamalPubkey, ApiError> {
+    let bytes = bs58::decode(value)
+        .into_vec()
+        .map_err(|e| ApiError::invalid_input(field, e))?;
+    ElGamalPubkey::from_bytes(&bytes)
+        .ok_or_else(|| ApiError::invalid_input(field, "not a valid ElGamal pubkey"))
+}
+
+/// Parse a base58 ElGamal secret key from a request field
+pub(crate) fn parse_elgamal_secret_key(field: &'static str, value: &str) -> Result<ElGamalSecretKey, ApiError> {
+    let bytes = bs58::decode(value)
+        .into_vec()
+        .map_err(|e| ApiError::invalid_input(field, e))?;
+    ElGamalSecretKey::try_from(bytes.as_slice())
+        .map_err(|_| ApiError::invalid_input(field, "not a valid ElGamal secret key"))
+}
+
+/// Parse a base58 AES key from a request field
+pub(crate) fn parse_aes_key(field: &'static str, value: &str) -> Result<AeKey, ApiError> {
+    let bytes = bs58::decode(value)
+        .into_vec()
+        .map_err(|e| ApiError::invalid_input(field, e))?;
+    AeKey::try_from(bytes.as_slice()).map_err(|_| ApiError::invalid_input(field, "not a valid AES key"))
+}
+
+/// Derive the wallet's ElGamal keypair and AES key for `token_account` from
+/// its signatures over the key derivation messages
+pub(crate) fn derive_user_keys(
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+    signatures: &KeySignatures,
+) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+    let elgamal_signature = Signature::from_str(&signatures.elgamal_signature)
+        .map_err(|e| ApiError::invalid_input("key_signatures.elgamal_signature", e))?;
+    let aes_signature = Signature::from_str(&signatures.aes_signature)
+        .map_err(|e| ApiError::invalid_input("key_signatures.aes_signature", e))?;
+
+    Ok(derive_keys_from_signatures(
+        wallet,
+        token_account,
+        signatures.version,
+        signatures.epoch,
+        &elgamal_signature,
+        &aes_signature,
+    )?)
+}
+
+/// Header carrying the owner's signature over the custodial access message
+pub const OWNER_SIGNATURE_HEADER: &str = "x-owner-signature";
+/// Header carrying the expiry (unix seconds) the owner's signature covers
+pub const OWNER_SIGNATURE_EXPIRES_HEADER: &str = "x-owner-signature-expires";
+
+/// Resolve the owner's ElGamal keypair and AES key for `token_account`
+///
+/// Derived from the wallet's signatures when they are provided; otherwise
+/// loaded from the key vault, for custodial accounts, once the request is
+/// authorized by [`authorize_custodial`].
+pub(crate) async fn user_keys(
+    state: &AppState,
+    headers: &HeaderMap,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+    signatures: Option<&KeySignatures>,
+) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+    if let Some(signatures) = signatures {
+        return derive_user_keys(wallet, token_account, signatures);
+    }
+
+    owned_stored_keys(state, headers, wallet, token_account)
+        .await?
+        .ok_or_else(|| {
+            ApiError::invalid_input(
+                "key_signatures",
+                format!("required: no stored keys for {}", token_account),
+            )
+        })
+}
+
+/// Check the request may use the stored keys of `wallet`'s custodial
+/// `token_account`
+///
+/// Accepts the admin token, or the wallet's signature over
+/// `custodial_access_message(token_account, expires_at)` in the
+/// `X-Owner-Signature` header with `expires_at` in
+/// `X-Owner-Signature-Expires`.
+pub(crate) fn authorize_custodial(
+    state: &AppState,
+    headers: &HeaderMap,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+) -> Result<(), ApiError> {
+    if require_admin(state, headers).is_ok() {
+        return Ok(());
+    }
+
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let (Some(signature), Some(expires_at)) = (
+        header(OWNER_SIGNATURE_HEADER),
+        header(OWNER_SIGNATURE_EXPIRES_HEADER),
+    ) else {
+        return Err(ApiError::invalid_input(
+            "key_signatures",
+            "required unless the admin token or the owner's X-Owner-Signature authorizes the stored keys",
+        ));
+    };
+
+    let signature = Signature::from_str(signature)
+        .map_err(|e| ApiError::invalid_input("X-Owner-Signature", e))?;
+    let expires_at = expires_at
+        .parse()
+        .map_err(|e| ApiError::invalid_input("X-Owner-Signature-Expires", e))?;
+    verify_custodial_access(wallet, token_account, expires_at, &signature, unix_now())?;
+
+    Ok(())
+}
+
+/// Keys of `wallet`'s custodial account from the key vault, for an
+/// authorized request; keys stored for another owner are not returned
+#[cfg(feature = "database")]
+pub(crate) async fn owned_stored_keys(
+    state: &AppState,
+    headers: &HeaderMap,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+) -> Result<Option<(ElGamalKeypair, AeKey)>, ApiError> {
+    authorize_custodial(state, headers, wallet, token_account)?;
+    let Some(vault) = &state.vault else {
+        return Ok(None);
+    };
+
+    if vault.owner(token_account).await? != Some(*wallet) {
+        return Ok(None);
+    }
+    Ok(vault.load(token_account).await?)
+}
+
+/// Keys of a custodial account; there is no key vault without the `database` feature
+#[cfg(not(feature = "database"))]
+pub(crate) async fn owned_stored_keys(
+    state: &AppState,
+    headers: &HeaderMap,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+) -> Result<Option<(ElGamalKeypair, AeKey)>, ApiError> {
+    authorize_custodial(state, headers, wallet, token_account)?;
+    Ok(None)
+}
+
+/// Keys of a custodial account from the key vault, if it has any stored
+///
+/// Does no authorization: callers must have checked the admin token.
+#[cfg(feature = "database")]
+pub(crate) async fn stored_keys(
+    state: &AppState,
+    token_account: &Pubkey,
+) -> Result<Option<(ElGamalKeypair, AeKey)>, ApiError> {
+    match &state.vault {
+        Some(vault) => Ok(vault.load(token_account).await?),
+        None => Ok(None),
+    }
+}
+
+/// Keys of a custodial account; there is no key vault without the `database` feature
+#[cfg(not(feature = "database"))]
+pub(crate) async fn stored_keys(
+    _state: &AppState,
+    _token_account: &Pubkey,
+) -> Result<Option<(ElGamalKeypair, AeKey)>, ApiError> {
+    Ok(None)
+}
+
+/// Check the `Authorization: Bearer <ADMIN_TOKEN>` header
+pub(crate) fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let expected = state
+        .config
+        .admin_token
+        .as_deref()
+        .ok_or(ApiError::Unauthorized)?;
+
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)?;
+
+    // Constant time, so response timing does not reveal a matching prefix
+    if !bool::from(provided.as_bytes().ct_eq(expected.as_bytes())) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok(())
+}
+
+/// Fetch an account, distinguishing a missing account from an RPC failure
+pub(crate) async fn fetch_account(client: &RpcClient, pubkey: &Pubkey) -> Result<Account, ApiError> {
+    get_account_info(client, pubkey)
+        .await
+        .map_err(ApiError::rpc)?
+        .ok_or(ApiError::AccountNotFound(*pubkey))
+}
+
+/// Fetch an account along with the slot it was read at
+///
+/// With `min_context_slot`, the RPC node refuses to answer from an older slot.
+pub(crate) async fn fetch_account_with_slot(
+    client: &RpcClient,
+    pubkey: &Pubkey,
+    min_context_slot: Option<u64>,
+) -> Result<(Account, u64), ApiError> {
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(client.commitment()),
+        min_context_slot,
+        ..RpcAccountInfoConfig::default()
+    };
+    let response = client
+        .get_account_with_config(pubkey, config)
+        .await
+        .map_err(ApiError::rpc)?;
+    let account = response.value.ok_or(ApiError::AccountNotFound(*pubkey))?;
+    Ok((account, response.context.slot))
+}
@@ -0,0 +1,118 @@
+use axum::{Json, extract::State};
+use spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, StateWithExtensions,
+        confidential_transfer::ConfidentialTransferAccount,
+    },
+    solana_zk_sdk::encryption::elgamal::ElGamalCiphertext,
+    state::Account,
+};
+
+use crate::{
+    crypto::{EligibilityProof, verify_eligibility_proof},
+    error::ApiError,
+    models::*,
+    routes::{fetch_account_with_slot, parse_pubkey},
+    state::AppState,
+};
+
+/// Verify an eligibility proof from /api/proof/generate
+///
+/// Re-reads the account's available balance ciphertext at `slot` or later and
+/// checks the proof against it. A proof stays valid until the account's
+/// available balance changes. An `attestation` passed along is checked as
+/// well. Rejections are reported as `valid: false` with a reason rather than
+/// as errors.
+pub async fn verify_proof(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifyProofRequest>,
+) -> Result<Json<VerifyProofResponse>, ApiError> {
+    tracing::info!(
+        "Verifying eligibility proof for {} (threshold {}, slot {})",
+        payload.token_account,
+        payload.threshold,
+        payload.slot
+    );
+
+    // 1. Parse and validate inputs
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    let proof = match EligibilityProof::from_base64(&payload.proof) {
+        Ok(proof) => proof,
+        Err(e) => return Ok(rejected("MALFORMED_PROOF", e, None)),
+    };
+
+    // An attestation must be one this server issued for the same statement,
+    // and not expired or revoked since
+    if let Some(attestation) = &payload.attestation {
+        if let Err(e) = state.attestations.verify(attestation) {
+            return Ok(rejected(e.code(), e, None));
+        }
+        let statement = &attestation.statement;
+        if statement.token_account != token_account.to_string()
+            || statement.threshold != payload.threshold
+            || statement.slot != payload.slot
+        {
+            return Ok(rejected(
+                "ATTESTATION_MISMATCH",
+                "attestation is for another account, threshold or slot",
+                None,
+            ));
+        }
+    }
+
+    // 2. Read the account at the proof's slot or later
+    let client = &state.rpc;
+    let current_slot = client.get_slot().await.map_err(ApiError::rpc)?;
+    if payload.slot > current_slot {
+        return Ok(rejected(
+            "SLOT_NOT_REACHED",
+            format!("slot {} is ahead of the cluster (at {})", payload.slot, current_slot),
+            None,
+        ));
+    }
+
+    let (account_data, slot) =
+        fetch_account_with_slot(client, &token_account, Some(payload.slot)).await?;
+
+    let Ok(token_account_data) = StateWithExtensions::<Account>::unpack(&account_data.data) else {
+        return Ok(rejected("WRONG_ACCOUNT", "not a Token-2022 account", Some(slot)));
+    };
+    let Ok(ct_extension) = token_account_data.get_extension::<ConfidentialTransferAccount>() else {
+        return Ok(rejected("WRONG_ACCOUNT", "confidential transfers not configured", Some(slot)));
+    };
+
+    let available_balance = ElGamalCiphertext::try_from(ct_extension.available_balance)
+        .map_err(|_| ApiError::invalid_account(token_account, "malformed available balance ciphertext"))?;
+
+    // 3. Run the zero-knowledge verification against the on-chain state
+    match verify_eligibility_proof(
+        &proof,
+        &ct_extension.elgamal_pubkey,
+        &available_balance,
+        payload.threshold,
+    ) {
+        Ok(()) => Ok(Json(VerifyProofResponse {
+            success: true,
+            valid: true,
+            reason_code: None,
+            reason: None,
+            verified_slot: Some(slot),
+        })),
+        Err(e) => Ok(rejected(e.code(), e, Some(slot))),
+    }
+}
+
+fn rejected(
+    reason_code: &'static str,
+    reason: impl ToString,
+    verified_slot: Option<u64>,
+) -> Json<VerifyProofResponse> {
+    Json(VerifyProofResponse {
+        success: true,
+        valid: false,
+        reason_code: Some(reason_code),
+        reason: Some(reason.to_string()),
+        verified_slot,
+    })
+}
@@ -0,0 +1,1125 @@
+use axum::{Json, extract::State, http::HeaderMap};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::{MAX_SEED_LEN, Pubkey},
+    signature::{Keypair, Signature},
+    signer::Signer,
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount, ConfidentialTransferMint,
+            instruction::{apply_pending_balance, approve_account, deposit},
+        },
+    },
+    id as token_2022_program_id,
+    instruction::{close_account, initialize_account3, transfer_checked},
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey,
+        elgamal::ElGamalKeypair,
+        pod::elgamal::{PodElGamalCiphertext, PodElGamalPubkey},
+    },
+    state::{Account, Mint},
+};
+
+use crate::{
+    auto_apply,
+    crypto::{BalanceDecryptionError, apply_pending_to_available, decrypt_balance},
+    error::ApiError,
+    models::*,
+    routes::{
+        PreparedInstructions,
+        account::{confidential_account_instructions, prepare_empty_account},
+        authorize_custodial,
+        deposit::{PendingApply, check_pending_applied, prepare_apply_pending},
+        derive_user_keys, fetch_account, owned_stored_keys, parse_pubkey,
+        tx::{encode_transactions, require_payer_owner, unsigned_transactions},
+        user_keys,
+        withdraw::prepare_withdraw,
+    },
+    solana::{ProofContextGuard, get_account_info},
+    state::AppState,
+};
+
+/// Most steps one submit-mode request runs: a full rotation takes six, plus
+/// room to apply again when a credit lands before the account is emptied
+const MAX_STEPS: usize = 10;
+
+/// Rotate the ElGamal key of a confidential account
+///
+/// Token-2022 cannot reconfigure an account's confidential transfer
+/// extension, so the account is drained and recreated under the new key:
+/// 1. Apply pending credits, withdraw the available balance and empty the
+///    account (old keys)
+/// 2. Park the public balance in a holding account and close the token account
+/// 3. Recreate the associated token account and configure it with the new
+///    ElGamal pubkey and a fresh pubkey validity proof
+/// 4. Move the parked balance back, deposit it and apply it (new keys)
+///
+/// Each request works out where the rotation stands from on-chain state, so
+/// an interrupted rotation resumes where it stopped. With `mode: "submit"`
+/// (the owner must be the server payer) the remaining steps run in order and
+/// each is reported once confirmed; a failure after the first step is
+/// reported with `failed_step` alongside the steps that did confirm. With
+/// `mode: "unsigned"` the transactions of the next step are returned for the
+/// owner to sign; call again once they are confirmed.
+///
+/// Non-custodial accounts pass `key_signatures` for the current keys and
+/// `new_key_signatures` for a new epoch, and a finished rotation is reported
+/// as `completed`. Custodial accounts need the admin token or the owner's
+/// signature (see `authorize_custodial`). A custodial rotation begins with
+/// `start: true`, which generates the new keys and keeps them pending in the
+/// key vault until the account is recreated with them; requests without it
+/// resume the rotation, and report `completed` once none is in progress.
+///
+/// The balance is public while parked, so rotate once the old keys are
+/// exposed rather than to hide the amount.
+pub async fn rotate_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<RotateKeyRequest>,
+) -> Result<Json<RotateKeyResponse>, ApiError> {
+    tracing::info!(
+        "Rotating ElGamal key of {} for wallet: {}",
+        payload.token_account,
+        payload.wallet_address
+    );
+
+    // 1. Parse and validate inputs
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    match (&payload.key_signatures, &payload.new_key_signatures) {
+        (Some(_), None) => {
+            return Err(ApiError::invalid_input("new_key_signatures", "required with key_signatures"));
+        }
+        (None, Some(_)) => {
+            return Err(ApiError::invalid_input("key_signatures", "required with new_key_signatures"));
+        }
+        (Some(old), Some(new)) if old.version == new.version && old.epoch == new.epoch => {
+            return Err(ApiError::invalid_input(
+                "new_key_signatures",
+                "must sign the key derivation messages of a new epoch",
+            ));
+        }
+        _ => {}
+    }
+
+    // 2. Check the request speaks for the owner: its signatures for both
+    // keys, or authorization to use a custodial account's stored keys
+    match (&payload.key_signatures, &payload.new_key_signatures) {
+        (Some(old), Some(new)) => {
+            derive_user_keys(&wallet_pubkey, &token_account, old)?;
+            derive_user_keys(&wallet_pubkey, &token_account, new)?;
+        }
+        _ => authorize_custodial(&state, &headers, &wallet_pubkey, &token_account)?,
+    }
+
+    let client = &state.rpc;
+    let payer = &state.payer;
+
+    require_payer_owner(payload.mode, &wallet_pubkey, &payer.pubkey())?;
+
+    // 3. Read the token account, the holding account and the mint
+    let holding_account = holding_account(&wallet_pubkey, &token_account)?;
+    let snapshot = read_snapshot(client, &wallet_pubkey, &token_account, &holding_account).await?;
+    let mint = load_mint(client, snapshot.mint).await?;
+
+    let rotation = Rotation {
+        state: &state,
+        headers: &headers,
+        wallet: wallet_pubkey,
+        token_account,
+        holding_account,
+        mint,
+        key_signatures: payload.key_signatures.as_ref(),
+        new_key_signatures: payload.new_key_signatures.as_ref(),
+    };
+
+    // A custodial rotation only begins on request, so polling after the last
+    // one finished does not drain the account again
+    if payload.new_key_signatures.is_none() {
+        let has_pending_keys = has_pending_custodial_keys(&state, &token_account).await?;
+        if !rotation_in_progress(&snapshot, has_pending_keys) {
+            if !payload.start {
+                return Ok(Json(rotation.completed(&snapshot, Vec::new())));
+            }
+            let (new_elgamal_keypair, _) = pending_custodial_keys(&state, &wallet_pubkey, &token_account).await?;
+            tracing::info!(
+                "Key rotation of {} started, rotating to {}",
+                token_account,
+                new_elgamal_keypair.pubkey()
+            );
+        }
+    }
+
+    // Non-custodial mode: return the next step for the owner's wallet to sign
+    // and submit via /api/tx/submit
+    if payload.mode == SubmissionMode::Unsigned {
+        rotation.settle(&snapshot).await?;
+        let Some(step) = rotation.next_step(&snapshot).await? else {
+            return Ok(Json(rotation.completed(&snapshot, Vec::new())));
+        };
+        let prepared = rotation.prepare(step, &snapshot).await?;
+
+        let recent_blockhash = client
+            .get_latest_blockhash()
+            .await
+            .map_err(ApiError::rpc)?;
+        let payer_pubkey = payer.pubkey();
+
+        let mut step_tx =
+            Transaction::new_with_payer(&prepared.instructions.instructions, Some(&payer_pubkey));
+        step_tx.partial_sign(&[payer.as_ref()], recent_blockhash);
+        let transactions = unsigned_transactions(
+            &state,
+            &wallet_pubkey,
+            &prepared.instructions.proof_contexts,
+            step_tx,
+            recent_blockhash,
+        );
+
+        return Ok(Json(RotateKeyResponse {
+            next_step: Some(step),
+            next_step_detail: Some(prepared.detail),
+            transactions: encode_transactions(&transactions)?,
+            new_elgamal_pubkey: prepared.new_keys.map(|(elgamal_keypair, _)| elgamal_keypair.pubkey().to_string()),
+            ..rotation.response()
+        }));
+    }
+
+    // 4. Run the remaining steps, re-reading on-chain state before each one
+    let mut steps = Vec::new();
+    let mut snapshot = Some(snapshot);
+    for _ in 0..MAX_STEPS {
+        let current = match snapshot.take() {
+            Some(current) => current,
+            None => match rotation.snapshot().await {
+                Ok(current) => current,
+                Err(e) => return rotation.failed(steps, None, e),
+            },
+        };
+        if let Err(e) = rotation.settle(&current).await {
+            return rotation.failed(steps, None, e);
+        }
+
+        let step = match rotation.next_step(&current).await {
+            Ok(Some(step)) => step,
+            Ok(None) => return Ok(Json(rotation.completed(&current, steps))),
+            Err(e) => return rotation.failed(steps, None, e),
+        };
+
+        tracing::info!("Key rotation of {}: {:?}", token_account, step);
+        let prepared = match rotation.prepare(step, &current).await {
+            Ok(prepared) => prepared,
+            Err(e) => return rotation.failed(steps, Some(step), e),
+        };
+
+        let (result, unclosed_proof_accounts) =
+            submit_step(client, payer, &token_account, &prepared).await;
+        let signature = match result {
+            Ok(signature) => signature,
+            Err(e) => return rotation.failed(steps, Some(step), e),
+        };
+        tracing::info!("Key rotation of {}: {:?} confirmed: {}", token_account, step, signature);
+
+        steps.push(RotationStepResult {
+            step,
+            signature: signature.to_string(),
+            detail: prepared.detail,
+            unclosed_proof_accounts: unclosed_proof_accounts.iter().map(ToString::to_string).collect(),
+        });
+
+        if step == RotationStep::Restore {
+            let new_elgamal_pubkey = prepared
+                .new_keys
+                .as_ref()
+                .map(|(elgamal_keypair, _)| elgamal_keypair.pubkey().to_string());
+
+            // Keep the auto-apply worker on the account, now under the new keys
+            if let Some((elgamal_keypair, aes_key)) = prepared.new_keys {
+                if state.auto_apply.is_registered(&token_account) {
+                    if let Err(e) =
+                        auto_apply::register(&state, token_account, elgamal_keypair, aes_key).await
+                    {
+                        tracing::warn!("Failed to save the new auto-apply keys of {}: {}", token_account, e);
+                    }
+                }
+            }
+
+            tracing::info!("Key rotation of {} completed", token_account);
+
+            return Ok(Json(RotateKeyResponse {
+                completed: true,
+                steps,
+                new_elgamal_pubkey,
+                ..rotation.response()
+            }));
+        }
+    }
+
+    let e = ApiError::internal("Key rotation did not finish within the step limit", MAX_STEPS);
+    rotation.failed(steps, None, e)
+}
+
+/// A key rotation of one associated token account
+struct Rotation<'a> {
+    state: &'a AppState,
+    /// Authorize the stored keys of custodial accounts
+    headers: &'a HeaderMap,
+    wallet: Pubkey,
+    token_account: Pubkey,
+    holding_account: Pubkey,
+    mint: RotationMint,
+    key_signatures: Option<&'a KeySignatures>,
+    new_key_signatures: Option<&'a KeySignatures>,
+}
+
+/// Where a rotation stands on chain
+struct Snapshot {
+    mint: Pubkey,
+    /// Public balance and confidential transfer extension of the token
+    /// account, if it exists; the extension is missing until it is configured
+    token_account: Option<(u64, Option<ConfidentialTransferAccount>)>,
+    /// Public balance parked in the holding account, if it exists
+    parked: Option<u64>,
+}
+
+impl Snapshot {
+    fn extension(&self) -> Option<&ConfidentialTransferAccount> {
+        self.token_account.as_ref().and_then(|(_, extension)| extension.as_ref())
+    }
+}
+
+/// The mint details a rotation needs
+struct RotationMint {
+    pubkey: Pubkey,
+    decimals: u8,
+    extension_types: Vec<ExtensionType>,
+    /// Confidential transfer authority, which approves the recreated account
+    authority: Option<Pubkey>,
+}
+
+/// Instructions of one rotation step
+struct PreparedStep {
+    instructions: PreparedInstructions,
+    /// What the step does, for the progress report
+    detail: String,
+    /// Whether the step applies pending credits (checked after confirmation)
+    applies_pending: bool,
+    /// The new keys, once the step uses them
+    new_keys: Option<(ElGamalKeypair, AeKey)>,
+}
+
+impl Rotation<'_> {
+    async fn snapshot(&self) -> Result<Snapshot, ApiError> {
+        let snapshot =
+            read_snapshot(&self.state.rpc, &self.wallet, &self.token_account, &self.holding_account).await?;
+        if snapshot.mint != self.mint.pubkey {
+            return Err(ApiError::invalid_account(
+                self.token_account,
+                format!("mint changed to {} during rotation", snapshot.mint),
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    /// The next step to run, or `None` once the rotation is complete
+    async fn next_step(&self, snapshot: &Snapshot) -> Result<Option<RotationStep>, ApiError> {
+        // With the new key known, a finished rotation is recognisable
+        let new_elgamal_pubkey = match self.new_key_signatures {
+            Some(signatures) => {
+                let (new_elgamal_keypair, _) = derive_user_keys(&self.wallet, &self.token_account, signatures)?;
+                Some(PodElGamalPubkey::from(*new_elgamal_keypair.pubkey()))
+            }
+            None => None,
+        };
+
+        match select_step(&self.token_account, snapshot, new_elgamal_pubkey.as_ref())? {
+            Selection::Done => Ok(None),
+            Selection::Step(step) => Ok(Some(step)),
+            Selection::Drain(extension) => {
+                let (_, aes_key) = self.old_keys(extension).await?;
+                let available_balance = decrypt_balance(&aes_key, &extension.decryptable_available_balance)?;
+                Ok(Some(drain_step(extension, available_balance)))
+            }
+        }
+    }
+
+    /// Make a custodial account's pending keys current once the account is
+    /// configured with them, that is once the reconfigure step has landed
+    async fn settle(&self, snapshot: &Snapshot) -> Result<(), ApiError> {
+        match (self.new_key_signatures, snapshot.extension()) {
+            (None, Some(extension)) => promote_pending_keys(self.state, &self.token_account, extension).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Build the instructions of `step`; the owner signs them
+    async fn prepare(&self, step: RotationStep, snapshot: &Snapshot) -> Result<PreparedStep, ApiError> {
+        let client = &self.state.rpc;
+        let payer_pubkey = self.state.payer.pubkey();
+        let token_program = token_2022_program_id();
+
+        let configured = || {
+            snapshot.extension().ok_or_else(|| {
+                ApiError::invalid_account(self.token_account, "confidential transfers not configured")
+            })
+        };
+
+        let prepared = match step {
+            RotationStep::ApplyPending => {
+                let extension = configured()?;
+                let (elgamal_keypair, aes_key) = self.old_keys(extension).await?;
+                let PendingApply {
+                    instruction,
+                    pending_balance_credit_counter,
+                    new_available_balance,
+                } = prepare_apply_pending(
+                    &self.token_account,
+                    &self.wallet,
+                    extension,
+                    elgamal_keypair.secret(),
+                    &aes_key,
+                )?;
+
+                PreparedStep {
+                    instructions: PreparedInstructions {
+                        proof_contexts: Vec::new(),
+                        instructions: vec![instruction],
+                    },
+                    detail: format!(
+                        "apply {} pending credit(s) for an available balance of {}",
+                        pending_balance_credit_counter, new_available_balance
+                    ),
+                    applies_pending: true,
+                    new_keys: None,
+                }
+            }
+            RotationStep::Withdraw => {
+                let extension = configured()?;
+                let (elgamal_keypair, aes_key) = self.old_keys(extension).await?;
+                let amount = decrypt_balance(&aes_key, &extension.decryptable_available_balance)?;
+
+                PreparedStep {
+                    instructions: prepare_withdraw(
+                        client,
+                        &payer_pubkey,
+                        &self.token_account,
+                        &self.wallet,
+                        extension,
+                        &self.mint.pubkey,
+                        amount,
+                        self.mint.decimals,
+                        &elgamal_keypair,
+                        &aes_key,
+                    )
+                    .await?,
+                    detail: format!("withdraw {} to the public balance", amount),
+                    applies_pending: false,
+                    new_keys: None,
+                }
+            }
+            RotationStep::Empty => {
+                let extension = configured()?;
+                let (elgamal_keypair, _) = self.old_keys(extension).await?;
+
+                PreparedStep {
+                    instructions: prepare_empty_account(
+                        client,
+                        &payer_pubkey,
+                        &self.token_account,
+                        &self.wallet,
+                        extension,
+                        &elgamal_keypair,
+                    )
+                    .await?,
+                    detail: "prove the available balance is zero and empty the account".to_string(),
+                    applies_pending: false,
+                    new_keys: None,
+                }
+            }
+            RotationStep::Park => {
+                let amount = snapshot.token_account.as_ref().map_or(0, |(amount, _)| *amount);
+
+                // The holding account takes the extensions the mint requires
+                let required_extensions =
+                    ExtensionType::get_required_init_account_extensions(&self.mint.extension_types);
+                let space = ExtensionType::try_calculate_account_len::<Account>(&required_extensions)
+                    .map_err(|e| ApiError::internal("Failed to calculate holding account size", e))?;
+                let rent = client
+                    .get_minimum_balance_for_rent_exemption(space)
+                    .await
+                    .map_err(ApiError::rpc)?;
+
+                let mut instructions = vec![
+                    system_instruction::create_account_with_seed(
+                        &payer_pubkey,
+                        &self.holding_account,
+                        &self.wallet,
+                        &holding_seed(&self.token_account),
+                        rent,
+                        space as u64,
+                        &token_program,
+                    ),
+                    initialize_account3(&token_program, &self.holding_account, &self.mint.pubkey, &self.wallet)
+                        .map_err(|e| ApiError::internal("Failed to create initialize account instruction", e))?,
+                ];
+                if amount > 0 {
+                    instructions.push(self.transfer(&self.token_account, &self.holding_account, amount)?);
+                }
+                // The account is recreated at the payer's expense, so its rent goes there
+                instructions.push(
+                    close_account(&token_program, &self.token_account, &payer_pubkey, &self.wallet, &[])
+                        .map_err(|e| ApiError::internal("Failed to create close account instruction", e))?,
+                );
+
+                PreparedStep {
+                    instructions: PreparedInstructions {
+                        proof_contexts: Vec::new(),
+                        instructions,
+                    },
+                    detail: format!(
+                        "move the public balance of {} to {} and close the token account",
+                        amount, self.holding_account
+                    ),
+                    applies_pending: false,
+                    new_keys: None,
+                }
+            }
+            RotationStep::Reconfigure => {
+                let (elgamal_keypair, aes_key) = match self.new_key_signatures {
+                    Some(signatures) => derive_user_keys(&self.wallet, &self.token_account, signatures)?,
+                    None => pending_custodial_keys(self.state, &self.wallet, &self.token_account).await?,
+                };
+
+                let instructions = confidential_account_instructions(
+                    &payer_pubkey,
+                    &self.wallet,
+                    &self.mint.pubkey,
+                    &self.token_account,
+                    &elgamal_keypair,
+                    &aes_key,
+                    snapshot.token_account.is_none(),
+                )?;
+
+                PreparedStep {
+                    instructions: PreparedInstructions {
+                        proof_contexts: Vec::new(),
+                        instructions,
+                    },
+                    detail: format!(
+                        "recreate the token account with ElGamal pubkey {}",
+                        elgamal_keypair.pubkey()
+                    ),
+                    applies_pending: false,
+                    new_keys: Some((elgamal_keypair, aes_key)),
+                }
+            }
+            RotationStep::Restore => {
+                let extension = configured()?;
+                let amount = snapshot.parked.unwrap_or_default();
+                let (elgamal_keypair, aes_key) = self.new_keys(extension).await?;
+
+                let mut instructions = Vec::new();
+
+                // Deposits need an approved account; the payer can approve it
+                // when it is the mint's confidential transfer authority
+                if !bool::from(extension.approved) {
+                    match self.mint.authority {
+                        Some(authority) if authority == payer_pubkey => instructions.push(
+                            approve_account(&token_program, &self.token_account, &self.mint.pubkey, &authority, &[])
+                                .map_err(|e| ApiError::internal("Failed to create approve account instruction", e))?,
+                        ),
+                        _ => {
+                            return Err(ApiError::invalid_account(
+                                self.token_account,
+                                "not approved by the mint's confidential transfer authority; \
+                                 approve it via /api/account/approve, then resume",
+                            ));
+                        }
+                    }
+                }
+
+                if amount > 0 {
+                    instructions.push(self.transfer(&self.holding_account, &self.token_account, amount)?);
+                }
+                instructions.push(
+                    close_account(&token_program, &self.holding_account, &payer_pubkey, &self.wallet, &[])
+                        .map_err(|e| ApiError::internal("Failed to create close account instruction", e))?,
+                );
+                if amount > 0 {
+                    instructions.push(
+                        deposit(
+                            &token_program,
+                            &self.token_account,
+                            &self.mint.pubkey,
+                            amount,
+                            self.mint.decimals,
+                            &self.wallet,
+                            &[],
+                        )
+                        .map_err(|e| ApiError::internal("Failed to create deposit instruction", e))?,
+                    );
+                }
+
+                // Apply the deposit, and anything credited since the account
+                // was recreated, in the same transaction
+                let pending_balance_credit_counter =
+                    u64::from(extension.pending_balance_credit_counter) + u64::from(amount > 0);
+                let applies_pending = pending_balance_credit_counter > 0;
+                if applies_pending {
+                    let (available_balance, _) = apply_pending_to_available(
+                        &aes_key,
+                        elgamal_keypair.secret(),
+                        &extension.decryptable_available_balance,
+                        &extension.pending_balance_lo,
+                        &extension.pending_balance_hi,
+                    )?;
+                    let new_available_balance = available_balance
+                        .checked_add(amount)
+                        .ok_or(BalanceDecryptionError::Overflow)?;
+
+                    instructions.push(
+                        apply_pending_balance(
+                            &token_program,
+                            &self.token_account,
+                            pending_balance_credit_counter,
+                            &aes_key.encrypt(new_available_balance).into(),
+                            &self.wallet,
+                            &[],
+                        )
+                        .map_err(|e| ApiError::internal("Failed to create apply pending balance instruction", e))?,
+                    );
+                }
+
+                PreparedStep {
+                    instructions: PreparedInstructions {
+                        proof_contexts: Vec::new(),
+                        instructions,
+                    },
+                    detail: format!("restore {} to the confidential available balance", amount),
+                    applies_pending,
+                    new_keys: Some((elgamal_keypair, aes_key)),
+                }
+            }
+        };
+
+        Ok(prepared)
+    }
+
+    /// The current keys, checked against the account's ElGamal pubkey
+    async fn old_keys(&self, extension: &ConfidentialTransferAccount) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+        let keys = user_keys(self.state, self.headers, &self.wallet, &self.token_account, self.key_signatures).await?;
+        check_keys("key_signatures", &keys.0, extension)?;
+        Ok(keys)
+    }
+
+    /// The keys the account was recreated with, checked against its ElGamal pubkey
+    async fn new_keys(&self, extension: &ConfidentialTransferAccount) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+        let keys = match self.new_key_signatures {
+            Some(signatures) => derive_user_keys(&self.wallet, &self.token_account, signatures)?,
+            None => owned_stored_keys(self.state, self.headers, &self.wallet, &self.token_account)
+                .await?
+                .ok_or_else(|| {
+                    ApiError::invalid_input(
+                        "new_key_signatures",
+                        format!("required: no stored keys for {}", self.token_account),
+                    )
+                })?,
+        };
+        check_keys("new_key_signatures", &keys.0, extension)?;
+        Ok(keys)
+    }
+
+    /// Move public balance between the token and holding accounts
+    fn transfer(
+        &self,
+        source: &Pubkey,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<Instruction, ApiError> {
+        transfer_checked(
+            &token_2022_program_id(),
+            source,
+            &self.mint.pubkey,
+            destination,
+            &self.wallet,
+            &[],
+            amount,
+            self.mint.decimals,
+        )
+        .map_err(|e| ApiError::internal("Failed to create transfer instruction", e))
+    }
+
+    fn response(&self) -> RotateKeyResponse {
+        RotateKeyResponse {
+            success: true,
+            completed: false,
+            steps: Vec::new(),
+            next_step: None,
+            next_step_detail: None,
+            transactions: Vec::new(),
+            holding_account: self.holding_account.to_string(),
+            new_elgamal_pubkey: None,
+            failed_step: None,
+            error_code: None,
+            error: None,
+        }
+    }
+
+    fn completed(&self, snapshot: &Snapshot, steps: Vec<RotationStepResult>) -> RotateKeyResponse {
+        RotateKeyResponse {
+            completed: true,
+            steps,
+            new_elgamal_pubkey: snapshot.extension().map(|extension| extension.elgamal_pubkey.to_string()),
+            ..self.response()
+        }
+    }
+
+    /// Report a failure; once a step has confirmed, the steps so far are
+    /// returned with it rather than lost in an error response
+    fn failed(
+        &self,
+        steps: Vec<RotationStepResult>,
+        failed_step: Option<RotationStep>,
+        error: ApiError,
+    ) -> Result<Json<RotateKeyResponse>, ApiError> {
+        if steps.is_empty() {
+            return Err(error);
+        }
+
+        tracing::warn!(
+            "Key rotation of {} stopped after {} step(s): {}",
+            self.token_account,
+            steps.len(),
+            error
+        );
+
+        Ok(Json(RotateKeyResponse {
+            success: false,
+            steps,
+            failed_step,
+            error_code: Some(error.code()),
+            error: Some(error.to_string()),
+            ..self.response()
+        }))
+    }
+}
+
+/// Where a rotation stands, as far as on-chain state alone tells
+#[derive(Debug)]
+enum Selection<'a> {
+    /// The account is configured with the new key and nothing is parked
+    Done,
+    Step(RotationStep),
+    /// The account is still under the old key with no pending credits; the
+    /// step depends on its available balance, which takes the old keys
+    Drain(&'a ConfidentialTransferAccount),
+}
+
+/// Select the next step from on-chain state; `new_elgamal_pubkey` is the new
+/// key when the request knows it up front (non-custodial accounts)
+fn select_step<'a>(
+    token_account: &Pubkey,
+    snapshot: &'a Snapshot,
+    new_elgamal_pubkey: Option<&PodElGamalPubkey>,
+) -> Result<Selection<'a>, ApiError> {
+    let selection = match (&snapshot.token_account, snapshot.parked) {
+        // Recreated under the new key; the parked balance is not back yet
+        (Some((_, Some(_))), Some(_)) => Selection::Step(RotationStep::Restore),
+        // Closed, or recreated without the configure landing
+        (None, Some(_)) | (Some((_, None)), Some(_)) => Selection::Step(RotationStep::Reconfigure),
+        (Some((_, None)), None) => {
+            return Err(ApiError::invalid_account(
+                *token_account,
+                "confidential transfers not configured",
+            ));
+        }
+        (Some((_, Some(extension))), None) => {
+            if new_elgamal_pubkey == Some(&extension.elgamal_pubkey) {
+                Selection::Done
+            } else if u64::from(extension.pending_balance_credit_counter) > 0 {
+                Selection::Step(RotationStep::ApplyPending)
+            } else {
+                Selection::Drain(extension)
+            }
+        }
+        (None, None) => return Err(ApiError::AccountNotFound(*token_account)),
+    };
+
+    Ok(selection)
+}
+
+/// The step that drains an account still under the old key, given its
+/// decrypted available balance
+fn drain_step(extension: &ConfidentialTransferAccount, available_balance: u64) -> RotationStep {
+    if available_balance > 0 {
+        RotationStep::Withdraw
+    } else if extension.available_balance != PodElGamalCiphertext::default() {
+        RotationStep::Empty
+    } else {
+        RotationStep::Park
+    }
+}
+
+/// New keys for a custodial account being recreated, generated once and kept
+/// pending in the key vault until the account is configured with them
+#[cfg(feature = "database")]
+async fn pending_custodial_keys(
+    state: &AppState,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+    let vault = state.vault.as_ref().ok_or_else(|| {
+        ApiError::invalid_input("new_key_signatures", "required: no key vault is configured")
+    })?;
+    Ok(vault.pending(token_account, wallet).await?)
+}
+
+#[cfg(not(feature = "database"))]
+async fn pending_custodial_keys(
+    _state: &AppState,
+    _wallet: &Pubkey,
+    _token_account: &Pubkey,
+) -> Result<(ElGamalKeypair, AeKey), ApiError> {
+    Err(ApiError::invalid_input(
+        "new_key_signatures",
+        "required: the key vault needs the `database` feature",
+    ))
+}
+
+/// Whether a custodial account has new keys waiting for the account to be
+/// recreated with them
+#[cfg(feature = "database")]
+async fn has_pending_custodial_keys(state: &AppState, token_account: &Pubkey) -> Result<bool, ApiError> {
+    match &state.vault {
+        Some(vault) => Ok(vault.load_pending(token_account).await?.is_some()),
+        None => Ok(false),
+    }
+}
+
+#[cfg(not(feature = "database"))]
+async fn has_pending_custodial_keys(_state: &AppState, _token_account: &Pubkey) -> Result<bool, ApiError> {
+    Ok(false)
+}
+
+/// Whether a custodial rotation is under way: its new keys are pending until
+/// the account is reconfigured, and the balance is parked until it is restored
+fn rotation_in_progress(snapshot: &Snapshot, has_pending_keys: bool) -> bool {
+    has_pending_keys || snapshot.parked.is_some()
+}
+
+/// Make pending keys current if the account is configured with them
+#[cfg(feature = "database")]
+async fn promote_pending_keys(
+    state: &AppState,
+    token_account: &Pubkey,
+    extension: &ConfidentialTransferAccount,
+) -> Result<(), ApiError> {
+    let Some(vault) = &state.vault else {
+        return Ok(());
+    };
+    let Some((elgamal_keypair, _)) = vault.load_pending(token_account).await? else {
+        return Ok(());
+    };
+
+    if PodElGamalPubkey::from(*elgamal_keypair.pubkey()) == extension.elgamal_pubkey
+        && vault.promote_pending(token_account).await?
+    {
+        tracing::info!("Custodial keys of {} rotated to {}", token_account, elgamal_keypair.pubkey());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "database"))]
+async fn promote_pending_keys(
+    _state: &AppState,
+    _token_account: &Pubkey,
+    _extension: &ConfidentialTransferAccount,
+) -> Result<(), ApiError> {
+    Ok(())
+}
+
+/// Create the step's proof context accounts, send its transaction, and
+/// close the proof context accounts again even if a step failed
+async fn submit_step(
+    client: &RpcClient,
+    payer: &Keypair,
+    token_account: &Pubkey,
+    prepared: &PreparedStep,
+) -> (Result<Signature, ApiError>, Vec<Pubkey>) {
+    let mut proof_guard = ProofContextGuard::new(client, payer);
+    let result = async {
+        let recent_blockhash = client
+            .get_latest_blockhash()
+            .await
+            .map_err(ApiError::rpc)?;
+
+        for context in &prepared.instructions.proof_contexts {
+            proof_guard.track(context);
+            for proof_tx in context.transactions(payer, recent_blockhash) {
+                client
+                    .send_and_confirm_transaction(&proof_tx)
+                    .await
+                    .map_err(|e| ApiError::transaction(&proof_tx, e))?;
+            }
+            tracing::info!("{} proof account created: {}", context.label, context.pubkey());
+        }
+
+        let transaction = Transaction::new_signed_with_payer(
+            &prepared.instructions.instructions,
+            Some(&payer.pubkey()),
+            &[payer],
+            recent_blockhash,
+        );
+        let signature = client
+            .send_and_confirm_transaction(&transaction)
+            .await
+            .map_err(|e| ApiError::transaction(&transaction, e))?;
+
+        if prepared.applies_pending {
+            check_pending_applied(client, token_account, &signature).await?;
+        }
+
+        Ok(signature)
+    }
+    .await;
+
+    let unclosed_proof_accounts = proof_guard.close_all().await;
+    (result, unclosed_proof_accounts)
+}
+
+/// Read the token and holding accounts of a rotation
+async fn read_snapshot(
+    client: &RpcClient,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+    holding_account: &Pubkey,
+) -> Result<Snapshot, ApiError> {
+    let check_owner = |account: &Pubkey, state: &Account| {
+        if state.owner != *wallet {
+            return Err(ApiError::invalid_input(
+                "wallet_address",
+                format!("{} does not own {}", wallet, account),
+            ));
+        }
+        Ok(())
+    };
+
+    let mut mint = None;
+
+    let current = match get_account_info(client, token_account).await.map_err(ApiError::rpc)? {
+        Some(account_data) => {
+            let account = StateWithExtensions::<Account>::unpack(&account_data.data)
+                .map_err(|_| ApiError::invalid_account(*token_account, "not a Token-2022 account"))?;
+            check_owner(token_account, &account.base)?;
+            mint = Some(account.base.mint);
+            Some((
+                account.base.amount,
+                account.get_extension::<ConfidentialTransferAccount>().ok().copied(),
+            ))
+        }
+        None => None,
+    };
+
+    let parked = match get_account_info(client, holding_account).await.map_err(ApiError::rpc)? {
+        Some(account_data) => {
+            let account = StateWithExtensions::<Account>::unpack(&account_data.data)
+                .map_err(|_| ApiError::invalid_account(*holding_account, "not a Token-2022 account"))?;
+            check_owner(holding_account, &account.base)?;
+            if mint.is_some_and(|mint| mint != account.base.mint) {
+                return Err(ApiError::invalid_account(
+                    *holding_account,
+                    format!("holds mint {}, not the token account's", account.base.mint),
+                ));
+            }
+            mint = Some(account.base.mint);
+            Some(account.base.amount)
+        }
+        None => None,
+    };
+
+    let mint = mint.ok_or(ApiError::AccountNotFound(*token_account))?;
+
+    // Only an associated token account can be recreated at the same address
+    if get_associated_token_address_with_program_id(wallet, &mint, &token_2022_program_id()) != *token_account {
+        return Err(ApiError::invalid_account(
+            *token_account,
+            "not the owner's associated token account; only those can be rotated",
+        ));
+    }
+
+    Ok(Snapshot {
+        mint,
+        token_account: current,
+        parked,
+    })
+}
+
+/// Read the mint, rejecting extensions that get in the way of moving the
+/// public balance with `transfer_checked`
+async fn load_mint(client: &RpcClient, mint_pubkey: Pubkey) -> Result<RotationMint, ApiError> {
+    let mint_data = fetch_account(client, &mint_pubkey).await?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data.data)
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "not a Token-2022 mint"))?;
+    let extension_types = mint
+        .get_extension_types()
+        .map_err(|e| ApiError::internal("Failed to read mint extensions", e))?;
+
+    if let Some(unsupported) = extension_types.iter().find(|extension_type| {
+        matches!(
+            extension_type,
+            ExtensionType::TransferFeeConfig | ExtensionType::TransferHook | ExtensionType::NonTransferable
+        )
+    }) {
+        return Err(ApiError::invalid_account(
+            mint_pubkey,
+            format!("key rotation does not support mints with {:?}", unsupported),
+        ));
+    }
+
+    let authority = mint
+        .get_extension::<ConfidentialTransferMint>()
+        .ok()
+        .and_then(|ct_mint| Option::<Pubkey>::from(ct_mint.authority));
+
+    Ok(RotationMint {
+        pubkey: mint_pubkey,
+        decimals: mint.base.decimals,
+        extension_types,
+        authority,
+    })
+}
+
+/// Check that keys resolved from `field` belong to the account
+fn check_keys(
+    field: &'static str,
+    elgamal_keypair: &ElGamalKeypair,
+    extension: &ConfidentialTransferAccount,
+) -> Result<(), ApiError> {
+    if PodElGamalPubkey::from(*elgamal_keypair.pubkey()) != extension.elgamal_pubkey {
+        return Err(ApiError::invalid_input(
+            field,
+            "keys do not match the account's ElGamal pubkey",
+        ));
+    }
+    Ok(())
+}
+
+/// Holding account for the public balance while `token_account` is
+/// recreated: a token account at an address derived from the owner's wallet
+fn holding_account(wallet: &Pubkey, token_account: &Pubkey) -> Result<Pubkey, ApiError> {
+    Pubkey::create_with_seed(wallet, &holding_seed(token_account), &token_2022_program_id())
+        .map_err(|e| ApiError::internal("Failed to derive holding account address", e))
+}
+
+/// Seed of the holding account: the token account address, cut to the seed limit
+fn holding_seed(token_account: &Pubkey) -> String {
+    let mut seed = token_account.to_string();
+    seed.truncate(MAX_SEED_LEN);
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    fn snapshot(
+        token_account: Option<(u64, Option<ConfidentialTransferAccount>)>,
+        parked: Option<u64>,
+    ) -> Snapshot {
+        Snapshot {
+            mint: Pubkey::new_unique(),
+            token_account,
+            parked,
+        }
+    }
+
+    fn select<'a>(
+        snapshot: &'a Snapshot,
+        new_elgamal_pubkey: Option<&PodElGamalPubkey>,
+    ) -> Result<Selection<'a>, ApiError> {
+        select_step(&Pubkey::new_unique(), snapshot, new_elgamal_pubkey)
+    }
+
+    fn elgamal_pubkey() -> PodElGamalPubkey {
+        PodElGamalPubkey::from(*ElGamalKeypair::new_rand().pubkey())
+    }
+
+    #[test]
+    fn test_select_step_while_recreating() {
+        let extension = ConfidentialTransferAccount::zeroed();
+
+        assert!(matches!(
+            select(&snapshot(Some((0, Some(extension))), Some(5)), None),
+            Ok(Selection::Step(RotationStep::Restore))
+        ));
+        assert!(matches!(
+            select(&snapshot(None, Some(5)), None),
+            Ok(Selection::Step(RotationStep::Reconfigure))
+        ));
+        assert!(matches!(
+            select(&snapshot(Some((0, None)), Some(0)), None),
+            Ok(Selection::Step(RotationStep::Reconfigure))
+        ));
+
+        assert!(matches!(
+            select(&snapshot(None, None), None),
+            Err(ApiError::AccountNotFound(_))
+        ));
+        assert!(matches!(
+            select(&snapshot(Some((0, None)), None), None),
+            Err(ApiError::InvalidAccount { .. })
+        ));
+    }
+
+    #[test]
+    fn test_select_step_under_old_key() {
+        let mut extension = ConfidentialTransferAccount::zeroed();
+        extension.elgamal_pubkey = elgamal_pubkey();
+        let current = extension.elgamal_pubkey;
+
+        let under_old_key = snapshot(Some((0, Some(extension))), None);
+        assert!(matches!(select(&under_old_key, None), Ok(Selection::Drain(_))));
+        assert!(matches!(select(&under_old_key, Some(&elgamal_pubkey())), Ok(Selection::Drain(_))));
+        // Already configured with the new key
+        assert!(matches!(select(&under_old_key, Some(&current)), Ok(Selection::Done)));
+
+        // Pending credits are applied before anything is decrypted
+        extension.pending_balance_credit_counter = 2.into();
+        assert!(matches!(
+            select(&snapshot(Some((0, Some(extension))), None), None),
+            Ok(Selection::Step(RotationStep::ApplyPending))
+        ));
+    }
+
+    #[test]
+    fn test_custodial_rotation_in_progress() {
+        let extension = ConfidentialTransferAccount::zeroed();
+
+        // Started, the account not yet drained
+        assert!(rotation_in_progress(&snapshot(Some((0, Some(extension))), None), true));
+        // Reconfigured and the keys promoted, the balance still parked
+        assert!(rotation_in_progress(&snapshot(Some((0, Some(extension))), Some(5)), false));
+        // Restored, or never started
+        assert!(!rotation_in_progress(&snapshot(Some((0, Some(extension))), None), false));
+    }
+
+    #[test]
+    fn test_drain_step() {
+        let mut extension = ConfidentialTransferAccount::zeroed();
+        assert_eq!(drain_step(&extension, 10), RotationStep::Withdraw);
+        assert_eq!(drain_step(&extension, 0), RotationStep::Park);
+
+        // A zero balance still has to be proven zero while its ciphertext is set
+        extension.available_balance = ElGamalKeypair::new_rand().pubkey().encrypt(0u64).into();
+        assert_eq!(drain_step(&extension, 0), RotationStep::Empty);
+    }
+}
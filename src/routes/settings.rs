@@ -0,0 +1,151 @@
+use axum::{Json, extract::State};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signer::Signer,
+    transaction::Transaction,
+};
+use spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, StateWithExtensions,
+        confidential_transfer::{ConfidentialTransferAccount, instruction as ct_instruction},
+    },
+    id as token_2022_program_id,
+    state::Account,
+};
+
+use crate::{
+    error::ApiError,
+    models::*,
+    routes::{
+        fetch_account, parse_pubkey,
+        tx::{encode_transactions, require_payer_owner},
+    },
+    state::AppState,
+};
+
+/// Allow the account to receive confidential transfers
+pub async fn enable_confidential_credits(
+    state: State<AppState>,
+    payload: Json<UpdateCreditsRequest>,
+) -> Result<Json<UpdateCreditsResponse>, ApiError> {
+    update_credits(
+        state,
+        payload,
+        "enable confidential credits",
+        ct_instruction::enable_confidential_credits,
+    )
+    .await
+}
+
+/// Reject incoming confidential transfers
+pub async fn disable_confidential_credits(
+    state: State<AppState>,
+    payload: Json<UpdateCreditsRequest>,
+) -> Result<Json<UpdateCreditsResponse>, ApiError> {
+    update_credits(
+        state,
+        payload,
+        "disable confidential credits",
+        ct_instruction::disable_confidential_credits,
+    )
+    .await
+}
+
+/// Allow the account to receive public (non-confidential) transfers
+pub async fn enable_non_confidential_credits(
+    state: State<AppState>,
+    payload: Json<UpdateCreditsRequest>,
+) -> Result<Json<UpdateCreditsResponse>, ApiError> {
+    update_credits(
+        state,
+        payload,
+        "enable non-confidential credits",
+        ct_instruction::enable_non_confidential_credits,
+    )
+    .await
+}
+
+/// Reject incoming public transfers, so the account only receives confidentially
+pub async fn disable_non_confidential_credits(
+    state: State<AppState>,
+    payload: Json<UpdateCreditsRequest>,
+) -> Result<Json<UpdateCreditsResponse>, ApiError> {
+    update_credits(
+        state,
+        payload,
+        "disable non-confidential credits",
+        ct_instruction::disable_non_confidential_credits,
+    )
+    .await
+}
+
+/// Build and send one credit-toggle instruction signed by the account owner
+async fn update_credits<F, E>(
+    State(state): State<AppState>,
+    Json(payload): Json<UpdateCreditsRequest>,
+    action: &'static str,
+    build_instruction: F,
+) -> Result<Json<UpdateCreditsResponse>, ApiError>
+where
+    F: Fn(&Pubkey, &Pubkey, &Pubkey, &[&Pubkey]) -> Result<Instruction, E>,
+    E: std::fmt::Debug,
+{
+    tracing::info!("Request to {} on {}", action, payload.token_account);
+
+    // 1. Parse and validate inputs
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
+
+    let client = &state.rpc;
+    let payer = &state.payer;
+    require_payer_owner(payload.mode, &wallet_pubkey, &payer.pubkey())?;
+
+    // 2. Check the account is configured for confidential transfers and owned by the wallet
+    let account_data = fetch_account(client, &token_account).await?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
+    token_account_data
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
+
+    // 3. Build the transaction (owner must sign)
+    let instruction = build_instruction(&token_2022_program_id(), &token_account, &wallet_pubkey, &[])
+        .map_err(|e| ApiError::internal("Failed to create credit settings instruction", e))?;
+
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+    let recent_blockhash = client
+        .get_latest_blockhash()
+        .await
+        .map_err(ApiError::rpc)?;
+    transaction.partial_sign(&[payer.as_ref()], recent_blockhash);
+
+    // Non-custodial mode: the owner's wallet signs and submits via /api/tx/submit
+    if payload.mode == SubmissionMode::Unsigned {
+        return Ok(Json(UpdateCreditsResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&[transaction])?,
+        }));
+    }
+
+    let signature = client
+        .send_and_confirm_transaction(&transaction)
+        .await
+        .map_err(|e| ApiError::transaction(&transaction, e))?;
+
+    tracing::info!("{} on {}: {}", action, token_account, signature);
+
+    Ok(Json(UpdateCreditsResponse {
+        success: true,
+        signature: Some(signature.to_string()),
+        transactions: Vec::new(),
+    }))
+}
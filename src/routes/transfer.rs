@@ -1,19 +1,24 @@
-use axum::{Json, http::StatusCode};
+use axum::{Json, extract::State, http::HeaderMap};
 use solana_sdk::{
-    pubkey::Pubkey,
-    signature::{Keypair, Signer},
+    signature::Signer,
     transaction::Transaction,
 };
 use spl_token_2022::{
     id as token_2022_program_id,
     solana_zk_sdk::encryption::elgamal::ElGamalPubkey,
 };
-use std::str::FromStr;
 
 use crate::{
-    crypto::{generate_elgamal_keypair, generate_aes_key, generate_transfer_proof},
+    crypto::{generate_transfer_proof, generate_transfer_with_fee_proof},
+    error::ApiError,
     models::*,
-    solana::create_rpc_client,
+    routes::{
+        fetch_account, parse_elgamal_pubkey, parse_pubkey,
+        tx::{encode_transactions, require_payer_owner, unsigned_transactions},
+        user_keys,
+    },
+    solana::{ProofContextGuard, create_proof_context},
+    state::AppState,
 };
 
 /// Execute a confidential transfer between two token accounts
@@ -23,15 +28,28 @@ use crate::{
 /// 2. Ciphertext validity proof - proves encryption is correct
 /// 3. Range proof - proves amount is valid and non-negative
 /// 
+/// Mints with `TransferFeeConfig` need TWO more, and use `transfer_with_fee`:
+/// 4. Fee sigma proof - proves the fee matches the mint's rate and cap
+/// 5. Fee ciphertext validity proof - proves the fee is encrypted correctly
+/// 
 /// Flow:
 /// 1. Get sender's account state
 /// 2. Generate all three proofs
-/// 3. Create proof context state accounts (3 accounts)
+/// 3. Create proof context state accounts (3 or 5 accounts); proofs too large
+///    to verify inline are written to a record account and verified from it
 /// 4. Submit transfer transaction (references proof accounts)
-/// 5. Close proof context accounts (recover rent)
+/// 5. Close proof context accounts (recover rent), even if a step above failed
+///
+/// With `mode: "unsigned"` the transactions for steps 3-5 are returned instead
+/// for the owner to sign; the owner pays for the proof context accounts.
+///
+/// If the mint has an auditor ElGamal pubkey, the amount is also encrypted
+/// under it and the auditor ciphertexts are returned in the response.
 pub async fn confidential_transfer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<TransferRequest>,
-) -> Result<Json<TransferResponse>, StatusCode> {
+) -> Result<Json<TransferResponse>, ApiError> {
     tracing::info!(
         "Confidential transfer: {} tokens from {} to {}",
         payload.amount,
@@ -40,211 +58,338 @@ pub async fn confidential_transfer(
     );
 
     // 1. Parse and validate inputs
-    let sender_wallet = Pubkey::from_str(&payload.sender_wallet)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let sender_token_account = Pubkey::from_str(&payload.sender_token_account)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let recipient_token_account = Pubkey::from_str(&payload.recipient_token_account)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let sender_wallet = parse_pubkey("sender_wallet", &payload.sender_wallet)?;
+    let sender_token_account = parse_pubkey("sender_token_account", &payload.sender_token_account)?;
+    let recipient_token_account = parse_pubkey("recipient_token_account", &payload.recipient_token_account)?;
     
     // Parse recipient ElGamal public key
-    let recipient_elgamal_pubkey_bytes = bs58::decode(&payload.recipient_elgamal_pubkey)
-        .into_vec()
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let recipient_elgamal_pubkey = ElGamalPubkey::from_bytes(&recipient_elgamal_pubkey_bytes)
-        .ok_or(StatusCode::BAD_REQUEST)?;
+    let recipient_elgamal_pubkey =
+        parse_elgamal_pubkey("recipient_elgamal_pubkey", &payload.recipient_elgamal_pubkey)?;
 
     // 2. Get RPC client
-    let client = create_rpc_client();
+    let client = &state.rpc;
 
-    // 3. Load payer keypair
-    let payer = load_payer_keypair().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 3. Get the fee payer loaded at startup
+    let payer = &state.payer;
+    require_payer_owner(payload.mode, &sender_wallet, &payer.pubkey())?;
 
     // 4. Get sender's account state
-    let sender_account_data = client
-        .get_account(&sender_token_account)
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let sender_account_data = fetch_account(client, &sender_token_account).await?;
 
     // 5. Parse the ConfidentialTransferAccount extension
-    use spl_token_2022::extension::{
-        BaseStateWithExtensions,
-        confidential_transfer::{ConfidentialTransferAccount, account_info::TransferAccountInfo},
+    use spl_token_2022::{
+        extension::{
+            BaseStateWithExtensions, StateWithExtensions,
+            confidential_transfer::{
+                ConfidentialTransferAccount, ConfidentialTransferMint,
+                account_info::TransferAccountInfo,
+                instruction::{transfer, transfer_with_fee},
+            },
+            confidential_transfer_fee::ConfidentialTransferFeeConfig,
+            transfer_fee::TransferFeeConfig,
+        },
+        solana_zk_sdk::{
+            encryption::pod::elgamal::PodElGamalPubkey,
+            zk_elgamal_proof_program::instruction::ProofInstruction,
+        },
+        state::{Account, Mint},
     };
-    
-    let token_account_data = spl_token_2022::state::Account::unpack(&sender_account_data.data)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+
+    let token_account_data = StateWithExtensions::<Account>::unpack(&sender_account_data.data)
+        .map_err(|_| ApiError::invalid_account(sender_token_account, "not a Token-2022 account"))?;
     
     let ct_extension = token_account_data
         .get_extension::<ConfidentialTransferAccount>()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| ApiError::invalid_account(sender_token_account, "confidential transfers not configured"))?;
+
+    if token_account_data.base.owner != sender_wallet {
+        return Err(ApiError::invalid_input(
+            "sender_wallet",
+            format!("{} does not own {}", sender_wallet, sender_token_account),
+        ));
+    }
+
+    // 6. Read the mint's auditor ElGamal pubkey (if one is configured)
+    let mint_pubkey = token_account_data.base.mint;
+    let mint_data = fetch_account(client, &mint_pubkey).await?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data.data)
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "not a Token-2022 mint"))?;
+    let ct_mint = mint
+        .get_extension::<ConfidentialTransferMint>()
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "confidential transfers not enabled on mint"))?;
+
+    let auditor_elgamal_pubkey = Option::<PodElGamalPubkey>::from(ct_mint.auditor_elgamal_pubkey)
+        .map(ElGamalPubkey::try_from)
+        .transpose()
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "invalid auditor ElGamal pubkey"))?;
 
-    // 6. Generate sender's ElGamal and AES keys (deterministically)
-    let sender_user_wallet = Keypair::new(); // In production, from user's signature
-    let sender_elgamal = generate_elgamal_keypair(&sender_user_wallet, &sender_token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let sender_aes = generate_aes_key(&sender_user_wallet, &sender_token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 7. Load the sender's ElGamal and AES keys (wallet signatures or key vault)
+    let (sender_elgamal, sender_aes) =
+        user_keys(&state, &headers, &sender_wallet, &sender_token_account, payload.key_signatures.as_ref()).await?;
 
-    // 7. Create TransferAccountInfo from extension data
+    // 8. Create TransferAccountInfo from extension data
     let transfer_account_info = TransferAccountInfo::new(ct_extension);
+    let new_decryptable_available_balance = transfer_account_info
+        .new_decryptable_available_balance(payload.amount, &sender_aes)
+        .map_err(|_| ApiError::invalid_input("amount", "exceeds available confidential balance"))?
+        .into();
 
-    // 8. Generate transfer proofs (all 3 at once)
-    let transfer_proof_data = generate_transfer_proof(
-        &transfer_account_info,
-        payload.amount,
-        &sender_elgamal,
-        &sender_aes,
-        &recipient_elgamal_pubkey,
-        None, // No auditor
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // 9. Create proof context state accounts
-    use spl_token_confidential_transfer_proof_extraction::instruction::ProofInstruction;
-    
-    // Create keypairs for the three proof accounts
-    let equality_proof_keypair = Keypair::new();
-    let ciphertext_proof_keypair = Keypair::new();
-    let range_proof_keypair = Keypair::new();
-
-    // 10. Create equality proof context account
-    tracing::info!("Creating equality proof context account...");
-    let create_equality_ix = ProofInstruction::VerifyBatchedProof
-        .encode_verify_proof(
-            Some(&equality_proof_keypair.pubkey()),
-            &transfer_proof_data.equality_proof_data,
-        )
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut eq_tx = Transaction::new_with_payer(
-        &create_equality_ix,
-        Some(&payer.pubkey()),
-    );
-    let recent_blockhash = client.get_latest_blockhash().await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    eq_tx.sign(&[&payer, &equality_proof_keypair], recent_blockhash);
-    
-    let eq_sig = client.send_and_confirm_transaction(&eq_tx).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    tracing::info!("Equality proof account created: {}", eq_sig);
-
-    // 11. Create ciphertext validity proof context account
-    tracing::info!("Creating ciphertext validity proof context account...");
-    let create_ciphertext_ix = ProofInstruction::VerifyBatchedProof
-        .encode_verify_proof(
-            Some(&ciphertext_proof_keypair.pubkey()),
-            &transfer_proof_data.ciphertext_validity_proof_data,
-        )
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut ct_tx = Transaction::new_with_payer(
-        &create_ciphertext_ix,
-        Some(&payer.pubkey()),
-    );
-    ct_tx.sign(&[&payer, &ciphertext_proof_keypair], recent_blockhash);
-    
-    let ct_sig = client.send_and_confirm_transaction(&ct_tx).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    tracing::info!("Ciphertext validity proof account created: {}", ct_sig);
-
-    // 12. Create range proof context account
-    tracing::info!("Creating range proof context account...");
-    let create_range_ix = ProofInstruction::VerifyBatchedProof
-        .encode_verify_proof(
-            Some(&range_proof_keypair.pubkey()),
-            &transfer_proof_data.range_proof_data,
-        )
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut range_tx = Transaction::new_with_payer(
-        &create_range_ix,
-        Some(&payer.pubkey()),
-    );
-    range_tx.sign(&[&payer, &range_proof_keypair], recent_blockhash);
-    
-    let range_sig = client.send_and_confirm_transaction(&range_tx).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    tracing::info!("Range proof account created: {}", range_sig);
+    // 9. Detect the transfer-fee extensions on the mint
+    let fee_parameters = match mint.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => {
+            let ct_fee_config = mint
+                .get_extension::<ConfidentialTransferFeeConfig>()
+                .map_err(|_| ApiError::invalid_account(mint_pubkey, "transfer fee mint lacks ConfidentialTransferFeeConfig"))?;
+            let withheld_authority_elgamal_pubkey =
+                ElGamalPubkey::try_from(ct_fee_config.withdraw_withheld_authority_elgamal_pubkey)
+                    .map_err(|_| ApiError::invalid_account(mint_pubkey, "invalid withdraw-withheld authority ElGamal pubkey"))?;
 
-    // 13. Now execute the actual transfer with proof references
-    use spl_token_2022::instruction::transfer_confidential;
-    
-    let transfer_ix = transfer_confidential(
-        &token_2022_program_id(),
-        &sender_token_account,
-        &recipient_token_account,
-        &sender_wallet,
-        Some(&equality_proof_keypair.pubkey()),
-        Some(&ciphertext_proof_keypair.pubkey()),
-        Some(&range_proof_keypair.pubkey()),
-        payload.amount,
-        None, // No auditor
-        &sender_elgamal,
-        &sender_aes,
-        &recipient_elgamal_pubkey,
-        None, // No auditor pubkey
-        &[],
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let epoch = client.get_epoch_info().await.map_err(ApiError::rpc)?.epoch;
+            let transfer_fee = fee_config.get_epoch_fee(epoch);
+            let fee_rate_basis_points = u16::from(transfer_fee.transfer_fee_basis_points);
+            let maximum_fee = u64::from(transfer_fee.maximum_fee);
+            let fee = fee_config
+                .calculate_epoch_fee(epoch, payload.amount)
+                .ok_or_else(|| ApiError::invalid_input("amount", "transfer fee overflow"))?;
+
+            Some((withheld_authority_elgamal_pubkey, fee_rate_basis_points, maximum_fee, fee))
+        }
+        Err(_) => None,
+    };
+
+    // 10. Generate proofs and verify each into its own context state account
+    // (3 proofs for a plain transfer, 5 when the mint charges a transfer fee)
+    let payer_pubkey = payer.pubkey();
+    let (proof_contexts, transfer_ixs, fee, auditor_ciphertexts) = match fee_parameters {
+        None => {
+            let transfer_proof_data = generate_transfer_proof(
+                &transfer_account_info,
+                payload.amount,
+                &sender_elgamal,
+                &sender_aes,
+                &recipient_elgamal_pubkey,
+                auditor_elgamal_pubkey.as_ref(),
+            )
+            .map_err(|e| ApiError::ProofGeneration(e.to_string()))?;
+            let with_ciphertext = &transfer_proof_data.ciphertext_validity_proof_data_with_ciphertext;
+
+            let equality = create_proof_context(
+                client,
+                &payer_pubkey,
+                "equality",
+                ProofInstruction::VerifyCiphertextCommitmentEquality,
+                &transfer_proof_data.equality_proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+            let ciphertext_validity = create_proof_context(
+                client,
+                &payer_pubkey,
+                "ciphertext validity",
+                ProofInstruction::VerifyBatchedGroupedCiphertext3HandlesValidity,
+                &with_ciphertext.proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+            let range = create_proof_context(
+                client,
+                &payer_pubkey,
+                "range",
+                ProofInstruction::VerifyBatchedRangeProofU128,
+                &transfer_proof_data.range_proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+
+            // Build the actual transfer with proof references (owner must sign)
+            let transfer_ixs = transfer(
+                &token_2022_program_id(),
+                &sender_token_account,
+                &mint_pubkey,
+                &recipient_token_account,
+                &new_decryptable_available_balance,
+                &with_ciphertext.ciphertext_lo,
+                &with_ciphertext.ciphertext_hi,
+                &sender_wallet,
+                &[],
+                ProofLocation::ContextStateAccount(&equality.pubkey()),
+                ProofLocation::ContextStateAccount(&ciphertext_validity.pubkey()),
+                ProofLocation::ContextStateAccount(&range.pubkey()),
+            )
+            .map_err(|e| ApiError::internal("Failed to create transfer instruction", e))?;
 
+            let auditor_ciphertexts = (with_ciphertext.ciphertext_lo, with_ciphertext.ciphertext_hi);
+            (vec![equality, ciphertext_validity, range], transfer_ixs, None, auditor_ciphertexts)
+        }
+        Some((withheld_authority_elgamal_pubkey, fee_rate_basis_points, maximum_fee, fee)) => {
+            let transfer_proof_data = generate_transfer_with_fee_proof(
+                &transfer_account_info,
+                payload.amount,
+                &sender_elgamal,
+                &sender_aes,
+                &recipient_elgamal_pubkey,
+                auditor_elgamal_pubkey.as_ref(),
+                &withheld_authority_elgamal_pubkey,
+                fee_rate_basis_points,
+                maximum_fee,
+            )
+            .map_err(|e| ApiError::ProofGeneration(e.to_string()))?;
+            let with_ciphertext =
+                &transfer_proof_data.transfer_amount_ciphertext_validity_proof_data_with_ciphertext;
+
+            let equality = create_proof_context(
+                client,
+                &payer_pubkey,
+                "equality",
+                ProofInstruction::VerifyCiphertextCommitmentEquality,
+                &transfer_proof_data.equality_proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+            let ciphertext_validity = create_proof_context(
+                client,
+                &payer_pubkey,
+                "ciphertext validity",
+                ProofInstruction::VerifyBatchedGroupedCiphertext3HandlesValidity,
+                &with_ciphertext.proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+            let fee_sigma = create_proof_context(
+                client,
+                &payer_pubkey,
+                "fee sigma",
+                ProofInstruction::VerifyPercentageWithCap,
+                &transfer_proof_data.percentage_with_cap_proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+            let fee_ciphertext_validity = create_proof_context(
+                client,
+                &payer_pubkey,
+                "fee ciphertext validity",
+                ProofInstruction::VerifyBatchedGroupedCiphertext2HandlesValidity,
+                &transfer_proof_data.fee_ciphertext_validity_proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+            let range = create_proof_context(
+                client,
+                &payer_pubkey,
+                "range",
+                ProofInstruction::VerifyBatchedRangeProofU256,
+                &transfer_proof_data.range_proof_data,
+            )
+            .await
+            .map_err(ApiError::rpc)?;
+
+            // Build the transfer-with-fee with proof references (owner must sign)
+            let transfer_ixs = transfer_with_fee(
+                &token_2022_program_id(),
+                &sender_token_account,
+                &mint_pubkey,
+                &recipient_token_account,
+                &new_decryptable_available_balance,
+                &with_ciphertext.ciphertext_lo,
+                &with_ciphertext.ciphertext_hi,
+                &sender_wallet,
+                &[],
+                ProofLocation::ContextStateAccount(&equality.pubkey()),
+                ProofLocation::ContextStateAccount(&ciphertext_validity.pubkey()),
+                ProofLocation::ContextStateAccount(&fee_sigma.pubkey()),
+                ProofLocation::ContextStateAccount(&fee_ciphertext_validity.pubkey()),
+                ProofLocation::ContextStateAccount(&range.pubkey()),
+            )
+            .map_err(|e| ApiError::internal("Failed to create transfer with fee instruction", e))?;
+
+            let auditor_ciphertexts = (with_ciphertext.ciphertext_lo, with_ciphertext.ciphertext_hi);
+            (
+                vec![equality, ciphertext_validity, fee_sigma, fee_ciphertext_validity, range],
+                transfer_ixs,
+                Some(fee),
+                auditor_ciphertexts,
+            )
+        }
+    };
+
+    // Amount encrypted under the auditor key, returned so auditors can match the transfer
+    let (auditor_ciphertext_lo, auditor_ciphertext_hi) = match auditor_elgamal_pubkey {
+        Some(_) => (
+            Some(auditor_ciphertexts.0.to_string()),
+            Some(auditor_ciphertexts.1.to_string()),
+        ),
+        None => (None, None),
+    };
+
+    let recent_blockhash = client.get_latest_blockhash().await
+        .map_err(ApiError::rpc)?;
+
+    // 11. Build the transfer transaction (owner must sign)
     let mut transfer_tx = Transaction::new_with_payer(
-        &[transfer_ix],
-        Some(&payer.pubkey()),
+        &transfer_ixs,
+        Some(&payer_pubkey),
     );
-    transfer_tx.sign(&[&payer], recent_blockhash);
-    
-    let transfer_sig = client.send_and_confirm_transaction(&transfer_tx).await
-        .map_err(|e| {
-            tracing::error!("Transfer transaction failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    transfer_tx.partial_sign(&[payer.as_ref()], recent_blockhash);
 
-    tracing::info!("Confidential transfer successful: {}", transfer_sig);
+    // Non-custodial mode: the owner's wallet signs everything and submits it
+    // in order via /api/tx/submit, paying for the proof context accounts; the
+    // server closes them afterwards and returns the rent
+    if payload.mode == SubmissionMode::Unsigned {
+        let transactions =
+            unsigned_transactions(&state, &sender_wallet, &proof_contexts, transfer_tx, recent_blockhash);
 
-    // 14. Close proof context accounts to recover rent
-    use spl_token_2022::instruction::close_context_state;
-    
-    // Close equality proof account
-    let close_eq_ix = close_context_state(
-        &equality_proof_keypair.pubkey(),
-        &sender_token_account,
-        &payer.pubkey(),
-    );
-    let mut close_eq_tx = Transaction::new_with_payer(&[close_eq_ix], Some(&payer.pubkey()));
-    close_eq_tx.sign(&[&payer], recent_blockhash);
-    client.send_and_confirm_transaction(&close_eq_tx).await.ok();
-
-    // Close ciphertext proof account
-    let close_ct_ix = close_context_state(
-        &ciphertext_proof_keypair.pubkey(),
-        &sender_token_account,
-        &payer.pubkey(),
-    );
-    let mut close_ct_tx = Transaction::new_with_payer(&[close_ct_ix], Some(&payer.pubkey()));
-    close_ct_tx.sign(&[&payer], recent_blockhash);
-    client.send_and_confirm_transaction(&close_ct_tx).await.ok();
-
-    // Close range proof account
-    let close_range_ix = close_context_state(
-        &range_proof_keypair.pubkey(),
-        &sender_token_account,
-        &payer.pubkey(),
-    );
-    let mut close_range_tx = Transaction::new_with_payer(&[close_range_ix], Some(&payer.pubkey()));
-    close_range_tx.sign(&[&payer], recent_blockhash);
-    client.send_and_confirm_transaction(&close_range_tx).await.ok();
+        return Ok(Json(TransferResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&transactions)?,
+            fee,
+            auditor_ciphertext_lo,
+            auditor_ciphertext_hi,
+            unclosed_proof_accounts: Vec::new(),
+        }));
+    }
+
+    // 12. Build the transactions for each proof context account (more than
+    // one when the proof is written to a record account first)
+    let proof_txs: Vec<Vec<Transaction>> = proof_contexts
+        .iter()
+        .map(|context| context.transactions(payer, recent_blockhash))
+        .collect();
 
-    tracing::info!("Proof accounts closed, rent recovered");
+    // 13. Create the proof context accounts and execute the transfer. The guard
+    // tracks every account created, so they are closed even if a step fails
+    let mut proof_guard = ProofContextGuard::new(client, payer.as_ref());
+    let result = async {
+        for (context, context_txs) in proof_contexts.iter().zip(&proof_txs) {
+            tracing::info!("Creating {} proof context account...", context.label);
+            proof_guard.track(context);
+            for proof_tx in context_txs {
+                let sig = client.send_and_confirm_transaction(proof_tx).await
+                    .map_err(|e| ApiError::transaction(proof_tx, e))?;
+                tracing::debug!("{} proof transaction confirmed: {}", context.label, sig);
+            }
+            tracing::info!("{} proof account created: {}", context.label, context.pubkey());
+        }
+
+        client.send_and_confirm_transaction(&transfer_tx).await
+            .map_err(|e| ApiError::transaction(&transfer_tx, e))
+    }
+    .await;
+
+    // 14. Close proof context accounts (recover rent), then surface any failure
+    let unclosed_proof_accounts = proof_guard.close_all().await;
+    let transfer_sig = result?;
+
+    tracing::info!("Confidential transfer successful: {}", transfer_sig);
 
     Ok(Json(TransferResponse {
         success: true,
-        signature: transfer_sig.to_string(),
-        error: None,
+        signature: Some(transfer_sig.to_string()),
+        transactions: Vec::new(),
+        fee,
+        auditor_ciphertext_lo,
+        auditor_ciphertext_hi,
+        unclosed_proof_accounts: unclosed_proof_accounts.iter().map(ToString::to_string).collect(),
     }))
 }
-
-// Helper function to load payer keypair
-fn load_payer_keypair() -> anyhow::Result<Keypair> {
-    Ok(Keypair::new())
-}
\ No newline at end of file
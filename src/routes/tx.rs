@@ -0,0 +1,126 @@
+use axum::{Json, extract::State};
+use solana_sdk::{hash::Hash, pubkey::Pubkey, transaction::Transaction};
+
+use crate::{
+    error::ApiError,
+    models::*,
+    routes::deposit::{applied_token_accounts, check_pending_applied},
+    solana::{ProofContextAccount, decode_transaction, encode_transaction},
+    state::AppState,
+};
+
+/// Submit transactions signed by the user's wallet
+///
+/// Used with `mode: "unsigned"`: the operation endpoints return the prepared
+/// transactions, the wallet adds the owner signature, and this endpoint
+/// broadcasts them in the order given, stopping at the first failure. The
+/// proof context accounts they used are closed afterwards either way.
+///
+/// A confirmed apply pending balance is checked like in submit mode: a
+/// credit that raced it is reported as a failure (see `send_apply_pending`).
+pub async fn submit_transactions(
+    State(state): State<AppState>,
+    Json(payload): Json<SubmitTransactionsRequest>,
+) -> Result<Json<SubmitTransactionsResponse>, ApiError> {
+    tracing::info!("Submitting {} signed transactions", payload.transactions.len());
+
+    if payload.transactions.is_empty() {
+        return Err(ApiError::invalid_input("transactions", "no transactions provided"));
+    }
+
+    // 1. Decode and check every transaction before broadcasting any of them
+    let transactions = payload
+        .transactions
+        .iter()
+        .map(|encoded| decode_transaction(encoded))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| ApiError::invalid_input("transactions", e))?;
+
+    if let Some(index) = transactions.iter().position(|tx| tx.verify().is_err()) {
+        return Err(ApiError::invalid_input(
+            "transactions",
+            format!("transaction {} is missing signatures", index),
+        ));
+    }
+
+    // 2. Broadcast in order
+    let client = &state.rpc;
+    let mut signatures = Vec::with_capacity(transactions.len());
+
+    let result: Result<(), ApiError> = async {
+        for transaction in &transactions {
+            let signature = client
+                .send_and_confirm_transaction(transaction)
+                .await
+                .map_err(|e| ApiError::transaction(transaction, e))?;
+            signatures.push(signature.to_string());
+
+            for token_account in applied_token_accounts(transaction) {
+                check_pending_applied(client, &token_account, &signature).await?;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    // 3. Close the proof context accounts handed out for these transactions
+    // (recover rent), then surface any failure
+    let unclosed_proof_accounts = state
+        .proof_sweeper
+        .close_used(client, &state.payer, &transactions)
+        .await;
+    result?;
+
+    Ok(Json(SubmitTransactionsResponse {
+        success: true,
+        signatures,
+        unclosed_proof_accounts: unclosed_proof_accounts.iter().map(ToString::to_string).collect(),
+    }))
+}
+
+/// Reject submit mode when the server payer cannot sign for `owner`
+///
+/// Checked before any proof context account is paid for, since the owner's
+/// signature is missing and the operation would fail on chain.
+pub(crate) fn require_payer_owner(mode: SubmissionMode, owner: &Pubkey, payer: &Pubkey) -> Result<(), ApiError> {
+    if mode == SubmissionMode::Submit && owner != payer {
+        return Err(ApiError::invalid_input(
+            "mode",
+            format!("owner {} must sign; use unsigned mode", owner),
+        ));
+    }
+    Ok(())
+}
+
+/// Encode prepared transactions for the user's wallet to sign
+pub(crate) fn encode_transactions(
+    transactions: &[Transaction],
+) -> Result<Vec<String>, ApiError> {
+    transactions
+        .iter()
+        .map(encode_transaction)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| ApiError::internal("Failed to encode transaction", e))
+}
+
+/// Build the transactions of an unsigned-mode operation, in submission order:
+/// the proof context setup, then the operation itself
+///
+/// The owner pays the fees and rent of the proof accounts. The server closes
+/// them and returns the rent to the owner, from /api/tx/submit or, if the
+/// transactions are sent elsewhere or never, once their blockhash expires.
+pub(crate) fn unsigned_transactions(
+    state: &AppState,
+    owner: &Pubkey,
+    proof_contexts: &[ProofContextAccount],
+    operation_tx: Transaction,
+    recent_blockhash: Hash,
+) -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    for context in proof_contexts {
+        state.proof_sweeper.hand_out(context, owner, recent_blockhash);
+        transactions.extend(context.unsigned_transactions(owner, &state.payer, recent_blockhash));
+    }
+    transactions.push(operation_tx);
+    transactions
+}
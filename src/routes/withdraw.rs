@@ -1,16 +1,37 @@
-use axum::{Json, http::StatusCode};
+use axum::{Json, extract::State, http::HeaderMap};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
     pubkey::Pubkey,
-    signature::{Keypair, Signer},
+    signature::Signer,
     transaction::Transaction,
 };
-use spl_token_2022::id as token_2022_program_id;
-use std::str::FromStr;
+use spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, StateWithExtensions,
+        confidential_transfer::{
+            ConfidentialTransferAccount, account_info::WithdrawAccountInfo, instruction::withdraw,
+        },
+    },
+    id as token_2022_program_id,
+    solana_zk_sdk::{
+        encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+        zk_elgamal_proof_program::instruction::ProofInstruction,
+    },
+    state::{Account, Mint},
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
 
 use crate::{
-    crypto::{generate_elgamal_keypair, generate_aes_key, generate_withdraw_proof},
+    crypto::generate_withdraw_proof,
+    error::ApiError,
     models::*,
-    solana::create_rpc_client,
+    routes::{
+        PreparedInstructions, fetch_account, parse_pubkey,
+        tx::{encode_transactions, require_payer_owner, unsigned_transactions},
+        user_keys,
+    },
+    solana::{ProofContextGuard, create_proof_context},
+    state::AppState,
 };
 
 /// Withdraw tokens from confidential available balance to public balance
@@ -25,10 +46,15 @@ use crate::{
 /// 2. Generate withdraw proofs (equality + range)
 /// 3. Create proof context state accounts (2 accounts)
 /// 4. Submit withdraw transaction
-/// 5. Close proof context accounts (recover rent)
+/// 5. Close proof context accounts (recover rent), even if a step above failed
+///
+/// With `mode: "unsigned"` the transactions for steps 3-5 are returned instead
+/// for the owner to sign; the owner pays for the proof context accounts.
 pub async fn withdraw_tokens(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<WithdrawRequest>,
-) -> Result<Json<WithdrawResponse>, StatusCode> {
+) -> Result<Json<WithdrawResponse>, ApiError> {
     tracing::info!(
         "Withdrawing {} tokens from confidential balance for wallet: {}",
         payload.amount,
@@ -36,199 +62,213 @@ pub async fn withdraw_tokens(
     );
 
     // 1. Parse and validate inputs
-    let wallet_pubkey = Pubkey::from_str(&payload.wallet_address)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let token_account = Pubkey::from_str(&payload.token_account)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let wallet_pubkey = parse_pubkey("wallet_address", &payload.wallet_address)?;
+    let token_account = parse_pubkey("token_account", &payload.token_account)?;
 
     // 2. Get RPC client
-    let client = create_rpc_client();
+    let client = &state.rpc;
 
-    // 3. Load payer keypair
-    let payer = load_payer_keypair().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 3. Get the fee payer loaded at startup
+    let payer = &state.payer;
+    require_payer_owner(payload.mode, &wallet_pubkey, &payer.pubkey())?;
 
     // 4. Get account state to read confidential balance
-    let account_data = client
-        .get_account(&token_account)
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let account_data = fetch_account(client, &token_account).await?;
 
     // 5. Parse the ConfidentialTransferAccount extension
-    use spl_token_2022::extension::{
-        BaseStateWithExtensions,
-        confidential_transfer::{ConfidentialTransferAccount, account_info::WithdrawAccountInfo},
-    };
     
-    let token_account_data = spl_token_2022::state::Account::unpack(&account_data.data)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let token_account_data = StateWithExtensions::<Account>::unpack(&account_data.data)
+        .map_err(|_| ApiError::invalid_account(token_account, "not a Token-2022 account"))?;
     
     let ct_extension = token_account_data
         .get_extension::<ConfidentialTransferAccount>()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| ApiError::invalid_account(token_account, "confidential transfers not configured"))?;
 
-    // 6. Generate user's ElGamal and AES keys (deterministically)
-    let user_wallet = Keypair::new(); // In production, from user's signature
-    let elgamal_keypair = generate_elgamal_keypair(&user_wallet, &token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let aes_key = generate_aes_key(&user_wallet, &token_account)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if token_account_data.base.owner != wallet_pubkey {
+        return Err(ApiError::invalid_input(
+            "wallet_address",
+            format!("{} does not own {}", wallet_pubkey, token_account),
+        ));
+    }
 
-    // 7. Create WithdrawAccountInfo from extension data
-    let withdraw_account_info = WithdrawAccountInfo::new(ct_extension);
+    // 6. Read the mint for its decimals
+    let mint_pubkey = token_account_data.base.mint;
+    let mint_data = fetch_account(client, &mint_pubkey).await?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data.data)
+        .map_err(|_| ApiError::invalid_account(mint_pubkey, "not a Token-2022 mint"))?;
+    let decimals = mint.base.decimals;
 
-    // 8. Generate withdraw proofs (equality + range)
-    tracing::info!("Generating withdraw proofs...");
-    let withdraw_proof_data = generate_withdraw_proof(
-        &withdraw_account_info,
+    if payload.decimals.is_some_and(|requested| requested != decimals) {
+        return Err(ApiError::invalid_input(
+            "decimals",
+            format!("mint {} has {} decimals", mint_pubkey, decimals),
+        ));
+    }
+
+    // 7. Load the user's ElGamal and AES keys (wallet signatures or key vault)
+    let (elgamal_keypair, aes_key) =
+        user_keys(&state, &headers, &wallet_pubkey, &token_account, payload.key_signatures.as_ref()).await?;
+
+    // 8. Generate the withdraw proofs (equality + range) and verify each into
+    // its own context state account
+    let payer_pubkey = payer.pubkey();
+    let PreparedInstructions {
+        proof_contexts,
+        instructions: withdraw_ixs,
+    } = prepare_withdraw(
+        client,
+        &payer_pubkey,
+        &token_account,
+        &wallet_pubkey,
+        ct_extension,
+        &mint_pubkey,
         payload.amount,
+        decimals,
         &elgamal_keypair,
         &aes_key,
     )
-    .map_err(|e| {
-        tracing::error!("Failed to generate withdraw proof: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    // 9. Create proof context state accounts
-    use spl_token_confidential_transfer_proof_extraction::instruction::ProofInstruction;
-    
-    // Create keypairs for the two proof accounts
-    let equality_proof_keypair = Keypair::new();
-    let range_proof_keypair = Keypair::new();
-
-    // 10. Create equality proof context account
-    tracing::info!("Creating equality proof context account...");
-    let create_equality_ix = ProofInstruction::VerifyBatchedProof
-        .encode_verify_proof(
-            Some(&equality_proof_keypair.pubkey()),
-            &withdraw_proof_data.equality_proof_data,
-        )
-        .map_err(|e| {
-            tracing::error!("Failed to encode equality proof: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    .await?;
 
     let recent_blockhash = client
         .get_latest_blockhash()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(ApiError::rpc)?;
 
-    let mut eq_tx = Transaction::new_with_payer(
-        &create_equality_ix,
-        Some(&payer.pubkey()),
-    );
-    eq_tx.sign(&[&payer, &equality_proof_keypair], recent_blockhash);
-    
-    let eq_sig = client
-        .send_and_confirm_transaction(&eq_tx)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to create equality proof account: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-    
-    tracing::info!("Equality proof account created: {}", eq_sig);
-
-    // 11. Create range proof context account
-    tracing::info!("Creating range proof context account...");
-    let create_range_ix = ProofInstruction::VerifyBatchedProof
-        .encode_verify_proof(
-            Some(&range_proof_keypair.pubkey()),
-            &withdraw_proof_data.range_proof_data,
-        )
-        .map_err(|e| {
-            tracing::error!("Failed to encode range proof: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let mut range_tx = Transaction::new_with_payer(
-        &create_range_ix,
-        Some(&payer.pubkey()),
+    // 9. Build the withdraw transaction with proof references (owner must sign)
+    let mut withdraw_tx = Transaction::new_with_payer(
+        &withdraw_ixs,
+        Some(&payer_pubkey),
     );
-    range_tx.sign(&[&payer, &range_proof_keypair], recent_blockhash);
-    
-    let range_sig = client
-        .send_and_confirm_transaction(&range_tx)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to create range proof account: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-    
-    tracing::info!("Range proof account created: {}", range_sig);
+    withdraw_tx.partial_sign(&[payer.as_ref()], recent_blockhash);
 
-    // 12. Execute the withdraw transaction with proof references
-    use spl_token_2022::instruction::withdraw_confidential;
-    
-    let withdraw_ix = withdraw_confidential(
-        &token_2022_program_id(),
-        &token_account,
-        &wallet_pubkey,
-        Some(&equality_proof_keypair.pubkey()),
-        Some(&range_proof_keypair.pubkey()),
-        payload.amount,
-        payload.decimals,
-        Some(withdraw_account_info),
-        &elgamal_keypair,
-        &aes_key,
-        &[],
-    )
-    .map_err(|e| {
-        tracing::error!("Failed to create withdraw instruction: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    // Non-custodial mode: the owner's wallet signs everything and submits it
+    // in order via /api/tx/submit, paying for the proof context accounts; the
+    // server closes them afterwards and returns the rent
+    if payload.mode == SubmissionMode::Unsigned {
+        let transactions =
+            unsigned_transactions(&state, &wallet_pubkey, &proof_contexts, withdraw_tx, recent_blockhash);
 
-    let mut withdraw_tx = Transaction::new_with_payer(
-        &[withdraw_ix],
-        Some(&payer.pubkey()),
-    );
-    withdraw_tx.sign(&[&payer], recent_blockhash);
-    
-    let withdraw_sig = client
-        .send_and_confirm_transaction(&withdraw_tx)
-        .await
-        .map_err(|e| {
-            tracing::error!("Withdraw transaction failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        return Ok(Json(WithdrawResponse {
+            success: true,
+            signature: None,
+            transactions: encode_transactions(&transactions)?,
+            unclosed_proof_accounts: Vec::new(),
+        }));
+    }
 
-    tracing::info!("Withdraw successful: {}", withdraw_sig);
+    // 10. Build the transactions for each proof context account (more than
+    // one when the proof is written to a record account first)
+    let proof_txs: Vec<Vec<Transaction>> = proof_contexts
+        .iter()
+        .map(|context| context.transactions(payer, recent_blockhash))
+        .collect();
 
-    // 13. Close proof context accounts to recover rent
-    use spl_token_2022::instruction::close_context_state;
-    
-    tracing::info!("Closing proof context accounts...");
-    
-    // Close equality proof account
-    let close_eq_ix = close_context_state(
-        &equality_proof_keypair.pubkey(),
-        &token_account,
-        &payer.pubkey(),
-    );
-    let mut close_eq_tx = Transaction::new_with_payer(&[close_eq_ix], Some(&payer.pubkey()));
-    close_eq_tx.sign(&[&payer], recent_blockhash);
-    client.send_and_confirm_transaction(&close_eq_tx).await.ok();
+    // 11. Create the proof context accounts and execute the withdraw. The guard
+    // tracks every account created, so they are closed even if a step fails
+    let mut proof_guard = ProofContextGuard::new(client, payer.as_ref());
+    let result = async {
+        for (context, context_txs) in proof_contexts.iter().zip(&proof_txs) {
+            tracing::info!("Creating {} proof context account...", context.label);
+            proof_guard.track(context);
+            for proof_tx in context_txs {
+                let sig = client
+                    .send_and_confirm_transaction(proof_tx)
+                    .await
+                    .map_err(|e| ApiError::transaction(proof_tx, e))?;
+                tracing::debug!("{} proof transaction confirmed: {}", context.label, sig);
+            }
+            tracing::info!("{} proof account created: {}", context.label, context.pubkey());
+        }
 
-    // Close range proof account
-    let close_range_ix = close_context_state(
-        &range_proof_keypair.pubkey(),
-        &token_account,
-        &payer.pubkey(),
-    );
-    let mut close_range_tx = Transaction::new_with_payer(&[close_range_ix], Some(&payer.pubkey()));
-    close_range_tx.sign(&[&payer], recent_blockhash);
-    client.send_and_confirm_transaction(&close_range_tx).await.ok();
+        client
+            .send_and_confirm_transaction(&withdraw_tx)
+            .await
+            .map_err(|e| ApiError::transaction(&withdraw_tx, e))
+    }
+    .await;
 
-    tracing::info!("Proof accounts closed, rent recovered");
+    // 12. Close proof context accounts (recover rent), then surface any failure
+    let unclosed_proof_accounts = proof_guard.close_all().await;
+    let withdraw_sig = result?;
+
+    tracing::info!("Withdraw successful: {}", withdraw_sig);
 
     Ok(Json(WithdrawResponse {
         success: true,
-        signature: withdraw_sig.to_string(),
-        error: None,
+        signature: Some(withdraw_sig.to_string()),
+        transactions: Vec::new(),
+        unclosed_proof_accounts: unclosed_proof_accounts.iter().map(ToString::to_string).collect(),
     }))
 }
 
-// Helper function to load payer keypair
-fn load_payer_keypair() -> anyhow::Result<Keypair> {
-    Ok(Keypair::new())
-}
\ No newline at end of file
+/// Generate the withdraw proofs, build their context state accounts and the
+/// owner-signed withdraw instruction that references them
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn prepare_withdraw(
+    client: &RpcClient,
+    payer: &Pubkey,
+    token_account: &Pubkey,
+    owner: &Pubkey,
+    ct_extension: &ConfidentialTransferAccount,
+    mint: &Pubkey,
+    amount: u64,
+    decimals: u8,
+    elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+) -> Result<PreparedInstructions, ApiError> {
+    // Create WithdrawAccountInfo from extension data
+    let withdraw_account_info = WithdrawAccountInfo::new(ct_extension);
+    let new_decryptable_available_balance = withdraw_account_info
+        .new_decryptable_available_balance(amount, aes_key)
+        .map_err(|_| ApiError::invalid_input("amount", "exceeds available confidential balance"))?
+        .into();
+
+    // Generate withdraw proofs (equality + range)
+    tracing::info!("Generating withdraw proofs...");
+    let withdraw_proof_data = generate_withdraw_proof(
+        &withdraw_account_info,
+        amount,
+        elgamal_keypair,
+        aes_key,
+    )
+    .map_err(|e| ApiError::ProofGeneration(e.to_string()))?;
+
+    // Verify each proof into its own context state account
+    let equality = create_proof_context(
+        client,
+        payer,
+        "equality",
+        ProofInstruction::VerifyCiphertextCommitmentEquality,
+        &withdraw_proof_data.equality_proof_data,
+    )
+    .await
+    .map_err(ApiError::rpc)?;
+    let range = create_proof_context(
+        client,
+        payer,
+        "range",
+        ProofInstruction::VerifyBatchedRangeProofU64,
+        &withdraw_proof_data.range_proof_data,
+    )
+    .await
+    .map_err(ApiError::rpc)?;
+
+    let instructions = withdraw(
+        &token_2022_program_id(),
+        token_account,
+        mint,
+        amount,
+        decimals,
+        &new_decryptable_available_balance,
+        owner,
+        &[],
+        ProofLocation::ContextStateAccount(&equality.pubkey()),
+        ProofLocation::ContextStateAccount(&range.pubkey()),
+    )
+    .map_err(|e| ApiError::internal("Failed to create withdraw instruction", e))?;
+
+    Ok(PreparedInstructions {
+        proof_contexts: vec![equality, range],
+        instructions,
+    })
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
 use solana_sdk::{
     commitment_config::CommitmentConfig,
     signature::Keypair,
@@ -8,12 +9,10 @@ use solana_sdk::{
 use std::sync::Arc;
 
 /// Create a Solana RPC client
-pub fn create_rpc_client() -> Arc<RpcClient> {
-    let rpc_url = std::env::var("SOLANA_RPC_URL")
-        .unwrap_or_else(|_| "https://api.devnet.solana.com".to_string());
-
+/// Built once at startup and shared through `AppState`
+pub fn create_rpc_client(rpc_url: &str) -> Arc<RpcClient> {
     Arc::new(RpcClient::new_with_commitment(
-        rpc_url,
+        rpc_url.to_string(),
         CommitmentConfig::confirmed(),
     ))
 }
@@ -32,12 +31,29 @@ pub async fn send_and_confirm(
 }
 
 /// Get account info
+/// Returns `None` if the account does not exist; RPC failures are errors
 pub async fn get_account_info(
     client: &RpcClient,
     pubkey: &solana_sdk::pubkey::Pubkey,
-) -> Result<Option<solana_sdk::account::Account>> {
-    let account = client.get_account(pubkey).await.ok();
-    Ok(account)
+) -> Result<Option<solana_sdk::account::Account>, ClientError> {
+    let response = client
+        .get_account_with_commitment(pubkey, client.commitment())
+        .await?;
+    Ok(response.value)
+}
+
+/// Serialize a (possibly partially signed) transaction as base64 wire format
+/// This is the encoding wallets expect for `signTransaction`
+pub fn encode_transaction(transaction: &Transaction) -> Result<String> {
+    let bytes = bincode::serialize(transaction)?;
+    Ok(BASE64_STANDARD.encode(bytes))
+}
+
+/// Decode a base64 wire-format transaction
+pub fn decode_transaction(encoded: &str) -> Result<Transaction> {
+    let bytes = BASE64_STANDARD.decode(encoded)?;
+    let transaction = bincode::deserialize(&bytes)?;
+    Ok(transaction)
 }
 
 #[cfg(test)]
@@ -46,7 +62,25 @@ mod tests {
 
     #[test]
     fn test_create_rpc_client() {
-        let client = create_rpc_client();
+        let client = create_rpc_client("http://127.0.0.1:8899");
         assert!(Arc::strong_count(&client) > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_transaction_encoding_round_trip() {
+        use solana_sdk::{hash::Hash, signer::Signer, system_instruction};
+
+        let payer = Keypair::new();
+        let owner = Keypair::new();
+        let ix = system_instruction::transfer(&owner.pubkey(), &payer.pubkey(), 1);
+        let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+        transaction.partial_sign(&[&payer], Hash::new_unique());
+
+        let encoded = encode_transaction(&transaction).unwrap();
+        let decoded = decode_transaction(&encoded).unwrap();
+
+        assert_eq!(decoded, transaction);
+        // Owner signature is still missing
+        assert!(!decoded.is_signed());
+    }
+}
@@ -1,3 +1,9 @@
 pub mod client;
+pub mod payer;
+pub mod proof_context;
+pub mod transactions;
 
-pub use client::*;
\ No newline at end of file
+pub use client::*;
+pub use payer::*;
+pub use proof_context::*;
+pub use transactions::*;
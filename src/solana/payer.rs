@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use solana_sdk::signature::Keypair;
+use std::path::{Path, PathBuf};
+
+/// Explicitly configured path to a Solana CLI JSON keypair file
+pub const PAYER_KEYPAIR_PATH_ENV: &str = "PAYER_KEYPAIR_PATH";
+/// Base58-encoded 64-byte payer secret key
+pub const PAYER_PRIVATE_KEY_ENV: &str = "PAYER_PRIVATE_KEY";
+
+/// Load the fee payer keypair
+///
+/// Sources are tried in order:
+/// 1. `PAYER_KEYPAIR_PATH` - explicitly configured keypair file
+/// 2. `PAYER_PRIVATE_KEY` - base58 secret key
+/// 3. `~/.config/solana/id.json` - the Solana CLI default keypair
+///
+/// Fails if none of them is configured; the server must not fall back to an
+/// unfunded throwaway keypair.
+pub fn load_payer_keypair() -> Result<Keypair> {
+    if let Ok(path) = std::env::var(PAYER_KEYPAIR_PATH_ENV) {
+        return keypair_from_json_file(&path)
+            .with_context(|| format!("{} is set but could not be loaded", PAYER_KEYPAIR_PATH_ENV));
+    }
+
+    if let Ok(encoded) = std::env::var(PAYER_PRIVATE_KEY_ENV) {
+        return keypair_from_base58(&encoded)
+            .with_context(|| format!("{} is set but could not be loaded", PAYER_PRIVATE_KEY_ENV));
+    }
+
+    if let Some(path) = default_cli_keypair_path().filter(|path| path.exists()) {
+        return keypair_from_json_file(&path);
+    }
+
+    anyhow::bail!(
+        "No payer keypair configured: set {} or {}, or create ~/.config/solana/id.json",
+        PAYER_KEYPAIR_PATH_ENV,
+        PAYER_PRIVATE_KEY_ENV
+    )
+}
+
+/// Read a Solana CLI keypair file (a JSON array of 64 bytes)
+pub fn keypair_from_json_file(path: impl AsRef<Path>) -> Result<Keypair> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read keypair file {}", path.display()))?;
+    let bytes: Vec<u8> = serde_json::from_str(&contents)
+        .with_context(|| format!("Keypair file {} is not a JSON byte array", path.display()))?;
+
+    Keypair::try_from(bytes.as_slice())
+        .map_err(|e| anyhow::anyhow!("Invalid keypair in {}: {}", path.display(), e))
+}
+
+/// Decode a base58-encoded 64-byte secret key
+pub fn keypair_from_base58(encoded: &str) -> Result<Keypair> {
+    let bytes = bs58::decode(encoded.trim())
+        .into_vec()
+        .context("Payer secret key is not valid base58")?;
+
+    Keypair::try_from(bytes.as_slice())
+        .map_err(|e| anyhow::anyhow!("Invalid payer secret key: {}", e))
+}
+
+fn default_cli_keypair_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".config").join("solana").join("id.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signer::Signer;
+
+    #[test]
+    fn test_keypair_from_json_file() {
+        let keypair = Keypair::new();
+        let path = std::env::temp_dir().join(format!("payer-{}.json", keypair.pubkey()));
+        std::fs::write(&path, serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap())
+            .unwrap();
+
+        let loaded = keypair_from_json_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn test_keypair_from_base58() {
+        let keypair = Keypair::new();
+        let encoded = bs58::encode(keypair.to_bytes()).into_string();
+
+        let loaded = keypair_from_base58(&encoded).unwrap();
+        assert_eq!(loaded.pubkey(), keypair.pubkey());
+
+        assert!(keypair_from_base58("not-base58!").is_err());
+    }
+}
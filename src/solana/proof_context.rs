@@ -0,0 +1,637 @@
+use bytemuck::Pod;
+use solana_client::{client_error::ClientError, nonblocking::rpc_client::RpcClient};
+use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_record::state::RecordData;
+use spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::{
+    self,
+    instruction::{ContextStateInfo, ProofInstruction, close_context_state},
+    proof_data::ZkProofData,
+    state::ProofContextState,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::get_account_info;
+
+/// Maximum serialized transaction size accepted by the cluster
+/// (IPv6 MTU minus IP and UDP headers)
+pub const PACKET_DATA_SIZE: usize = 1280 - 40 - 8;
+
+/// Proof bytes written to a record account per transaction, leaving room for
+/// the signature, account keys and write instruction header
+const RECORD_WRITE_CHUNK_SIZE: usize = 900;
+
+/// Close attempts per context account before it is reported as leaked
+const CLOSE_ATTEMPTS: u32 = 3;
+/// Base delay between close attempts, multiplied by the attempt number
+const CLOSE_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// How often the sweeper looks for handed-out accounts whose blockhash expired
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A proof verified into its own context state account, so that a later
+/// token instruction can reference it instead of carrying the proof inline
+pub struct ProofContextAccount {
+    /// Human-readable name used in logs ("equality", "range", ...)
+    pub label: &'static str,
+    pub keypair: Keypair,
+    /// Record account holding the proof when it is too large to verify inline
+    pub record: Option<ProofRecordAccount>,
+    /// Rent-exempt size of the context state account
+    space: usize,
+    rent: u64,
+    /// Verify the proof into the context state account once it is created
+    verify_instruction: Instruction,
+}
+
+/// An spl-record account the proof data is written into, chunk by chunk,
+/// before it is verified from the account
+pub struct ProofRecordAccount {
+    pub keypair: Keypair,
+    space: usize,
+    rent: u64,
+    /// Initialize the record account with the payer as its authority
+    initialize_instruction: Instruction,
+    /// One write per transaction, in order; the payer signs as authority
+    pub write_instructions: Vec<Instruction>,
+}
+
+impl ProofContextAccount {
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    /// Create the context state account, funded by `funder`, and verify the
+    /// proof into it
+    pub fn instructions(&self, funder: &Pubkey) -> Vec<Instruction> {
+        vec![
+            system_instruction::create_account(
+                funder,
+                &self.pubkey(),
+                self.rent,
+                self.space as u64,
+                &zk_elgamal_proof_program::id(),
+            ),
+            self.verify_instruction.clone(),
+        ]
+    }
+
+    /// Sign every transaction needed to verify the proof, in submission order
+    pub fn transactions(&self, payer: &Keypair, recent_blockhash: Hash) -> Vec<Transaction> {
+        let payer_pubkey = payer.pubkey();
+        let mut transactions = Vec::new();
+
+        if let Some(record) = &self.record {
+            transactions.push(Transaction::new_signed_with_payer(
+                &record.create_instructions(&payer_pubkey),
+                Some(&payer_pubkey),
+                &[payer, &record.keypair],
+                recent_blockhash,
+            ));
+            transactions.extend(record.write_instructions.iter().map(|write_ix| {
+                Transaction::new_signed_with_payer(
+                    std::slice::from_ref(write_ix),
+                    Some(&payer_pubkey),
+                    &[payer],
+                    recent_blockhash,
+                )
+            }));
+        }
+
+        transactions.push(Transaction::new_signed_with_payer(
+            &self.instructions(&payer_pubkey),
+            Some(&payer_pubkey),
+            &[payer, &self.keypair],
+            recent_blockhash,
+        ));
+        transactions
+    }
+
+    /// Build every transaction needed to verify the proof for the owner's
+    /// wallet to sign, in submission order
+    ///
+    /// The owner pays the fees and the rent of the new accounts, so handing
+    /// these out costs the payer nothing. The payer only signs as the record
+    /// account authority; it stays the authority of both accounts so the
+    /// server can close them.
+    pub fn unsigned_transactions(&self, owner: &Pubkey, payer: &Keypair, recent_blockhash: Hash) -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+
+        if let Some(record) = &self.record {
+            let mut create_tx = Transaction::new_with_payer(&record.create_instructions(owner), Some(owner));
+            create_tx.partial_sign(&[&record.keypair], recent_blockhash);
+            transactions.push(create_tx);
+
+            transactions.extend(record.write_instructions.iter().map(|write_ix| {
+                let mut write_tx = Transaction::new_with_payer(std::slice::from_ref(write_ix), Some(owner));
+                write_tx.partial_sign(&[payer], recent_blockhash);
+                write_tx
+            }));
+        }
+
+        let mut verify_tx = Transaction::new_with_payer(&self.instructions(owner), Some(owner));
+        verify_tx.partial_sign(&[&self.keypair], recent_blockhash);
+        transactions.push(verify_tx);
+        transactions
+    }
+}
+
+impl ProofRecordAccount {
+    /// Create the record account, funded by `funder`, and initialize it
+    fn create_instructions(&self, funder: &Pubkey) -> Vec<Instruction> {
+        vec![
+            system_instruction::create_account(
+                funder,
+                &self.keypair.pubkey(),
+                self.rent,
+                self.space as u64,
+                &spl_record::id(),
+            ),
+            self.initialize_instruction.clone(),
+        ]
+    }
+}
+
+/// Build the instructions that create a context state account owned by the
+/// ZK ElGamal proof program and verify `proof_data` into it
+///
+/// The payer is the context state authority, so the server can close the
+/// account and recover the rent without the owner's signature. If verifying
+/// inline would not fit in one transaction, the proof is written to a record
+/// account first and verified from there.
+pub async fn create_proof_context<T, U>(
+    client: &RpcClient,
+    payer: &Pubkey,
+    label: &'static str,
+    instruction_type: ProofInstruction,
+    proof_data: &T,
+) -> Result<ProofContextAccount, ClientError>
+where
+    T: Pod + ZkProofData<U>,
+    U: Pod,
+{
+    let keypair = Keypair::new();
+    let space = std::mem::size_of::<ProofContextState<U>>();
+    let rent = client.get_minimum_balance_for_rent_exemption(space).await?;
+
+    let context_state_info = ContextStateInfo {
+        context_state_account: &keypair.pubkey(),
+        context_state_authority: payer,
+    };
+
+    let context = ProofContextAccount {
+        label,
+        record: None,
+        space,
+        rent,
+        verify_instruction: instruction_type.encode_verify_proof(Some(context_state_info), proof_data),
+        keypair,
+    };
+    // Sized for unsigned mode, where the owner pays and the payer is only
+    // the authority
+    let funder = Pubkey::new_unique();
+    if fits_in_packet(&context.instructions(&funder), &funder) {
+        return Ok(context);
+    }
+
+    tracing::info!("{} proof exceeds the packet limit, verifying from a record account", label);
+
+    let record = create_proof_record(client, payer, bytemuck::bytes_of(proof_data)).await?;
+    let verify_instruction = instruction_type.encode_verify_proof_from_account(
+        Some(context_state_info),
+        &record.keypair.pubkey(),
+        RecordData::WRITABLE_START_INDEX as u32,
+    );
+
+    Ok(ProofContextAccount {
+        record: Some(record),
+        verify_instruction,
+        ..context
+    })
+}
+
+/// Build the instructions that store `proof_bytes` in a new record account
+/// with the payer as its authority
+async fn create_proof_record(
+    client: &RpcClient,
+    payer: &Pubkey,
+    proof_bytes: &[u8],
+) -> Result<ProofRecordAccount, ClientError> {
+    let keypair = Keypair::new();
+    let space = RecordData::WRITABLE_START_INDEX + proof_bytes.len();
+    let rent = client.get_minimum_balance_for_rent_exemption(space).await?;
+
+    let initialize_instruction = spl_record::instruction::initialize(&keypair.pubkey(), payer);
+
+    let write_instructions = proof_bytes
+        .chunks(RECORD_WRITE_CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let offset = (i * RECORD_WRITE_CHUNK_SIZE) as u64;
+            spl_record::instruction::write(&keypair.pubkey(), payer, offset, chunk)
+        })
+        .collect();
+
+    Ok(ProofRecordAccount {
+        keypair,
+        space,
+        rent,
+        initialize_instruction,
+        write_instructions,
+    })
+}
+
+/// Whether a transaction carrying `instructions` fits within the packet limit
+fn fits_in_packet(instructions: &[Instruction], payer: &Pubkey) -> bool {
+    let transaction = Transaction::new_with_payer(instructions, Some(payer));
+    bincode::serialized_size(&transaction)
+        .map(|size| size as usize <= PACKET_DATA_SIZE)
+        .unwrap_or(false)
+}
+
+/// Close a context state account created by `create_proof_context`,
+/// returning its rent to whoever funded it
+pub fn close_proof_context(context_account: &Pubkey, payer: &Pubkey, rent_destination: &Pubkey) -> Instruction {
+    close_context_state(
+        ContextStateInfo {
+            context_state_account: context_account,
+            context_state_authority: payer,
+        },
+        rent_destination,
+    )
+}
+
+/// Close a proof record account, returning its rent to whoever funded it
+pub fn close_proof_record(record_account: &Pubkey, payer: &Pubkey, rent_destination: &Pubkey) -> Instruction {
+    spl_record::instruction::close_account(record_account, payer, rent_destination)
+}
+
+/// An account the guard is responsible for, and how to close it
+#[derive(Debug, Clone, Copy)]
+enum TrackedAccount {
+    Context(Pubkey),
+    Record(Pubkey),
+}
+
+impl TrackedAccount {
+    fn pubkey(&self) -> &Pubkey {
+        match self {
+            Self::Context(pubkey) | Self::Record(pubkey) => pubkey,
+        }
+    }
+
+    fn close_instruction(&self, payer: &Pubkey, rent_destination: &Pubkey) -> Instruction {
+        match self {
+            Self::Context(pubkey) => close_proof_context(pubkey, payer, rent_destination),
+            Self::Record(pubkey) => close_proof_record(pubkey, payer, rent_destination),
+        }
+    }
+
+    /// The accounts `context` creates, record account first
+    fn of(context: &ProofContextAccount) -> Vec<Self> {
+        let mut accounts = Vec::new();
+        if let Some(record) = &context.record {
+            accounts.push(Self::Record(record.keypair.pubkey()));
+        }
+        accounts.push(Self::Context(context.pubkey()));
+        accounts
+    }
+}
+
+/// Tracks the proof context (and record) accounts a request creates so they
+/// are closed whether the request succeeds or fails
+///
+/// Call `track` before sending a context's transactions, run the rest of the
+/// operation, then call `close_all` before propagating its result. Accounts
+/// still tracked when the guard is dropped are logged as leaked.
+pub struct ProofContextGuard<'a> {
+    client: &'a RpcClient,
+    payer: &'a Keypair,
+    /// Label, account and where its rent goes
+    accounts: Vec<(&'static str, TrackedAccount, Pubkey)>,
+}
+
+impl<'a> ProofContextGuard<'a> {
+    pub fn new(client: &'a RpcClient, payer: &'a Keypair) -> Self {
+        Self {
+            client,
+            payer,
+            accounts: Vec::new(),
+        }
+    }
+
+    /// Track the accounts `context` is about to create
+    ///
+    /// Called before sending, so an account whose create lands but fails to
+    /// confirm is still closed.
+    pub fn track(&mut self, context: &ProofContextAccount) {
+        let payer = self.payer.pubkey();
+        self.accounts.extend(
+            TrackedAccount::of(context)
+                .into_iter()
+                .map(|account| (context.label, account, payer)),
+        );
+    }
+
+    /// Close every tracked account, retrying failures, and return the ones
+    /// that are still open
+    pub async fn close_all(mut self) -> Vec<Pubkey> {
+        let accounts = std::mem::take(&mut self.accounts);
+        let mut unclosed = Vec::new();
+
+        for (label, account, rent_destination) in accounts {
+            if !self.close(label, &account, &rent_destination).await {
+                unclosed.push(*account.pubkey());
+            }
+        }
+
+        if !unclosed.is_empty() {
+            tracing::error!(
+                "Proof context accounts left open, rent not recovered: {:?}",
+                unclosed
+            );
+        }
+
+        unclosed
+    }
+
+    async fn close(&self, label: &'static str, account: &TrackedAccount, rent_destination: &Pubkey) -> bool {
+        for attempt in 1..=CLOSE_ATTEMPTS {
+            match self.send_close(account, rent_destination).await {
+                Ok(signature) => {
+                    tracing::info!("{} proof account {} closed: {}", label, account.pubkey(), signature);
+                    return true;
+                }
+                Err(e) => {
+                    // Nothing to close if the create never landed or a previous
+                    // attempt closed it after all
+                    if let Ok(None) = get_account_info(self.client, account.pubkey()).await {
+                        return true;
+                    }
+
+                    tracing::warn!(
+                        "Closing {} proof account {} failed (attempt {}/{}): {}",
+                        label,
+                        account.pubkey(),
+                        attempt,
+                        CLOSE_ATTEMPTS,
+                        e
+                    );
+                    if attempt < CLOSE_ATTEMPTS {
+                        tokio::time::sleep(CLOSE_RETRY_DELAY * attempt).await;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    async fn send_close(&self, account: &TrackedAccount, rent_destination: &Pubkey) -> Result<Signature, ClientError> {
+        let payer = self.payer.pubkey();
+        let recent_blockhash = self.client.get_latest_blockhash().await?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[account.close_instruction(&payer, rent_destination)],
+            Some(&payer),
+            &[self.payer],
+            recent_blockhash,
+        );
+        self.client.send_and_confirm_transaction(&transaction).await
+    }
+}
+
+impl Drop for ProofContextGuard<'_> {
+    fn drop(&mut self) {
+        if !self.accounts.is_empty() {
+            tracing::error!(
+                "Proof context guard dropped without closing {:?}, rent not recovered",
+                self.accounts
+            );
+        }
+    }
+}
+
+/// Proof accounts handed out in unsigned mode, which the server closes
+///
+/// The owner's wallet creates them, so the server cannot tell when, or
+/// whether, they land. `/api/tx/submit` closes the ones its transactions use
+/// once they have run; the sweeper closes whatever is left once the
+/// blockhash the accounts were built with expires, after which they can no
+/// longer be created. Rent goes back to the owner, who paid it.
+#[derive(Default)]
+pub struct ProofContextSweeper {
+    accounts: Mutex<Vec<HandedOutAccount>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HandedOutAccount {
+    label: &'static str,
+    account: TrackedAccount,
+    owner: Pubkey,
+    recent_blockhash: Hash,
+}
+
+impl ProofContextSweeper {
+    /// Record the accounts `context` creates when the owner submits its
+    /// unsigned transactions, built with `recent_blockhash`
+    pub fn hand_out(&self, context: &ProofContextAccount, owner: &Pubkey, recent_blockhash: Hash) {
+        let handed_out = TrackedAccount::of(context).into_iter().map(|account| HandedOutAccount {
+            label: context.label,
+            account,
+            owner: *owner,
+            recent_blockhash,
+        });
+        self.accounts
+            .lock()
+            .expect("proof context sweeper poisoned")
+            .extend(handed_out);
+    }
+
+    /// Close the handed-out accounts used by `transactions` that their
+    /// owner signed, returning the ones that are still open
+    ///
+    /// Accounts stay recorded until their blockhash expires, in case the
+    /// owner's transactions are sent again.
+    pub async fn close_used(&self, client: &RpcClient, payer: &Keypair, transactions: &[Transaction]) -> Vec<Pubkey> {
+        let used: Vec<HandedOutAccount> = self
+            .accounts
+            .lock()
+            .expect("proof context sweeper poisoned")
+            .iter()
+            .filter(|handed_out| transactions.iter().any(|transaction| handed_out.used_by(transaction)))
+            .copied()
+            .collect();
+
+        let mut guard = ProofContextGuard::new(client, payer);
+        guard
+            .accounts
+            .extend(used.iter().map(|handed_out| (handed_out.label, handed_out.account, handed_out.owner)));
+        guard.close_all().await
+    }
+
+    /// Close the handed-out accounts whose blockhash has expired; the ones
+    /// that fail to close are kept for the next sweep
+    async fn sweep(&self, client: &RpcClient, payer: &Keypair) {
+        let mut blockhashes: Vec<Hash> = self
+            .accounts
+            .lock()
+            .expect("proof context sweeper poisoned")
+            .iter()
+            .map(|handed_out| handed_out.recent_blockhash)
+            .collect();
+        blockhashes.sort_unstable();
+        blockhashes.dedup();
+
+        for blockhash in blockhashes {
+            match client.is_blockhash_valid(&blockhash, client.commitment()).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!("Checking blockhash {} for the proof sweep failed: {}", blockhash, e);
+                    continue;
+                }
+            }
+
+            let expired: Vec<HandedOutAccount> = {
+                let mut accounts = self.accounts.lock().expect("proof context sweeper poisoned");
+                let (expired, live): (Vec<_>, Vec<_>) = accounts
+                    .drain(..)
+                    .partition(|handed_out| handed_out.recent_blockhash == blockhash);
+                *accounts = live;
+                expired
+            };
+
+            let mut guard = ProofContextGuard::new(client, payer);
+            guard
+                .accounts
+                .extend(expired.iter().map(|handed_out| (handed_out.label, handed_out.account, handed_out.owner)));
+            let unclosed = guard.close_all().await;
+
+            self.accounts
+                .lock()
+                .expect("proof context sweeper poisoned")
+                .extend(expired.into_iter().filter(|handed_out| unclosed.contains(handed_out.account.pubkey())));
+        }
+    }
+}
+
+impl HandedOutAccount {
+    /// Whether `transaction` references the account and the owner signed it
+    fn used_by(&self, transaction: &Transaction) -> bool {
+        let message = &transaction.message;
+        let signers = &message.account_keys[..usize::from(message.header.num_required_signatures)];
+        signers.contains(&self.owner) && message.account_keys.contains(self.account.pubkey())
+    }
+}
+
+/// Spawn the proof sweeper, closing expired handed-out accounts until shutdown
+pub fn spawn_proof_sweeper(
+    client: Arc<RpcClient>,
+    payer: Arc<Keypair>,
+    sweeper: Arc<ProofContextSweeper>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            sweeper.sweep(&client, &payer).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_size_check() {
+        let payer = Pubkey::new_unique();
+        let small = Instruction::new_with_bytes(Pubkey::new_unique(), &[0u8; 64], vec![]);
+        assert!(fits_in_packet(&[small], &payer));
+
+        let large = Instruction::new_with_bytes(Pubkey::new_unique(), &[0u8; PACKET_DATA_SIZE], vec![]);
+        assert!(!fits_in_packet(&[large], &payer));
+    }
+
+    #[test]
+    fn test_record_write_fits_in_packet() {
+        let payer = Pubkey::new_unique();
+        let write_ix = spl_record::instruction::write(
+            &Pubkey::new_unique(),
+            &payer,
+            0,
+            &[7u8; RECORD_WRITE_CHUNK_SIZE],
+        );
+        assert!(fits_in_packet(std::slice::from_ref(&write_ix), &payer));
+
+        // Unsigned mode: the owner pays the fee, the payer signs as authority
+        let owner = Pubkey::new_unique();
+        assert!(fits_in_packet(&[write_ix], &owner));
+    }
+
+    #[test]
+    fn test_unsigned_transactions_are_paid_by_the_owner() {
+        let payer = Keypair::new();
+        let owner = Pubkey::new_unique();
+        let context_keypair = Keypair::new();
+        let context = ProofContextAccount {
+            label: "test",
+            verify_instruction: Instruction::new_with_bytes(
+                zk_elgamal_proof_program::id(),
+                &[0u8; 8],
+                vec![solana_sdk::instruction::AccountMeta::new(context_keypair.pubkey(), false)],
+            ),
+            keypair: context_keypair,
+            record: None,
+            space: 64,
+            rent: 1_000_000,
+        };
+
+        let transactions = context.unsigned_transactions(&owner, &payer, Hash::new_unique());
+        assert_eq!(transactions.len(), 1);
+
+        // The owner is the fee payer and funder, and still has to sign
+        let transaction = &transactions[0];
+        assert_eq!(transaction.message.account_keys[0], owner);
+        assert_eq!(transaction.message.header.num_required_signatures, 2);
+        assert_eq!(transaction.signatures[0], Signature::default());
+        assert_ne!(transaction.signatures[1], Signature::default());
+    }
+
+    #[test]
+    fn test_handed_out_account_needs_the_owner_signature() {
+        let owner = Keypair::new();
+        let context = Pubkey::new_unique();
+        let handed_out = HandedOutAccount {
+            label: "test",
+            account: TrackedAccount::Context(context),
+            owner: owner.pubkey(),
+            recent_blockhash: Hash::new_unique(),
+        };
+        let uses_context = |fee_payer: &Pubkey| {
+            Transaction::new_with_payer(
+                &[Instruction::new_with_bytes(
+                    Pubkey::new_unique(),
+                    &[],
+                    vec![solana_sdk::instruction::AccountMeta::new_readonly(context, false)],
+                )],
+                Some(fee_payer),
+            )
+        };
+
+        assert!(handed_out.used_by(&uses_context(&owner.pubkey())));
+        // Anyone else's transaction cannot close the owner's accounts early
+        assert!(!handed_out.used_by(&uses_context(&Pubkey::new_unique())));
+    }
+}
@@ -0,0 +1,177 @@
+use solana_client::{
+    client_error::ClientError,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcTransactionConfig,
+    rpc_request::RpcRequest,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status_client_types::{
+    EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding, option_serializer::OptionSerializer,
+};
+use std::str::FromStr;
+
+/// Most signatures `getSignaturesForAddress` returns per call
+pub const SIGNATURES_PAGE_LIMIT: usize = 1000;
+
+/// A confirmed transaction with its top-level instructions resolved against
+/// the full account list, including address lookup table entries
+pub struct ConfirmedTransaction {
+    pub signature: Signature,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    /// The transaction failed, so its instructions had no effect
+    pub failed: bool,
+    pub instructions: Vec<ResolvedInstruction>,
+}
+
+/// A top-level instruction with its program and accounts as addresses
+pub struct ResolvedInstruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<Pubkey>,
+    pub data: Vec<u8>,
+}
+
+/// Fetch a confirmed transaction
+/// Returns `None` if the node does not know the signature; RPC failures are errors
+pub async fn get_confirmed_transaction(
+    client: &RpcClient,
+    signature: &Signature,
+) -> Result<Option<ConfirmedTransaction>, ClientError> {
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(client.commitment()),
+        max_supported_transaction_version: Some(0),
+    };
+    let response: Option<EncodedConfirmedTransactionWithStatusMeta> = client
+        .send(
+            RpcRequest::GetTransaction,
+            serde_json::json!([signature.to_string(), config]),
+        )
+        .await?;
+
+    Ok(response.and_then(|encoded| resolve_transaction(*signature, encoded)))
+}
+
+/// One page of signatures of the transactions that touched `address`, newest
+/// first: those before `before` (from the newest when `None`), back to but
+/// excluding `until`
+///
+/// A page shorter than [`SIGNATURES_PAGE_LIMIT`] is the last one.
+pub async fn get_signatures_page(
+    client: &RpcClient,
+    address: &Pubkey,
+    before: Option<Signature>,
+    until: Option<Signature>,
+) -> Result<Vec<(Signature, u64)>, ClientError> {
+    let page = client
+        .get_signatures_for_address_with_config(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until,
+                limit: Some(SIGNATURES_PAGE_LIMIT),
+                commitment: Some(client.commitment()),
+            },
+        )
+        .await?;
+
+    // Signatures the node returns are always valid base58
+    Ok(page
+        .into_iter()
+        .filter_map(|status| Some((Signature::from_str(&status.signature).ok()?, status.slot)))
+        .collect())
+}
+
+/// Signatures of the transactions that touched `address` within
+/// `start_slot..=end_slot`, oldest first
+///
+/// Pages backwards from the newest signature and stops once it passes
+/// `start_slot`, or after `limit` signatures in range. Returns `None` if
+/// `max_pages` pages do not reach back to `start_slot`.
+pub async fn get_signatures_in_slot_range(
+    client: &RpcClient,
+    address: &Pubkey,
+    start_slot: u64,
+    end_slot: u64,
+    limit: usize,
+    max_pages: usize,
+) -> Result<Option<Vec<(Signature, u64)>>, ClientError> {
+    let mut signatures = Vec::new();
+    let mut before = None;
+
+    'pages: for page_number in 1.. {
+        if page_number > max_pages {
+            return Ok(None);
+        }
+
+        let page = get_signatures_page(client, address, before, None).await?;
+        let page_len = page.len();
+
+        for (signature, slot) in page {
+            before = Some(signature);
+
+            if slot < start_slot {
+                break 'pages;
+            }
+            if slot <= end_slot {
+                signatures.push((signature, slot));
+                if signatures.len() >= limit {
+                    break 'pages;
+                }
+            }
+        }
+
+        if page_len < SIGNATURES_PAGE_LIMIT {
+            break;
+        }
+    }
+
+    signatures.reverse();
+    Ok(Some(signatures))
+}
+
+fn resolve_transaction(
+    signature: Signature,
+    encoded: EncodedConfirmedTransactionWithStatusMeta,
+) -> Option<ConfirmedTransaction> {
+    let transaction = encoded.transaction.transaction.decode()?;
+    let meta = encoded.transaction.meta;
+
+    // Static keys first, then writable and readonly lookup table entries
+    let mut account_keys = transaction.message.static_account_keys().to_vec();
+    if let Some(OptionSerializer::Some(loaded)) = meta.as_ref().map(|meta| &meta.loaded_addresses) {
+        account_keys.extend(
+            loaded
+                .writable
+                .iter()
+                .chain(&loaded.readonly)
+                .filter_map(|address| Pubkey::from_str(address).ok()),
+        );
+    }
+
+    let instructions = transaction
+        .message
+        .instructions()
+        .iter()
+        .filter_map(|instruction| {
+            Some(ResolvedInstruction {
+                program_id: *account_keys.get(usize::from(instruction.program_id_index))?,
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .map(|index| account_keys.get(usize::from(*index)).copied())
+                    .collect::<Option<Vec<_>>>()?,
+                data: instruction.data.clone(),
+            })
+        })
+        .collect();
+
+    Some(ConfirmedTransaction {
+        signature,
+        slot: encoded.slot,
+        block_time: encoded.block_time,
+        failed: meta.is_some_and(|meta| meta.err.is_some()),
+        instructions,
+    })
+}
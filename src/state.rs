@@ -0,0 +1,98 @@
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::signature::Keypair;
+use std::sync::Arc;
+
+use crate::{
+    auto_apply::AutoApplyRegistry,
+    config::Config,
+    crypto::AttestationAuthority,
+    solana::{self, ProofContextSweeper},
+};
+#[cfg(feature = "database")]
+use crate::{history::HistoryStore, vault::KeyVault};
+
+/// Shared application state, built once in `main` and handed to every
+/// handler through axum's `State` extractor
+///
+/// Cloning is cheap: every field is reference counted. Long-lived subsystems
+/// (key storage, metrics, background workers) belong here as well.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub rpc: Arc<RpcClient>,
+    pub payer: Arc<Keypair>,
+    pub attestations: Arc<AttestationAuthority>,
+    /// Proof context accounts handed out in unsigned mode, closed by the server
+    pub proof_sweeper: Arc<ProofContextSweeper>,
+    /// Accounts the auto-apply worker applies pending balances for
+    pub auto_apply: Arc<AutoApplyRegistry>,
+    /// Encrypted keys of custodial accounts, when a database is configured
+    #[cfg(feature = "database")]
+    pub vault: Option<Arc<KeyVault>>,
+    /// Indexed confidential transaction history, when the indexer is enabled
+    #[cfg(feature = "database")]
+    pub history: Option<Arc<HistoryStore>>,
+}
+
+impl AppState {
+    /// Build the state from configuration, loading the fee payer and the
+    /// attestation signing key, and opening the key vault and history store
+    /// if configured
+    pub async fn new(config: Config) -> anyhow::Result<Self> {
+        let rpc = solana::create_rpc_client(&config.rpc_url);
+        let payer = Arc::new(solana::load_payer_keypair()?);
+
+        let attestation_key = match &config.attestation_keypair_path {
+            Some(path) => solana::keypair_from_json_file(path)?,
+            None => {
+                tracing::warn!(
+                    "ATTESTATION_KEYPAIR_PATH not set; attestations are signed with an ephemeral key"
+                );
+                Keypair::new()
+            }
+        };
+        let attestations = Arc::new(match &config.attestation_state_path {
+            Some(path) => AttestationAuthority::with_state_file(
+                attestation_key,
+                config.attestation_ttl_secs,
+                path.clone(),
+            )?,
+            None => {
+                tracing::warn!(
+                    "ATTESTATION_STATE_PATH not set; key rotations and revocations are lost on restart"
+                );
+                AttestationAuthority::new(attestation_key, config.attestation_ttl_secs)
+            }
+        });
+
+        #[cfg(feature = "database")]
+        let vault = match &config.vault {
+            Some(vault_config) => {
+                let vault = KeyVault::connect(vault_config).await?;
+                tracing::info!("Key vault opened at {}", vault_config.database_url);
+                Some(Arc::new(vault))
+            }
+            None => None,
+        };
+
+        // History lives in the key vault's database
+        #[cfg(feature = "database")]
+        let history = match (&config.history, &vault) {
+            (Some(_), Some(vault)) => Some(Arc::new(HistoryStore::new(vault.pool().clone()).await?)),
+            _ => None,
+        };
+
+        Ok(Self {
+            config: Arc::new(config),
+            rpc,
+            payer,
+            attestations,
+            proof_sweeper: Arc::new(ProofContextSweeper::default()),
+            auto_apply: Arc::new(AutoApplyRegistry::default()),
+            #[cfg(feature = "database")]
+            vault,
+            #[cfg(feature = "database")]
+            history,
+        })
+    }
+}
@@ -0,0 +1,477 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_2022::solana_zk_sdk::encryption::{
+    AE_KEY_LEN,
+    auth_encryption::AeKey,
+    elgamal::{ElGamalKeypair, ElGamalSecretKey},
+};
+use sqlx::{
+    Row,
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::config::VaultConfig;
+
+/// AES-GCM nonce length, stored in front of each ciphertext
+const NONCE_LEN: usize = 12;
+
+/// Errors returned by the key vault
+#[derive(Debug, Error)]
+pub enum VaultError {
+    #[error("key database error: {0}")]
+    Database(#[from] sqlx::Error),
+    /// Decryption failed: wrong master key, or the row was tampered with
+    #[error("stored keys for {0} could not be decrypted")]
+    Decryption(Pubkey),
+    /// Decrypted bytes are not a valid key
+    #[error("stored keys for {0} are corrupt")]
+    CorruptKey(Pubkey),
+}
+
+/// SQLite storage for the ElGamal secret and AES key of custodial accounts
+///
+/// `account_keys` holds the keys each account is configured with;
+/// `pending_account_keys` holds the new keys of a key rotation until the
+/// account is recreated with them; `auto_apply_keys` holds the keys of the
+/// accounts registered for auto-apply, so registrations survive a restart.
+/// Each key is encrypted with AES-256-GCM under the master key from config.
+/// The token account and key name are bound in as associated data, so a
+/// ciphertext copied to another row or column fails to decrypt.
+pub struct KeyVault {
+    pool: SqlitePool,
+    cipher: Aes256Gcm,
+}
+
+impl KeyVault {
+    /// Open (creating if needed) the key database
+    pub async fn connect(config: &VaultConfig) -> Result<Self, VaultError> {
+        let options = SqliteConnectOptions::from_str(&config.database_url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await?;
+        Self::with_pool(pool, &config.master_key).await
+    }
+
+    async fn with_pool(pool: SqlitePool, master_key: &[u8; 32]) -> Result<Self, VaultError> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS account_keys (
+                token_account TEXT PRIMARY KEY,
+                owner TEXT NOT NULL,
+                elgamal_secret_key BLOB NOT NULL,
+                aes_key BLOB NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS pending_account_keys (
+                token_account TEXT PRIMARY KEY,
+                owner TEXT NOT NULL,
+                elgamal_secret_key BLOB NOT NULL,
+                aes_key BLOB NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS auto_apply_keys (
+                token_account TEXT PRIMARY KEY,
+                elgamal_secret_key BLOB NOT NULL,
+                aes_key BLOB NOT NULL,
+                created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self {
+            pool,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key)),
+        })
+    }
+
+    /// The underlying connection pool, shared with the history store
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Store the keys of a token account unless it already has keys stored;
+    /// returns whether they were stored
+    pub async fn insert(
+        &self,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<bool, VaultError> {
+        self.insert_into("account_keys", token_account, owner, elgamal_keypair, aes_key)
+            .await
+    }
+
+    /// Load the keys of a token account, if it has any stored
+    pub async fn load(&self, token_account: &Pubkey) -> Result<Option<(ElGamalKeypair, AeKey)>, VaultError> {
+        self.load_from("account_keys", token_account).await
+    }
+
+    /// The new keys of a rotation of a token account, generated on first use
+    ///
+    /// Every call returns the same keys until they are promoted, so a retried
+    /// rotation step never configures the account with keys that are lost.
+    pub async fn pending(
+        &self,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+    ) -> Result<(ElGamalKeypair, AeKey), VaultError> {
+        self.insert_into(
+            "pending_account_keys",
+            token_account,
+            owner,
+            &ElGamalKeypair::new_rand(),
+            &AeKey::new_rand(),
+        )
+        .await?;
+
+        self.load_from("pending_account_keys", token_account)
+            .await?
+            .ok_or(VaultError::Database(sqlx::Error::RowNotFound))
+    }
+
+    /// The pending rotation keys of a token account, if it has any
+    pub async fn load_pending(
+        &self,
+        token_account: &Pubkey,
+    ) -> Result<Option<(ElGamalKeypair, AeKey)>, VaultError> {
+        self.load_from("pending_account_keys", token_account).await
+    }
+
+    /// Make the pending rotation keys of a token account its current keys;
+    /// returns whether it had any
+    pub async fn promote_pending(&self, token_account: &Pubkey) -> Result<bool, VaultError> {
+        let mut transaction = self.pool.begin().await?;
+
+        let promoted = sqlx::query(
+            "INSERT INTO account_keys (token_account, owner, elgamal_secret_key, aes_key)
+             SELECT token_account, owner, elgamal_secret_key, aes_key
+             FROM pending_account_keys WHERE token_account = ?
+             ON CONFLICT (token_account) DO UPDATE SET
+                owner = excluded.owner,
+                elgamal_secret_key = excluded.elgamal_secret_key,
+                aes_key = excluded.aes_key",
+        )
+        .bind(token_account.to_string())
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected()
+            > 0;
+
+        sqlx::query("DELETE FROM pending_account_keys WHERE token_account = ?")
+            .bind(token_account.to_string())
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(promoted)
+    }
+
+    /// Owner the keys of a token account were stored for, if it has any
+    pub async fn owner(&self, token_account: &Pubkey) -> Result<Option<Pubkey>, VaultError> {
+        let owner: Option<String> = sqlx::query_scalar("SELECT owner FROM account_keys WHERE token_account = ?")
+            .bind(token_account.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        owner
+            .map(|owner| Pubkey::from_str(&owner).map_err(|_| VaultError::CorruptKey(*token_account)))
+            .transpose()
+    }
+
+    /// Delete the keys of a token account, pending rotation keys included;
+    /// returns whether any were stored
+    pub async fn remove(&self, token_account: &Pubkey) -> Result<bool, VaultError> {
+        let mut removed = false;
+        for table in ["account_keys", "pending_account_keys"] {
+            let result = sqlx::query(&format!("DELETE FROM {} WHERE token_account = ?", table))
+                .bind(token_account.to_string())
+                .execute(&self.pool)
+                .await?;
+            removed |= result.rows_affected() > 0;
+        }
+        Ok(removed)
+    }
+
+    /// Store the keys the auto-apply worker uses for a token account,
+    /// replacing any stored for it before
+    pub async fn save_auto_apply(
+        &self,
+        token_account: &Pubkey,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<(), VaultError> {
+        let aes_key_bytes: [u8; AE_KEY_LEN] = aes_key.clone().into();
+        let elgamal_secret_key = self.seal(token_account, "elgamal_secret_key", elgamal_keypair.secret().as_bytes());
+        let aes_key = self.seal(token_account, "aes_key", &aes_key_bytes);
+
+        sqlx::query(
+            "INSERT INTO auto_apply_keys (token_account, elgamal_secret_key, aes_key)
+             VALUES (?, ?, ?)
+             ON CONFLICT (token_account) DO UPDATE SET
+                elgamal_secret_key = excluded.elgamal_secret_key,
+                aes_key = excluded.aes_key",
+        )
+        .bind(token_account.to_string())
+        .bind(elgamal_secret_key)
+        .bind(aes_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete the auto-apply keys of a token account; returns whether it had any
+    pub async fn remove_auto_apply(&self, token_account: &Pubkey) -> Result<bool, VaultError> {
+        let result = sqlx::query("DELETE FROM auto_apply_keys WHERE token_account = ?")
+            .bind(token_account.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Every account registered for auto-apply, with its keys
+    pub async fn auto_apply_accounts(&self) -> Result<Vec<(Pubkey, ElGamalKeypair, AeKey)>, VaultError> {
+        let token_accounts: Vec<String> = sqlx::query_scalar("SELECT token_account FROM auto_apply_keys")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut accounts = Vec::with_capacity(token_accounts.len());
+        for token_account in token_accounts {
+            let Ok(token_account) = Pubkey::from_str(&token_account) else {
+                tracing::warn!("Skipping auto-apply row with invalid token account {}", token_account);
+                continue;
+            };
+            if let Some((elgamal_keypair, aes_key)) = self.load_from("auto_apply_keys", &token_account).await? {
+                accounts.push((token_account, elgamal_keypair, aes_key));
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Store keys in `table` unless the token account already has a row there
+    async fn insert_into(
+        &self,
+        table: &str,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+        elgamal_keypair: &ElGamalKeypair,
+        aes_key: &AeKey,
+    ) -> Result<bool, VaultError> {
+        let aes_key_bytes: [u8; AE_KEY_LEN] = aes_key.clone().into();
+        let elgamal_secret_key = self.seal(token_account, "elgamal_secret_key", elgamal_keypair.secret().as_bytes());
+        let aes_key = self.seal(token_account, "aes_key", &aes_key_bytes);
+
+        let result = sqlx::query(&format!(
+            "INSERT INTO {} (token_account, owner, elgamal_secret_key, aes_key)
+             VALUES (?, ?, ?, ?)
+             ON CONFLICT (token_account) DO NOTHING",
+            table
+        ))
+        .bind(token_account.to_string())
+        .bind(owner.to_string())
+        .bind(elgamal_secret_key)
+        .bind(aes_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn load_from(
+        &self,
+        table: &str,
+        token_account: &Pubkey,
+    ) -> Result<Option<(ElGamalKeypair, AeKey)>, VaultError> {
+        let row = sqlx::query(&format!(
+            "SELECT elgamal_secret_key, aes_key FROM {} WHERE token_account = ?",
+            table
+        ))
+        .bind(token_account.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let elgamal_secret_key = self.open(token_account, "elgamal_secret_key", row.try_get("elgamal_secret_key")?)?;
+        let aes_key = self.open(token_account, "aes_key", row.try_get("aes_key")?)?;
+
+        let elgamal_secret_key = ElGamalSecretKey::try_from(elgamal_secret_key.as_slice())
+            .map_err(|_| VaultError::CorruptKey(*token_account))?;
+        let aes_key = AeKey::try_from(aes_key.as_slice()).map_err(|_| VaultError::CorruptKey(*token_account))?;
+
+        Ok(Some((ElGamalKeypair::new(elgamal_secret_key), aes_key)))
+    }
+
+    /// Encrypt a key as `nonce || ciphertext`
+    fn seal(&self, token_account: &Pubkey, name: &str, key: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(token_account, name);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: key, aad: &aad })
+            .expect("AES-GCM encryption of a key cannot fail");
+
+        [nonce.as_slice(), &ciphertext].concat()
+    }
+
+    fn open(&self, token_account: &Pubkey, name: &str, sealed: Vec<u8>) -> Result<Vec<u8>, VaultError> {
+        if sealed.len() < NONCE_LEN {
+            return Err(VaultError::Decryption(*token_account));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = associated_data(token_account, name);
+
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| VaultError::Decryption(*token_account))
+    }
+}
+
+fn associated_data(token_account: &Pubkey, name: &str) -> Vec<u8> {
+    [token_account.as_ref(), b":", name.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn memory_vault(master_key: [u8; 32]) -> KeyVault {
+        // One connection: every in-memory connection is its own database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        KeyVault::with_pool(pool, &master_key).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_store_and_load() {
+        let vault = memory_vault([7u8; 32]).await;
+        let token_account = Pubkey::new_unique();
+        let elgamal = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+
+        assert!(vault.load(&token_account).await.unwrap().is_none());
+
+        let owner = Pubkey::new_unique();
+        assert!(vault.insert(&token_account, &owner, &elgamal, &aes_key).await.unwrap());
+        assert_eq!(vault.owner(&token_account).await.unwrap(), Some(owner));
+        let (loaded_elgamal, loaded_aes_key) = vault.load(&token_account).await.unwrap().unwrap();
+        assert_eq!(loaded_elgamal.pubkey(), elgamal.pubkey());
+        assert_eq!(loaded_aes_key, aes_key);
+
+        assert!(vault.remove(&token_account).await.unwrap());
+        assert!(vault.load(&token_account).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_insert_keeps_stored_keys() {
+        let vault = memory_vault([7u8; 32]).await;
+        let token_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let elgamal = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+
+        assert!(vault.insert(&token_account, &owner, &elgamal, &aes_key).await.unwrap());
+        assert!(
+            !vault
+                .insert(&token_account, &owner, &ElGamalKeypair::new_rand(), &AeKey::new_rand())
+                .await
+                .unwrap()
+        );
+
+        let (loaded_elgamal, loaded_aes_key) = vault.load(&token_account).await.unwrap().unwrap();
+        assert_eq!(loaded_elgamal.pubkey(), elgamal.pubkey());
+        assert_eq!(loaded_aes_key, aes_key);
+    }
+
+    #[tokio::test]
+    async fn test_pending_keys_are_promoted_once() {
+        let vault = memory_vault([7u8; 32]).await;
+        let token_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let elgamal = ElGamalKeypair::new_rand();
+        assert!(vault.insert(&token_account, &owner, &elgamal, &AeKey::new_rand()).await.unwrap());
+
+        // Generated once, and the current keys stay until promotion
+        let (pending_elgamal, pending_aes_key) = vault.pending(&token_account, &owner).await.unwrap();
+        let (again, _) = vault.pending(&token_account, &owner).await.unwrap();
+        assert_eq!(again.pubkey(), pending_elgamal.pubkey());
+        let (current, _) = vault.load(&token_account).await.unwrap().unwrap();
+        assert_eq!(current.pubkey(), elgamal.pubkey());
+
+        assert!(vault.promote_pending(&token_account).await.unwrap());
+        let (current, current_aes_key) = vault.load(&token_account).await.unwrap().unwrap();
+        assert_eq!(current.pubkey(), pending_elgamal.pubkey());
+        assert_eq!(current_aes_key, pending_aes_key);
+        assert!(vault.load_pending(&token_account).await.unwrap().is_none());
+        assert!(!vault.promote_pending(&token_account).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_auto_apply_keys_are_replaced_and_removed() {
+        let vault = memory_vault([7u8; 32]).await;
+        let token_account = Pubkey::new_unique();
+        assert!(vault.auto_apply_accounts().await.unwrap().is_empty());
+
+        vault
+            .save_auto_apply(&token_account, &ElGamalKeypair::new_rand(), &AeKey::new_rand())
+            .await
+            .unwrap();
+        let elgamal = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+        vault.save_auto_apply(&token_account, &elgamal, &aes_key).await.unwrap();
+
+        let accounts = vault.auto_apply_accounts().await.unwrap();
+        assert_eq!(accounts.len(), 1);
+        let (loaded_account, loaded_elgamal, loaded_aes_key) = &accounts[0];
+        assert_eq!(*loaded_account, token_account);
+        assert_eq!(loaded_elgamal.pubkey(), elgamal.pubkey());
+        assert_eq!(*loaded_aes_key, aes_key);
+
+        // Independent of the account's own stored keys
+        assert!(vault.load(&token_account).await.unwrap().is_none());
+
+        assert!(vault.remove_auto_apply(&token_account).await.unwrap());
+        assert!(!vault.remove_auto_apply(&token_account).await.unwrap());
+        assert!(vault.auto_apply_accounts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sealed_keys_are_bound_to_their_row() {
+        let vault = memory_vault([7u8; 32]).await;
+        let token_account = Pubkey::new_unique();
+
+        let sealed = vault.seal(&token_account, "aes_key", &[1u8; AE_KEY_LEN]);
+        assert_eq!(vault.open(&token_account, "aes_key", sealed.clone()).unwrap(), [1u8; AE_KEY_LEN]);
+
+        // Another account, another column or another master key
+        let other = Pubkey::new_unique();
+        assert!(matches!(
+            vault.open(&other, "aes_key", sealed.clone()),
+            Err(VaultError::Decryption(_))
+        ));
+        assert!(vault.open(&token_account, "elgamal_secret_key", sealed.clone()).is_err());
+
+        let other_vault = memory_vault([8u8; 32]).await;
+        assert!(other_vault.open(&token_account, "aes_key", sealed).is_err());
+    }
+}